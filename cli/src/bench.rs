@@ -0,0 +1,415 @@
+//! a load generator for benchmarking the embedded database or a key-value server
+
+use anyhow::{anyhow, Result};
+use bonerjams_config::database::{DbMode, DbOpts};
+use bonerjams_db::{api::client::KVClient, prelude::*, Database, DbBatch, DbTree};
+use hdrhistogram::Histogram;
+use rand::distributions::Distribution;
+use rand::rngs::StdRng;
+use rand::{Rng, RngCore, SeedableRng};
+use rand_distr::Zipf;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// the tree, or namespace, benchmark keys are written to
+pub const BENCH_NAMESPACE: &str = "bonerjams-bench";
+
+/// the upper bound of recorded latencies in microseconds
+const MAX_LATENCY_US: u64 = 60_000_000;
+
+/// how keys are selected for each operation
+#[derive(Clone, Copy, Debug)]
+pub enum KeyDistribution {
+    /// every key is equally likely to be selected
+    Uniform,
+    /// keys are selected following a zipfian distribution with the given exponent,
+    /// such that a small number of keys receive most operations
+    Zipfian(f64),
+}
+
+/// what the benchmark is run against
+#[derive(Clone, Debug)]
+pub enum BenchTarget {
+    /// a temporary embedded database created with the given options
+    Embedded(DbOpts),
+    /// a key-value server, whose tls certificate is not verified if `insecure` is set
+    Remote {
+        url: String,
+        namespace: String,
+        insecure: bool,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchOpts {
+    /// the total number of operations to issue
+    pub operations: u64,
+    /// the number of workers issuing operations concurrently
+    pub concurrency: usize,
+    /// the number of distinct keys operations are spread across
+    pub key_space: u64,
+    pub distribution: KeyDistribution,
+    /// the size of written values in bytes
+    pub value_size: usize,
+    /// the fraction of operations which are reads, between 0 and 1
+    pub read_ratio: f64,
+    /// the number of keys read or written by each operation
+    pub batch_size: usize,
+    /// if true, every key is written before the benchmark starts
+    pub prefill: bool,
+    pub target: BenchTarget,
+}
+
+/// latency percentiles of a single operation type, in microseconds
+#[derive(Serialize, Debug, Default)]
+pub struct LatencyReport {
+    pub count: u64,
+    pub mean_us: f64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub p999_us: u64,
+    pub max_us: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BenchReport {
+    pub target: String,
+    /// the number of operations that completed successfully
+    pub operations: u64,
+    /// the number of operations that failed
+    pub errors: u64,
+    pub elapsed_secs: f64,
+    pub ops_per_sec: f64,
+    /// throughput in keys, which differs from ops when batching
+    pub keys_per_sec: f64,
+    pub reads: LatencyReport,
+    pub writes: LatencyReport,
+    /// the size of the embedded database once the benchmark finished
+    pub size_on_disk: Option<u64>,
+}
+
+impl BenchOpts {
+    pub fn from_matches(matches: &clap::ArgMatches) -> Result<Self> {
+        let parse = |name: &str, default: &str| -> String {
+            matches.value_of(name).unwrap_or(default).to_string()
+        };
+        let distribution = match parse("distribution", "uniform").as_str() {
+            "uniform" => KeyDistribution::Uniform,
+            "zipfian" => KeyDistribution::Zipfian(parse("zipf-exponent", "0.99").parse()?),
+            distribution => return Err(anyhow!("unsupported distribution {}", distribution)),
+        };
+        let target = if let Some(url) = matches.value_of("url") {
+            BenchTarget::Remote {
+                url: url.to_string(),
+                namespace: parse("namespace", BENCH_NAMESPACE),
+                insecure: matches.is_present("insecure"),
+            }
+        } else {
+            BenchTarget::Embedded(DbOpts {
+                path: matches
+                    .value_of("path")
+                    .map(|path| path.to_string())
+                    .unwrap_or_else(|| {
+                        std::env::temp_dir()
+                            .join(format!("bonerjams-bench-{}.db", std::process::id()))
+                            .to_string_lossy()
+                            .to_string()
+                    }),
+                mode: Some(match parse("mode", "fast").as_str() {
+                    "fast" => DbMode::Fast,
+                    "low-space" => DbMode::LowSpace,
+                    mode => return Err(anyhow!("unsupported mode {}", mode)),
+                }),
+                compression_factor: matches
+                    .value_of("compression-factor")
+                    .map(|factor| factor.parse())
+                    .transpose()?,
+                ..Default::default()
+            })
+        };
+        let opts = Self {
+            operations: parse("operations", "100000").parse()?,
+            concurrency: parse("concurrency", "8").parse()?,
+            key_space: parse("key-space", "10000").parse()?,
+            distribution,
+            value_size: parse("value-size", "256").parse()?,
+            read_ratio: parse("read-ratio", "0.5").parse()?,
+            batch_size: parse("batch-size", "1").parse()?,
+            prefill: matches.is_present("prefill"),
+            target,
+        };
+        if opts.concurrency == 0 || opts.batch_size == 0 || opts.key_space == 0 {
+            return Err(anyhow!(
+                "concurrency, batch size and key space must be greater than 0"
+            ));
+        }
+        if !(0.0..=1.0).contains(&opts.read_ratio) {
+            return Err(anyhow!("read ratio must be between 0 and 1"));
+        }
+        Ok(opts)
+    }
+}
+
+impl BenchReport {
+    pub fn print_text(&self) {
+        println!("target:       {}", self.target);
+        println!("operations:   {} ({} errors)", self.operations, self.errors);
+        println!("elapsed:      {:.2}s", self.elapsed_secs);
+        println!(
+            "throughput:   {:.2} ops/s, {:.2} keys/s",
+            self.ops_per_sec, self.keys_per_sec
+        );
+        if let Some(size) = self.size_on_disk {
+            println!("size on disk: {} bytes", size);
+        }
+        println!(
+            "{:<8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}",
+            "latency", "count", "mean", "p50", "p90", "p99", "p99.9", "max"
+        );
+        for (name, latency) in [("reads", &self.reads), ("writes", &self.writes)] {
+            println!(
+                "{:<8} {:>10} {:>8.0}us {:>8}us {:>8}us {:>8}us {:>8}us {:>8}us",
+                name,
+                latency.count,
+                latency.mean_us,
+                latency.p50_us,
+                latency.p90_us,
+                latency.p99_us,
+                latency.p999_us,
+                latency.max_us
+            );
+        }
+    }
+}
+
+impl From<&Histogram<u64>> for LatencyReport {
+    fn from(histogram: &Histogram<u64>) -> Self {
+        if histogram.is_empty() {
+            return Default::default();
+        }
+        Self {
+            count: histogram.len(),
+            mean_us: histogram.mean(),
+            p50_us: histogram.value_at_quantile(0.5),
+            p90_us: histogram.value_at_quantile(0.9),
+            p99_us: histogram.value_at_quantile(0.99),
+            p999_us: histogram.value_at_quantile(0.999),
+            max_us: histogram.max(),
+        }
+    }
+}
+
+/// the store operations are issued against
+enum Backend {
+    Embedded(Arc<DbTree>),
+    Remote { client: KVClient, namespace: String },
+}
+
+impl Backend {
+    async fn write(&self, keys: Vec<String>, value: &[u8]) -> Result<()> {
+        match self {
+            Self::Embedded(tree) => tokio::task::block_in_place(|| {
+                let mut batch = DbBatch::new();
+                for key in keys.iter() {
+                    batch.insert_raw(key.as_bytes(), value)?;
+                }
+                Ok(tree.apply_batch(&mut batch)?)
+            }),
+            Self::Remote { client, namespace } => {
+                let mut key_values = keys
+                    .into_iter()
+                    .map(|key| KeyValue {
+                        key,
+                        value: value.to_vec(),
+                    })
+                    .collect::<Vec<_>>();
+                client
+                    .put_key_values(&[namespace.clone()], std::slice::from_mut(&mut key_values))
+                    .await
+            }
+        }
+    }
+    async fn read(&self, keys: Vec<String>) -> Result<()> {
+        match self {
+            Self::Embedded(tree) => tokio::task::block_in_place(|| {
+                for key in keys.iter() {
+                    tree.get(key.as_bytes())?;
+                }
+                Ok(())
+            }),
+            Self::Remote { client, namespace } => {
+                client
+                    .get_key_values(&[namespace.clone()], &mut [keys])
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// the results of a single worker
+struct WorkerResult {
+    reads: Histogram<u64>,
+    writes: Histogram<u64>,
+    errors: u64,
+}
+
+/// runs the benchmark, returning the report once all operations have completed
+pub async fn run(opts: BenchOpts) -> Result<BenchReport> {
+    let (backend, db, target) = match &opts.target {
+        BenchTarget::Embedded(db_opts) => {
+            let db = Database::new(db_opts)?;
+            let tree = db.open_tree(DbTrees::Custom(BENCH_NAMESPACE))?;
+            let target = format!(
+                "embedded {} (mode {:?}, compression {:?})",
+                db_opts.path,
+                db_opts.mode.unwrap_or_default(),
+                db_opts.compression_factor
+            );
+            (Backend::Embedded(tree), Some(db), target)
+        }
+        BenchTarget::Remote {
+            url,
+            namespace,
+            insecure,
+        } => (
+            Backend::Remote {
+                client: KVClient::builder()
+                    .url(url)
+                    .accept_invalid_certs(*insecure)
+                    .build()?,
+                namespace: namespace.clone(),
+            },
+            None,
+            format!("remote {} (namespace {})", url, namespace),
+        ),
+    };
+    let backend = Arc::new(backend);
+    let value = {
+        let mut value = vec![0_u8; opts.value_size];
+        rand::thread_rng().fill_bytes(&mut value);
+        value
+    };
+    if opts.prefill {
+        log::info!("prefilling {} keys", opts.key_space);
+        let keys = (0..opts.key_space).map(key_name).collect::<Vec<_>>();
+        for chunk in keys.chunks(1000) {
+            backend.write(chunk.to_vec(), &value).await?;
+        }
+    }
+    let value = Arc::new(value);
+    let start = Instant::now();
+    let mut workers = Vec::with_capacity(opts.concurrency);
+    for idx in 0..opts.concurrency {
+        let backend = backend.clone();
+        let value = value.clone();
+        let opts = opts.clone();
+        // spread any remainder across the first workers
+        let operations = opts.operations / opts.concurrency as u64
+            + u64::from((idx as u64) < opts.operations % opts.concurrency as u64);
+        workers.push(tokio::spawn(async move {
+            run_worker(&backend, &opts, &value, operations).await
+        }));
+    }
+    let mut reads = Histogram::<u64>::new_with_bounds(1, MAX_LATENCY_US, 3)?;
+    let mut writes = Histogram::<u64>::new_with_bounds(1, MAX_LATENCY_US, 3)?;
+    let mut errors = 0;
+    for worker in workers {
+        let result = worker.await??;
+        reads.add(&result.reads)?;
+        writes.add(&result.writes)?;
+        errors += result.errors;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let size_on_disk = match db.as_ref() {
+        Some(db) => {
+            db.flush()?;
+            Some(db.size_on_disk()?)
+        }
+        None => None,
+    };
+    let operations = reads.len() + writes.len();
+    Ok(BenchReport {
+        target,
+        operations,
+        errors,
+        elapsed_secs: elapsed,
+        ops_per_sec: operations as f64 / elapsed,
+        keys_per_sec: (operations * opts.batch_size as u64) as f64 / elapsed,
+        reads: (&reads).into(),
+        writes: (&writes).into(),
+        size_on_disk,
+    })
+}
+
+/// removes the embedded database created by the benchmark
+pub fn cleanup(opts: &BenchOpts) -> Result<()> {
+    if let BenchTarget::Embedded(db_opts) = &opts.target {
+        match std::fs::remove_dir_all(&db_opts.path) {
+            // the database is never created if the benchmark failed early
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+async fn run_worker(
+    backend: &Backend,
+    opts: &BenchOpts,
+    value: &[u8],
+    operations: u64,
+) -> Result<WorkerResult> {
+    let mut rng = StdRng::from_entropy();
+    let zipf = match opts.distribution {
+        KeyDistribution::Zipfian(exponent) => Some(
+            Zipf::new(opts.key_space, exponent)
+                .map_err(|err| anyhow!("invalid zipf exponent {:?}", err))?,
+        ),
+        KeyDistribution::Uniform => None,
+    };
+    let mut result = WorkerResult {
+        reads: Histogram::new_with_bounds(1, MAX_LATENCY_US, 3)?,
+        writes: Histogram::new_with_bounds(1, MAX_LATENCY_US, 3)?,
+        errors: 0,
+    };
+    for _ in 0..operations {
+        let keys = (0..opts.batch_size)
+            .map(|_| {
+                let idx = match zipf.as_ref() {
+                    // zipf samples are within [1, key_space]
+                    Some(zipf) => zipf.sample(&mut rng) as u64 - 1,
+                    None => rng.gen_range(0..opts.key_space),
+                };
+                key_name(idx)
+            })
+            .collect::<Vec<_>>();
+        let is_read = rng.gen_bool(opts.read_ratio);
+        let start = Instant::now();
+        let res = if is_read {
+            backend.read(keys).await
+        } else {
+            backend.write(keys, value).await
+        };
+        let latency = duration_us(start.elapsed());
+        match res {
+            Ok(()) if is_read => result.reads.saturating_record(latency),
+            Ok(()) => result.writes.saturating_record(latency),
+            Err(err) => {
+                log::debug!("benchmark operation failed {:#?}", err);
+                result.errors += 1;
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn key_name(idx: u64) -> String {
+    format!("key-{:016}", idx)
+}
+
+fn duration_us(duration: Duration) -> u64 {
+    (duration.as_micros() as u64).max(1)
+}
@@ -0,0 +1,113 @@
+//! commands for inspecting and managing a key-value cluster
+
+use anyhow::{anyhow, Result};
+use bonerjams_db::api::client::KVClient;
+use bonerjams_db::prelude::{DrainPhase, DrainStatus, SyncState};
+use std::time::Duration;
+
+/// how often the drain status is polled
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// prints a table of the cluster members, followed by a table of keyspaces
+pub async fn status(client: &KVClient) -> Result<()> {
+    let members = client.cluster_members().await?.members;
+    let keyspaces = client.cluster_keyspaces().await?;
+    let now = bonerjams_db::api::membership::now_millis();
+    println!(
+        "{:<24} {:<24} {:<24} {:<12} {:<6} {:>10}",
+        "node", "rpc address", "api address", "data center", "live", "last seen"
+    );
+    for member in members.iter() {
+        let last_seen = Duration::from_millis(now.saturating_sub(member.info.last_seen));
+        println!(
+            "{:<24} {:<24} {:<24} {:<12} {:<6} {:>9}s",
+            member.info.node_id,
+            member.info.rpc_address,
+            member.info.api_address.as_deref().unwrap_or("-"),
+            member.info.data_center.as_deref().unwrap_or("-"),
+            if member.live { "yes" } else { "no" },
+            last_seen.as_secs(),
+        );
+    }
+    println!();
+    println!(
+        "{:<32} {:>12} {:>12}",
+        "keyspace", "documents", "tombstones"
+    );
+    for keyspace in keyspaces.keyspaces.iter() {
+        println!(
+            "{:<32} {:>12} {:>12}",
+            keyspace.name, keyspace.documents, keyspace.tombstones
+        );
+    }
+    println!();
+    print_sync_state(&keyspaces.sync);
+    Ok(())
+}
+
+/// triggers repair of `namespace` if set, then prints the state of synchronisation
+pub async fn repair(client: &KVClient, namespace: Option<&str>) -> Result<()> {
+    let sync = match namespace {
+        Some(namespace) => {
+            let response = client.repair(namespace).await?;
+            println!("triggered repair of {}", response.namespace);
+            response.sync
+        }
+        None => client.repair_status().await?,
+    };
+    print_sync_state(&sync);
+    Ok(())
+}
+
+/// starts draining a node, and unless `wait` is false, reports progress until it completes
+pub async fn drain(client: &KVClient, wait: bool) -> Result<()> {
+    let mut status = client.drain().await?;
+    print_drain_status(&status);
+    while wait && status.phase == DrainPhase::Draining {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+        status = client.drain_status().await?;
+        print_drain_status(&status);
+    }
+    match status.phase {
+        DrainPhase::Failed => Err(anyhow!(
+            "drain failed: {}",
+            status.error.unwrap_or_default()
+        )),
+        DrainPhase::Drained => {
+            println!("node drained, it will now leave the cluster");
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn print_drain_status(status: &DrainStatus) {
+    println!("{:?}", status.phase);
+    if status.phase != DrainPhase::Draining {
+        return;
+    }
+    for keyspace in status.keyspaces.iter() {
+        let peers = keyspace.synced.len() + keyspace.pending.len();
+        if keyspace.pending.is_empty() {
+            println!("  {:<32} {}/{} peers synced", keyspace.name, peers, peers);
+        } else {
+            println!(
+                "  {:<32} {}/{} peers synced, waiting for {}",
+                keyspace.name,
+                keyspace.synced.len(),
+                peers,
+                keyspace.pending.join(", ")
+            );
+        }
+    }
+}
+
+fn print_sync_state(sync: &SyncState) {
+    println!(
+        "sync: {} ({} ongoing, {} slow, {} failed tasks)",
+        if sync.synced { "synced" } else { "syncing" },
+        sync.ongoing_tasks,
+        sync.slow_tasks,
+        sync.failed_tasks,
+    );
+}
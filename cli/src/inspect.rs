@@ -0,0 +1,172 @@
+//! offline inspection of an embedded database.
+//!
+//! sled does not support opening a database in read-only mode, so the inspect
+//! commands never write to the database and refuse to open a path that doesn't
+//! exist, rather than creating a new database. the database must not be in use
+//! by a running server while being inspected.
+
+use crate::transfer::Encoding;
+use anyhow::{anyhow, Result};
+use bonerjams_config::database::DbOpts;
+use bonerjams_db::{prelude::*, Database, DbTree};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// the codec values are expected to be encoded with
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Codec {
+    Json,
+    Utf8,
+}
+
+impl FromStr for Codec {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "utf8" | "utf-8" => Ok(Self::Utf8),
+            _ => Err(anyhow!("unsupported codec {}", s)),
+        }
+    }
+}
+
+impl Codec {
+    /// returns an error if the value can't be decoded with this codec
+    pub fn check(&self, value: &[u8]) -> Result<()> {
+        match self {
+            Self::Json => {
+                serde_json::from_slice::<serde_json::Value>(value)?;
+            }
+            Self::Utf8 => {
+                std::str::from_utf8(value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// opens an existing database for inspection
+pub fn open(opts: &DbOpts) -> Result<Arc<Database>> {
+    if !std::path::Path::new(&opts.path).exists() {
+        return Err(anyhow!("database {} does not exist", opts.path));
+    }
+    Database::new(opts)
+}
+
+/// opens an existing tree, as opening a tree which doesn't exist would create it
+pub fn open_tree(db: &Arc<Database>, name: &str) -> Result<Arc<DbTree>> {
+    if !db.tree_names().iter().any(|tree| tree.as_ref() == name.as_bytes()) {
+        return Err(anyhow!("tree {} does not exist", name));
+    }
+    db.open_tree(DbTrees::Custom(name))
+}
+
+/// prints every tree within the database along with the number of keys it holds.
+/// trees created with `DbTrees::Binary` have their names decoded from base64
+pub fn list_trees(db: &Arc<Database>) -> Result<()> {
+    println!("{:<48} {:>12}  decoded", "tree", "keys");
+    for name in db.tree_names() {
+        let name = String::from_utf8_lossy(&name).to_string();
+        let tree = db.open_tree(DbTrees::Custom(&name))?;
+        println!(
+            "{:<48} {:>12}  {}",
+            name,
+            tree.len(),
+            decode_tree_name(&name).unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+/// prints the size of the database, and the number of keys per tree
+pub fn stats(db: &Arc<Database>) -> Result<()> {
+    let names = db.tree_names();
+    let mut total_keys = 0;
+    for name in names.iter() {
+        let name = String::from_utf8_lossy(name).to_string();
+        total_keys += db.open_tree(DbTrees::Custom(&name))?.len();
+    }
+    println!("size on disk: {} bytes", db.size_on_disk()?);
+    println!("trees: {}", names.len());
+    println!("keys: {}", total_keys);
+    Ok(())
+}
+
+/// prints the keys in a tree which start with `prefix`, optionally
+/// including their values, stopping after `limit` keys if set
+pub fn dump_keys(
+    tree: &Arc<DbTree>,
+    prefix: &[u8],
+    limit: Option<usize>,
+    with_values: bool,
+) -> Result<()> {
+    for entry in tree.scan_prefix(prefix).take(limit.unwrap_or(usize::MAX)) {
+        let (key, value) = entry?;
+        if with_values {
+            println!("{}\t{}", display_bytes(&key), display_bytes(&value));
+        } else {
+            println!("{}", display_bytes(&key));
+        }
+    }
+    Ok(())
+}
+
+/// prints the value of a single key, pretty printing it if it is json
+pub fn show_value(tree: &Arc<DbTree>, key: &[u8]) -> Result<()> {
+    let value = tree
+        .get(key)?
+        .ok_or_else(|| anyhow!("key {} not found", display_bytes(key)))?;
+    match serde_json::from_slice::<serde_json::Value>(&value) {
+        Ok(value) => println!("{}", serde_json::to_string_pretty(&value)?),
+        Err(_) => println!("{}", display_bytes(&value)),
+    }
+    Ok(())
+}
+
+/// checks that every value in the tree decodes with the given codec, printing
+/// the keys which fail. an error is returned if any value failed to decode
+pub fn verify(tree: &Arc<DbTree>, codec: Codec) -> Result<()> {
+    let mut checked = 0;
+    let mut failed = 0;
+    for entry in tree.iter() {
+        let (key, value) = entry?;
+        checked += 1;
+        if let Err(err) = codec.check(&value) {
+            failed += 1;
+            println!("{}: {}", display_bytes(&key), err);
+        }
+    }
+    println!("checked {} values, {} failed to decode", checked, failed);
+    if failed > 0 {
+        return Err(anyhow!("{} values failed to decode as {:?}", failed, codec));
+    }
+    Ok(())
+}
+
+/// decodes a key or prefix argument using the `--key-encoding` argument
+pub fn key_arg(matches: &clap::ArgMatches, name: &str) -> Result<Vec<u8>> {
+    let encoding: Encoding = matches.value_of("key-encoding").unwrap_or("utf8").parse()?;
+    encoding.decode(matches.value_of(name).unwrap_or_default())
+}
+
+/// returns the raw name of a tree created from `DbTrees::Binary` if the
+/// name is valid base64 decoding to printable utf8
+fn decode_tree_name(name: &str) -> Option<String> {
+    if name == DEFAULT_TREE_ID {
+        return None;
+    }
+    let decoded = base64::decode(name).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    if decoded.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    Some(decoded)
+}
+
+/// displays bytes as utf8 when they are printable, otherwise as base64
+fn display_bytes(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(data) if !data.chars().any(|c| c.is_control()) => data.to_string(),
+        _ => format!("base64:{}", base64::encode(data)),
+    }
+}
@@ -1,8 +1,14 @@
 use anyhow::{anyhow, Result};
 use bonerjams_config::Configuration;
+use bonerjams_db::api::client::KVClient;
 use clap::{App, Arg, SubCommand};
 use tokio::select;
 use tokio_stream::wrappers::ReceiverStream;
+mod bench;
+mod cluster;
+mod inspect;
+mod shell;
+mod transfer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -14,6 +20,27 @@ async fn main() -> Result<()> {
         .long("value")
         .takes_value(true)
         .required(true);
+    let tree_flag = Arg::with_name("tree")
+        .long("tree")
+        .help("the tree to use, defaults to the default tree")
+        .takes_value(true)
+        .required(false);
+    let url_flag = Arg::with_name("url")
+        .long("url")
+        .help("url of a key-value server within the cluster")
+        .takes_value(true)
+        .required(true);
+    let admin_url_flag = Arg::with_name("admin-url")
+        .long("admin-url")
+        .help("url of the node's admin api, served on its api.admin.listen_address")
+        .takes_value(true)
+        .required(true);
+    let key_encoding_flag = Arg::with_name("key-encoding")
+        .long("key-encoding")
+        .help("how keys and prefixes given on the command line are encoded")
+        .takes_value(true)
+        .possible_values(&["utf8", "base64"])
+        .default_value("utf8");
 
     let matches =
         App::new("mevdaddy")
@@ -35,6 +62,26 @@ async fn main() -> Result<()> {
                     .takes_value(false)
                     .required(false),
             )
+            .arg(
+                Arg::with_name("insecure")
+                    .long("insecure")
+                    .help("accepts invalid tls certificates from the server")
+                    .takes_value(false)
+                    .required(false)
+                    .global(true),
+            )
+            .arg(
+                Arg::with_name("set")
+                    .long("set")
+                    .value_name("PATH=VALUE")
+                    .help(
+                        "overrides a config field such as api.listen_address=0.0.0.0:3001, \
+                         taking precedence over the config file and BONERJAMS_* env vars",
+                    )
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
             .subcommand(
                 SubCommand::with_name("config")
                     .about("config management commands")
@@ -47,6 +94,14 @@ async fn main() -> Result<()> {
                             .help("sets the config file")
                             .takes_value(true),
                     ),
+                    SubCommand::with_name("show")
+                        .about("prints the effective config, with secrets redacted")
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("prints the config as json rather than yaml")
+                                .takes_value(false),
+                        ),
                     SubCommand::with_name("new-certificate").arg(
                         Arg::with_name("hosts")
                             .long("hosts")
@@ -104,6 +159,150 @@ async fn main() -> Result<()> {
                         SubCommand::with_name("get").arg(key_flag.clone()),
                     ]),
             )
+            .subcommand(
+                SubCommand::with_name("export")
+                    .about("export a tree, or a namespace when --url is set, to a file")
+                    .arg(
+                        Arg::with_name("output")
+                            .long("output")
+                            .help("the file to write records to")
+                            .takes_value(true)
+                            .required(true),
+                    )
+                    .arg(
+                        Arg::with_name("keys")
+                            .long("keys")
+                            .help(
+                                "file of namespace keys to export, one per line. \
+                                 defaults to all keys",
+                            )
+                            .takes_value(true)
+                            .required(false),
+                    )
+                    .args(&transfer_args()),
+            )
+            .subcommand(
+                SubCommand::with_name("import")
+                    .about("import a file into a tree, or a namespace when --url is set")
+                    .arg(
+                        Arg::with_name("input")
+                            .long("input")
+                            .help("the file to read records from")
+                            .takes_value(true)
+                            .required(true),
+                    )
+                    .args(&transfer_args()),
+            )
+            .subcommand(
+                SubCommand::with_name("health")
+                    .about("check the readiness of a key-value server")
+                    .arg(
+                        Arg::with_name("url")
+                            .long("url")
+                            .help("url of the key-value server")
+                            .takes_value(true)
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("cluster")
+                    .about("key-value cluster management")
+                    .subcommands(vec![
+                        SubCommand::with_name("status")
+                            .about("show the members and keyspaces of the cluster")
+                            .arg(url_flag.clone()),
+                        SubCommand::with_name("repair")
+                            .about("trigger anti-entropy repair of a namespace on a node")
+                            .arg(admin_url_flag.clone())
+                            .arg(
+                                Arg::with_name("namespace")
+                                    .long("namespace")
+                                    .help("the namespace to repair, if absent show the sync state")
+                                    .takes_value(true),
+                            ),
+                        SubCommand::with_name("drain")
+                            .about(
+                                "stop writes to a node until its peers have caught up \
+                                 so it can leave the cluster",
+                            )
+                            .arg(admin_url_flag.clone())
+                            .arg(
+                                Arg::with_name("no-wait")
+                                    .long("no-wait")
+                                    .help("if present, return once the drain has started")
+                                    .takes_value(false),
+                            ),
+                    ]),
+            )
+            .subcommand(
+                SubCommand::with_name("shell")
+                    .about("interactive shell for a key-value server")
+                    .arg(
+                        Arg::with_name("url")
+                            .long("url")
+                            .help("url of the key-value server")
+                            .takes_value(true)
+                            .required(true),
+                    ),
+            )
+            .subcommand(
+                SubCommand::with_name("bench")
+                    .about(
+                        "benchmark an embedded database, or a key-value server when --url is set",
+                    )
+                    .args(&bench_args()),
+            )
+            .subcommand(
+                SubCommand::with_name("inspect")
+                    .about("offline inspection of the database in the config file")
+                    .subcommands(vec![
+                        SubCommand::with_name("trees")
+                            .about("list all trees along with their key counts"),
+                        SubCommand::with_name("stats")
+                            .about("show the database size and key counts"),
+                        SubCommand::with_name("keys")
+                            .about("dump the keys within a tree")
+                            .arg(tree_flag.clone())
+                            .arg(key_encoding_flag.clone())
+                            .arg(
+                                Arg::with_name("prefix")
+                                    .long("prefix")
+                                    .help("only show keys starting with this prefix")
+                                    .takes_value(true)
+                                    .required(false),
+                            )
+                            .arg(
+                                Arg::with_name("limit")
+                                    .long("limit")
+                                    .help("the maximum number of keys to show")
+                                    .takes_value(true)
+                                    .required(false),
+                            )
+                            .arg(
+                                Arg::with_name("values")
+                                    .long("values")
+                                    .help("if present, show values alongside keys")
+                                    .takes_value(false)
+                                    .required(false),
+                            ),
+                        SubCommand::with_name("get")
+                            .about("show the value of a key, pretty printing json")
+                            .arg(tree_flag.clone())
+                            .arg(key_encoding_flag.clone())
+                            .arg(key_flag.clone()),
+                        SubCommand::with_name("verify")
+                            .about("verify every value in a tree decodes with the given codec")
+                            .arg(tree_flag.clone())
+                            .arg(
+                                Arg::with_name("codec")
+                                    .long("codec")
+                                    .help("the codec values are expected to use")
+                                    .takes_value(true)
+                                    .possible_values(&["json", "utf8"])
+                                    .default_value("json"),
+                            ),
+                    ]),
+            )
             .get_matches();
     let config_file_path = get_config_or_default(&matches);
     process_matches(&matches, &config_file_path).await?;
@@ -113,18 +312,129 @@ async fn process_matches<'a>(matches: &clap::ArgMatches<'a>, config_file_path: &
     match matches.subcommand() {
         ("config", Some(conf_cmd)) => match conf_cmd.subcommand() {
             ("new", Some(_)) => Ok(Configuration::default().save(config_file_path, false)?),
+            ("show", Some(show_cmd)) => {
+                let conf = get_config(matches, config_file_path)?;
+                print!("{}", conf.to_redacted_string(show_cmd.is_present("json"))?);
+                Ok(())
+            }
             _ => invalid_subcommand("config"),
         },
         ("server", Some(server_cmd)) => {
-            let conf = get_config(config_file_path)?;
-            bonerjams_config::init_log(matches.is_present("debug"))?;
-            Ok(())
+            let mut conf = get_config(matches, config_file_path)?;
+            if matches.is_present("debug") {
+                conf.logging.level = "debug".to_string();
+            }
+            bonerjams_db::api::server::init_logging(&conf)?;
+            bonerjams_db::api::server::run(&conf, async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await
         }
         ("client", Some(client_cmd)) => match client_cmd.subcommand() {
             ("put", Some(put_cmd)) => Ok(()),
             ("get", Some(get_cmd)) => Ok(()),
             _ => invalid_subcommand("client"),
         },
+        ("export", Some(export_cmd)) => {
+            init_logging(matches, config_file_path)?;
+            let opts = transfer::TransferOpts::from_matches(export_cmd, "output")?;
+            let conf = transfer_config(matches, &opts, config_file_path)?;
+            transfer::export(&conf, &opts).await
+        }
+        ("import", Some(import_cmd)) => {
+            init_logging(matches, config_file_path)?;
+            let opts = transfer::TransferOpts::from_matches(import_cmd, "input")?;
+            let conf = transfer_config(matches, &opts, config_file_path)?;
+            transfer::import(&conf, &opts).await
+        }
+        ("health", Some(health_cmd)) => {
+            let client = new_client(matches, health_cmd.value_of("url").unwrap_or_default())?;
+            let status = client.health().await?;
+            for check in status.checks.iter() {
+                match check.detail.as_ref() {
+                    Some(detail) => println!("{:<10} failed: {}", check.name, detail),
+                    None => println!("{:<10} ok", check.name),
+                }
+            }
+            if status.healthy {
+                Ok(())
+            } else {
+                Err(anyhow!("node is not ready"))
+            }
+        }
+        ("cluster", Some(cluster_cmd)) => match cluster_cmd.subcommand() {
+            ("status", Some(status_cmd)) => {
+                let client = new_client(matches, status_cmd.value_of("url").unwrap_or_default())?;
+                cluster::status(&client).await
+            }
+            ("repair", Some(repair_cmd)) => {
+                let client =
+                    new_client(matches, repair_cmd.value_of("admin-url").unwrap_or_default())?;
+                cluster::repair(&client, repair_cmd.value_of("namespace")).await
+            }
+            ("drain", Some(drain_cmd)) => {
+                let client =
+                    new_client(matches, drain_cmd.value_of("admin-url").unwrap_or_default())?;
+                cluster::drain(&client, !drain_cmd.is_present("no-wait")).await
+            }
+            _ => invalid_subcommand("cluster"),
+        },
+        ("shell", Some(shell_cmd)) => {
+            init_logging(matches, config_file_path)?;
+            shell::Shell::new(new_client(
+                matches,
+                shell_cmd.value_of("url").unwrap_or_default(),
+            )?)?
+                .run()
+                .await
+        }
+        ("bench", Some(bench_cmd)) => {
+            init_logging(matches, config_file_path)?;
+            let opts = bench::BenchOpts::from_matches(bench_cmd)?;
+            let report = bench::run(opts.clone()).await;
+            let cleanup = if bench_cmd.is_present("path") {
+                Ok(())
+            } else {
+                bench::cleanup(&opts)
+            };
+            // a failed benchmark is reported before any failure to clean up after it
+            let report = report?;
+            cleanup?;
+            if bench_cmd.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                report.print_text();
+            }
+            Ok(())
+        }
+        ("inspect", Some(inspect_cmd)) => {
+            let conf = get_config(matches, config_file_path)?;
+            let db = inspect::open(&conf.db)?;
+            let tree_name = inspect_cmd
+                .subcommand()
+                .1
+                .and_then(|cmd| cmd.value_of("tree"))
+                .unwrap_or(bonerjams_db::types::DEFAULT_TREE_ID);
+            let tree = || inspect::open_tree(&db, tree_name);
+            match inspect_cmd.subcommand() {
+                ("trees", Some(_)) => inspect::list_trees(&db),
+                ("stats", Some(_)) => inspect::stats(&db),
+                ("keys", Some(keys_cmd)) => inspect::dump_keys(
+                    &tree()?,
+                    &inspect::key_arg(keys_cmd, "prefix")?,
+                    keys_cmd.value_of("limit").map(|l| l.parse()).transpose()?,
+                    keys_cmd.is_present("values"),
+                ),
+                ("get", Some(get_cmd)) => {
+                    inspect::show_value(&tree()?, &inspect::key_arg(get_cmd, "key")?)
+                }
+                ("verify", Some(verify_cmd)) => inspect::verify(
+                    &tree()?,
+                    verify_cmd.value_of("codec").unwrap_or("json").parse()?,
+                ),
+                _ => invalid_subcommand("inspect"),
+            }
+        }
         _ => invalid_command(),
     }
 }
@@ -136,8 +446,157 @@ fn get_config_or_default(matches: &clap::ArgMatches) -> String {
         .to_string()
 }
 
-pub fn get_config(path: &str) -> Result<Configuration> {
-    Configuration::load(path, false)
+// loads the config file, env vars and --set overrides. the default config file is
+// optional, so a node may be configured through env vars alone
+pub fn get_config(matches: &clap::ArgMatches, path: &str) -> Result<Configuration> {
+    let path = if matches.is_present("config") || std::path::Path::new(path).exists() {
+        Some(path)
+    } else {
+        None
+    };
+    let overrides = matches
+        .values_of("set")
+        .map(|values| values.map(str::to_string).collect::<Vec<_>>())
+        .unwrap_or_default();
+    Configuration::load_layered(path, false, &overrides)
+}
+
+/// returns a client for the node at `url`, which accepts invalid tls certificates
+/// if `--insecure` is set
+fn new_client(matches: &clap::ArgMatches, url: &str) -> Result<KVClient> {
+    KVClient::builder()
+        .url(url)
+        .accept_invalid_certs(matches.is_present("insecure"))
+        .build()
+}
+
+// initializes logging from the configuration, at the debug level if --debug is set
+fn init_logging(matches: &clap::ArgMatches, path: &str) -> Result<()> {
+    let mut conf = get_config(matches, path)?.logging;
+    if matches.is_present("debug") {
+        conf.level = "debug".to_string();
+    }
+    bonerjams_config::logging::init(&conf)
+}
+
+// the config file is only needed to locate the embedded database
+fn transfer_config(
+    matches: &clap::ArgMatches,
+    opts: &transfer::TransferOpts,
+    path: &str,
+) -> Result<Configuration> {
+    match opts.target {
+        transfer::Target::Tree(_) => get_config(matches, path),
+        transfer::Target::Namespace { .. } => Ok(Configuration::default()),
+    }
+}
+
+// arguments used by the bench command
+fn bench_args() -> Vec<Arg<'static, 'static>> {
+    let value_arg = |name: &'static str, help: &'static str, default: &'static str| {
+        Arg::with_name(name)
+            .long(name)
+            .help(help)
+            .takes_value(true)
+            .default_value(default)
+    };
+    vec![
+        value_arg(
+            "operations",
+            "the total number of operations to issue",
+            "100000",
+        ),
+        value_arg("concurrency", "the number of concurrent workers", "8"),
+        value_arg("key-space", "the number of distinct keys", "10000"),
+        value_arg("value-size", "the size of written values in bytes", "256"),
+        value_arg(
+            "read-ratio",
+            "the fraction of operations which are reads",
+            "0.5",
+        ),
+        value_arg("batch-size", "the number of keys per operation", "1"),
+        value_arg("distribution", "how keys are selected", "uniform")
+            .possible_values(&["uniform", "zipfian"]),
+        value_arg(
+            "zipf-exponent",
+            "the exponent of the zipfian distribution",
+            "0.99",
+        ),
+        value_arg("mode", "the embedded database mode", "fast")
+            .possible_values(&["fast", "low-space"]),
+        Arg::with_name("compression-factor")
+            .long("compression-factor")
+            .help("if present, enables compression of the embedded database")
+            .takes_value(true),
+        Arg::with_name("path")
+            .long("path")
+            .help("path of the embedded database, if present it is kept afterwards")
+            .takes_value(true)
+            .conflicts_with("url"),
+        Arg::with_name("url")
+            .long("url")
+            .help("url of the key-value server to benchmark")
+            .takes_value(true),
+        Arg::with_name("namespace")
+            .long("namespace")
+            .help("the namespace to use when benchmarking a key-value server")
+            .takes_value(true),
+        Arg::with_name("prefill")
+            .long("prefill")
+            .help("if present, write every key before the benchmark starts")
+            .takes_value(false),
+        Arg::with_name("json")
+            .long("json")
+            .help("if present, print the report as json")
+            .takes_value(false),
+    ]
+}
+
+// arguments shared by the import and export commands
+fn transfer_args() -> Vec<Arg<'static, 'static>> {
+    vec![
+        Arg::with_name("format")
+            .long("format")
+            .help("the file format to use")
+            .takes_value(true)
+            .possible_values(&["jsonl", "csv"])
+            .default_value("jsonl"),
+        Arg::with_name("key-encoding")
+            .long("key-encoding")
+            .help("how keys are encoded within the file")
+            .takes_value(true)
+            .possible_values(&["utf8", "base64"])
+            .default_value("utf8"),
+        Arg::with_name("value-encoding")
+            .long("value-encoding")
+            .help("how values are encoded within the file")
+            .takes_value(true)
+            .possible_values(&["utf8", "base64"])
+            .default_value("base64"),
+        Arg::with_name("tree")
+            .long("tree")
+            .help("the embedded database tree to use, defaults to the default tree")
+            .takes_value(true)
+            .conflicts_with("url"),
+        Arg::with_name("url")
+            .long("url")
+            .help("url of the key-value server, if present a namespace is used instead of a tree")
+            .takes_value(true)
+            .requires("namespace"),
+        Arg::with_name("namespace")
+            .long("namespace")
+            .help("the key-value server namespace to use")
+            .takes_value(true),
+        Arg::with_name("batch-size")
+            .long("batch-size")
+            .help("number of records to write per batch")
+            .takes_value(true),
+        Arg::with_name("resume")
+            .long("resume")
+            .help("if present, resume from the last saved checkpoint")
+            .takes_value(false)
+            .required(false),
+    ]
 }
 
 fn invalid_subcommand(command_group: &str) -> Result<()> {
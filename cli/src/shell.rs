@@ -0,0 +1,315 @@
+//! an interactive shell for working with a key-value server
+
+use anyhow::{anyhow, Result};
+use bonerjams_db::{api::client::KVClient, prelude::*};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const COMMANDS: &[&str] = &[
+    "use", "get", "put", "del", "scan", "stats", "history", "format", "help", "exit", "quit",
+];
+
+const HELP: &str = "commands:
+    use <namespace>          select the namespace used by other commands
+    get <key>...             show the values of one or more keys
+    put <key> <value>        store a value, the value is the remainder of the line
+    del <key>...             delete one or more keys
+    scan [prefix] [limit]    list the keys in the namespace
+    stats                    show cluster statistics
+    history                  show previously entered commands
+    format <text|json|base64> change how values are displayed
+    help                     show this message
+    exit                     leave the shell";
+
+/// the maximum number of keys fetched to populate key completion
+const COMPLETION_SCAN_LIMIT: usize = 1000;
+
+/// controls how values are displayed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    /// values are displayed as (lossy) utf8 strings
+    Text,
+    /// values are displayed as pretty printed json objects
+    Json,
+    /// values are displayed as base64
+    Base64,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "base64" => Ok(Self::Base64),
+            _ => Err(anyhow!("unsupported format {}", s)),
+        }
+    }
+}
+
+/// provides tab completion of commands, namespaces and keys.
+/// namespaces and keys are collected as they are used within the session
+#[derive(Default)]
+struct ShellHelper {
+    namespaces: BTreeSet<String>,
+    keys: BTreeSet<String>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = Pair;
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line
+            .rfind(char::is_whitespace)
+            .map(|idx| idx + 1)
+            .unwrap_or(0);
+        let word = &line[start..];
+        let candidates: Vec<String> = if start == 0 {
+            COMMANDS.iter().map(|command| command.to_string()).collect()
+        } else if line.starts_with("use ") {
+            self.namespaces.iter().cloned().collect()
+        } else if line.starts_with("format ") {
+            vec!["text".to_string(), "json".to_string(), "base64".to_string()]
+        } else {
+            self.keys.iter().cloned().collect()
+        };
+        Ok((
+            start,
+            candidates
+                .into_iter()
+                .filter(|candidate| candidate.starts_with(word))
+                .map(|candidate| Pair {
+                    display: candidate.clone(),
+                    replacement: candidate,
+                })
+                .collect(),
+        ))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+pub struct Shell {
+    client: KVClient,
+    editor: Editor<ShellHelper>,
+    namespace: Option<String>,
+    format: OutputFormat,
+    history_path: Option<PathBuf>,
+}
+
+impl Shell {
+    pub fn new(client: KVClient) -> Result<Self> {
+        let mut editor = Editor::new()?;
+        editor.set_helper(Some(ShellHelper::default()));
+        let history_path =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".bonerjams_history"));
+        if let Some(path) = history_path.as_ref() {
+            // the history file won't exist the first time the shell is used
+            let _ = editor.load_history(path);
+        }
+        Ok(Self {
+            client,
+            editor,
+            namespace: None,
+            format: OutputFormat::Text,
+            history_path,
+        })
+    }
+    /// reads and executes commands until the user exits
+    pub async fn run(&mut self) -> Result<()> {
+        println!("{}", HELP);
+        loop {
+            let prompt = match self.namespace.as_ref() {
+                Some(namespace) => format!("bonerjams:{}> ", namespace),
+                None => "bonerjams> ".to_string(),
+            };
+            let line = match tokio::task::block_in_place(|| self.editor.readline(&prompt)) {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(err) => return Err(err.into()),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            self.editor.add_history_entry(line);
+            match self.execute(line).await {
+                Ok(true) => break,
+                Ok(false) => (),
+                Err(err) => println!("error: {:#}", err),
+            }
+        }
+        if let Some(path) = self.history_path.as_ref() {
+            if let Err(err) = self.editor.save_history(path) {
+                log::warn!("failed to save shell history {:#?}", err);
+            }
+        }
+        Ok(())
+    }
+    /// executes a single command, returning true if the shell should exit
+    async fn execute(&mut self, line: &str) -> Result<bool> {
+        let (command, args) = match line.split_once(char::is_whitespace) {
+            Some((command, args)) => (command, args.trim()),
+            None => (line, ""),
+        };
+        match command {
+            "use" => {
+                if args.is_empty() {
+                    return Err(anyhow!("usage: use <namespace>"));
+                }
+                self.namespace = Some(args.to_string());
+                let keys = self
+                    .client
+                    .scan_keys(args, None, Some(COMPLETION_SCAN_LIMIT))
+                    .await?
+                    .keys;
+                if let Some(helper) = self.editor.helper_mut() {
+                    helper.namespaces.insert(args.to_string());
+                    helper.keys = keys.into_iter().collect();
+                }
+                println!("using namespace {}", args);
+            }
+            "get" => {
+                let namespace = self.namespace()?;
+                let keys = args
+                    .split_whitespace()
+                    .map(|key| key.to_string())
+                    .collect::<Vec<_>>();
+                if keys.is_empty() {
+                    return Err(anyhow!("usage: get <key>..."));
+                }
+                let mut response = self
+                    .client
+                    .get_key_values(&[namespace.clone()], &mut [keys.clone()])
+                    .await?;
+                let docs = response.entries.remove(&namespace).unwrap_or_default();
+                for key in keys {
+                    match docs.iter().find(|doc| doc.key.eq(&key)) {
+                        Some(doc) => println!("{}", self.format_document(doc)?),
+                        None => println!("{} not found", key),
+                    }
+                }
+            }
+            "put" => {
+                let namespace = self.namespace()?;
+                let (key, value) = args
+                    .split_once(char::is_whitespace)
+                    .ok_or_else(|| anyhow!("usage: put <key> <value>"))?;
+                self.client
+                    .put_key_values(
+                        &[namespace],
+                        &mut [vec![KeyValue {
+                            key: key.to_string(),
+                            value: value.trim().as_bytes().to_vec(),
+                        }]],
+                    )
+                    .await?;
+                if let Some(helper) = self.editor.helper_mut() {
+                    helper.keys.insert(key.to_string());
+                }
+                println!("ok");
+            }
+            "del" => {
+                let namespace = self.namespace()?;
+                let keys = args
+                    .split_whitespace()
+                    .map(|key| key.to_string())
+                    .collect::<Vec<_>>();
+                if keys.is_empty() {
+                    return Err(anyhow!("usage: del <key>..."));
+                }
+                self.client
+                    .delete_key_values(&[namespace], &mut [keys.clone()])
+                    .await?;
+                if let Some(helper) = self.editor.helper_mut() {
+                    keys.iter().for_each(|key| {
+                        helper.keys.remove(key);
+                    });
+                }
+                println!("ok");
+            }
+            "scan" => {
+                let namespace = self.namespace()?;
+                let mut args = args.split_whitespace();
+                let prefix = args.next();
+                let limit = args.next().map(|limit| limit.parse()).transpose()?;
+                let keys = self.client.scan_keys(&namespace, prefix, limit).await?.keys;
+                keys.iter().for_each(|key| println!("{}", key));
+                println!("({} keys)", keys.len());
+                if let Some(helper) = self.editor.helper_mut() {
+                    helper.keys.extend(keys);
+                }
+            }
+            "stats" => {
+                let stats = self.client.cluster_stats().await?;
+                match self.format {
+                    OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&stats)?),
+                    _ => {
+                        if let Value::Object(fields) = serde_json::to_value(&stats)? {
+                            fields
+                                .iter()
+                                .for_each(|(name, value)| println!("{}: {}", name, value));
+                        }
+                    }
+                }
+            }
+            "history" => {
+                self.editor
+                    .history()
+                    .iter()
+                    .enumerate()
+                    .for_each(|(idx, entry)| println!("{:>5}  {}", idx + 1, entry));
+            }
+            "format" => {
+                self.format = args.parse()?;
+                println!("output format set to {:?}", self.format);
+            }
+            "help" => println!("{}", HELP),
+            "exit" | "quit" => return Ok(true),
+            _ => return Err(anyhow!("unknown command {}, see help", command)),
+        }
+        Ok(false)
+    }
+    fn namespace(&self) -> Result<String> {
+        self.namespace
+            .clone()
+            .ok_or_else(|| anyhow!("no namespace selected, run `use <namespace>` first"))
+    }
+    fn format_document(&self, doc: &WrappedDocument) -> Result<String> {
+        Ok(match self.format {
+            OutputFormat::Text => format!("{} = {}", doc.key, String::from_utf8_lossy(&doc.data)),
+            OutputFormat::Base64 => format!("{} = {}", doc.key, base64::encode(&doc.data)),
+            OutputFormat::Json => {
+                let value = serde_json::from_slice::<Value>(&doc.data).unwrap_or_else(|_| {
+                    Value::String(String::from_utf8_lossy(&doc.data).to_string())
+                });
+                serde_json::to_string_pretty(&serde_json::json!({
+                    "key": doc.key,
+                    "value": value,
+                }))?
+            }
+        })
+    }
+}
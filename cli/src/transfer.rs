@@ -0,0 +1,566 @@
+//! import and export of embedded database trees, and key-value server namespaces
+//! as newline delimited json or csv
+
+use anyhow::{anyhow, Context, Result};
+use bonerjams_config::Configuration;
+use bonerjams_db::{api::client::KVClient, prelude::*, Database, DbBatch};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Bound;
+use std::str::FromStr;
+
+/// the default number of records that are written or committed at once
+pub const DEFAULT_BATCH_SIZE: usize = 1000;
+
+/// the file format records are stored in
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// newline delimited json, one record per line
+    Jsonl,
+    /// comma separated values with a `key,value` header
+    Csv,
+}
+
+/// controls how raw bytes are represented within a record
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Encoding {
+    Base64,
+    Utf8,
+}
+
+/// a single exported key-value pair, with the key and value
+/// encoded according to the selected `Encoding`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub key: String,
+    pub value: String,
+}
+
+/// persisted alongside the data file so an interrupted transfer can be resumed
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Checkpoint {
+    /// the number of input entries (records or keys) which have been fully processed
+    pub processed: u64,
+    /// the last key exported. keys exported from a tree are encoded with the key
+    /// encoding, whereas keys exported from a namespace are kept as is
+    pub last_key: Option<String>,
+    /// the length of the data file when the checkpoint was saved. records written
+    /// after it are truncated on resume, as they are exported again
+    #[serde(default)]
+    pub offset: u64,
+}
+
+/// the source or destination of a transfer
+#[derive(Debug, Clone)]
+pub enum Target {
+    /// a tree within the embedded database, `None` selects the default tree
+    Tree(Option<String>),
+    /// a namespace on a key-value server
+    Namespace {
+        url: String,
+        namespace: String,
+        /// file containing the keys to export, one per line. if None
+        /// all keys within the namespace are exported
+        keys: Option<String>,
+        /// if true, the server's tls certificate is not verified
+        insecure: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct TransferOpts {
+    /// the file records are read from or written to
+    pub path: String,
+    pub format: Format,
+    pub key_encoding: Encoding,
+    pub value_encoding: Encoding,
+    pub batch_size: usize,
+    /// if true, continue from the last saved checkpoint
+    pub resume: bool,
+    pub target: Target,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "jsonl" | "ndjson" => Ok(Self::Jsonl),
+            "csv" => Ok(Self::Csv),
+            _ => Err(anyhow!("unsupported format {}", s)),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "base64" => Ok(Self::Base64),
+            "utf8" | "utf-8" => Ok(Self::Utf8),
+            _ => Err(anyhow!("unsupported encoding {}", s)),
+        }
+    }
+}
+
+impl Encoding {
+    pub fn encode(&self, data: &[u8]) -> Result<String> {
+        match self {
+            Self::Base64 => Ok(base64::encode(data)),
+            Self::Utf8 => String::from_utf8(data.to_vec())
+                .with_context(|| "data is not valid utf8, use base64 encoding instead"),
+        }
+    }
+    pub fn decode(&self, data: &str) -> Result<Vec<u8>> {
+        match self {
+            Self::Base64 => Ok(base64::decode(data)?),
+            Self::Utf8 => Ok(data.as_bytes().to_vec()),
+        }
+    }
+}
+
+impl Checkpoint {
+    fn file_path(data_path: &str) -> String {
+        format!("{}.progress", data_path)
+    }
+    /// loads the checkpoint for the given data file, returning
+    /// an empty checkpoint if none exists
+    pub fn load(data_path: &str) -> Result<Self> {
+        match std::fs::read(Self::file_path(data_path)) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Default::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+    pub fn save(&self, data_path: &str) -> Result<()> {
+        std::fs::write(Self::file_path(data_path), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+    /// removes the checkpoint once a transfer has completed
+    pub fn clear(data_path: &str) -> Result<()> {
+        match std::fs::remove_file(Self::file_path(data_path)) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+impl TransferOpts {
+    /// parses the options shared by the import and export commands,
+    /// with `path_arg` being the name of the argument holding the data file
+    pub fn from_matches(matches: &clap::ArgMatches, path_arg: &str) -> Result<Self> {
+        let path = matches
+            .value_of(path_arg)
+            .ok_or_else(|| anyhow!("missing {} argument", path_arg))?
+            .to_string();
+        let target = if let Some(url) = matches.value_of("url") {
+            Target::Namespace {
+                url: url.to_string(),
+                namespace: matches
+                    .value_of("namespace")
+                    .ok_or_else(|| anyhow!("--namespace is required with --url"))?
+                    .to_string(),
+                keys: matches.value_of("keys").map(|keys| keys.to_string()),
+                insecure: matches.is_present("insecure"),
+            }
+        } else {
+            Target::Tree(matches.value_of("tree").map(|tree| tree.to_string()))
+        };
+        let batch_size = match matches.value_of("batch-size") {
+            Some(size) => size.parse()?,
+            None => DEFAULT_BATCH_SIZE,
+        };
+        if batch_size == 0 {
+            return Err(anyhow!("batch size must be greater than 0"));
+        }
+        Ok(Self {
+            path,
+            format: matches.value_of("format").unwrap_or("jsonl").parse()?,
+            key_encoding: matches.value_of("key-encoding").unwrap_or("utf8").parse()?,
+            value_encoding: matches
+                .value_of("value-encoding")
+                .unwrap_or("base64")
+                .parse()?,
+            batch_size,
+            resume: matches.is_present("resume"),
+            target,
+        })
+    }
+}
+
+/// exports all records from the target into the data file
+pub async fn export(conf: &Configuration, opts: &TransferOpts) -> Result<()> {
+    let mut checkpoint = if opts.resume {
+        Checkpoint::load(&opts.path)?
+    } else {
+        Checkpoint::default()
+    };
+    if checkpoint.offset == 0 {
+        // nothing was written, or the checkpoint predates offsets so start over
+        checkpoint = Checkpoint::default();
+    }
+    let mut writer = RecordWriter::open(&opts.path, opts.format, checkpoint.offset)?;
+    match &opts.target {
+        Target::Tree(tree) => {
+            let db = Database::new(&conf.db)?;
+            let tree = db.open_tree(tree_id(tree))?;
+            let start = match checkpoint.last_key.as_ref() {
+                Some(key) => Bound::Excluded(opts.key_encoding.decode(key)?),
+                None => Bound::Unbounded,
+            };
+            for entry in tree.range((start, Bound::Unbounded)) {
+                let (key, value) = entry?;
+                let record = Record {
+                    key: opts.key_encoding.encode(&key)?,
+                    value: opts.value_encoding.encode(&value)?,
+                };
+                writer.write(&record)?;
+                checkpoint.processed += 1;
+                checkpoint.last_key = Some(record.key);
+                if checkpoint.processed % opts.batch_size as u64 == 0 {
+                    checkpoint.offset = writer.flush()?;
+                    checkpoint.save(&opts.path)?;
+                    log::info!("exported {} records", checkpoint.processed);
+                }
+            }
+        }
+        Target::Namespace {
+            url,
+            namespace,
+            keys,
+            insecure,
+        } => {
+            let client = KVClient::builder()
+                .url(url)
+                .accept_invalid_certs(*insecure)
+                .build()?;
+            let mut keys = match keys.as_ref() {
+                Some(keys) => BufReader::new(File::open(keys)?)
+                    .lines()
+                    .collect::<std::io::Result<Vec<_>>>()?
+                    .into_iter()
+                    .filter(|key| !key.is_empty())
+                    .collect::<Vec<_>>(),
+                None => client.scan_keys(namespace, None, None).await?.keys,
+            };
+            // keys are exported in order, so a resumed export continues after the last
+            // exported key even if keys were written or deleted in the meantime
+            keys.sort_unstable();
+            keys.dedup();
+            let skip = match checkpoint.last_key.as_ref() {
+                Some(last_key) => keys.partition_point(|key| key <= last_key),
+                None => 0,
+            };
+            for chunk in keys[skip..].chunks(opts.batch_size) {
+                let mut response = client
+                    .get_key_values(&[namespace.clone()], &mut [chunk.to_vec()])
+                    .await?;
+                for doc in response.entries.remove(namespace).unwrap_or_default() {
+                    writer.write(&Record {
+                        key: opts.key_encoding.encode(doc.key.as_bytes())?,
+                        value: opts.value_encoding.encode(&doc.data)?,
+                    })?;
+                }
+                checkpoint.offset = writer.flush()?;
+                checkpoint.processed += chunk.len() as u64;
+                checkpoint.last_key = chunk.last().cloned();
+                checkpoint.save(&opts.path)?;
+                log::info!("exported {}/{} keys", checkpoint.processed, keys.len());
+            }
+        }
+    }
+    writer.flush()?;
+    Checkpoint::clear(&opts.path)?;
+    log::info!("finished export of {} entries", checkpoint.processed);
+    Ok(())
+}
+
+/// imports all records from the data file into the target
+pub async fn import(conf: &Configuration, opts: &TransferOpts) -> Result<()> {
+    let mut checkpoint = if opts.resume {
+        Checkpoint::load(&opts.path)?
+    } else {
+        Checkpoint::default()
+    };
+    let records = read_records(&opts.path, opts.format)?.skip(checkpoint.processed as usize);
+    match &opts.target {
+        Target::Tree(tree) => {
+            let db = Database::new(&conf.db)?;
+            let tree = db.open_tree(tree_id(tree))?;
+            let mut batch = DbBatch::new();
+            for record in records {
+                let record = record?;
+                batch.insert_raw(
+                    &opts.key_encoding.decode(&record.key)?,
+                    &opts.value_encoding.decode(&record.value)?,
+                )?;
+                if batch.count() as usize >= opts.batch_size {
+                    checkpoint.processed += batch.count();
+                    tree.apply_batch(&mut batch)?;
+                    tree.flush_async().await?;
+                    checkpoint.save(&opts.path)?;
+                    log::info!("imported {} records", checkpoint.processed);
+                    batch = DbBatch::new();
+                }
+            }
+            if batch.count() > 0 {
+                checkpoint.processed += batch.count();
+                tree.apply_batch(&mut batch)?;
+                tree.flush_async().await?;
+            }
+        }
+        Target::Namespace {
+            url,
+            namespace,
+            insecure,
+            ..
+        } => {
+            let client = KVClient::builder()
+                .url(url)
+                .accept_invalid_certs(*insecure)
+                .build()?;
+            let mut key_values = Vec::with_capacity(opts.batch_size);
+            let mut records = records.peekable();
+            while let Some(record) = records.next() {
+                let record = record?;
+                key_values.push(KeyValue {
+                    key: String::from_utf8(opts.key_encoding.decode(&record.key)?)
+                        .with_context(|| "namespace keys must be valid utf8")?,
+                    value: opts.value_encoding.decode(&record.value)?,
+                });
+                if key_values.len() >= opts.batch_size || records.peek().is_none() {
+                    checkpoint.processed += key_values.len() as u64;
+                    client
+                        .put_key_values(
+                            &[namespace.clone()],
+                            &mut [std::mem::take(&mut key_values)],
+                        )
+                        .await?;
+                    checkpoint.save(&opts.path)?;
+                    log::info!("imported {} records", checkpoint.processed);
+                }
+            }
+        }
+    }
+    Checkpoint::clear(&opts.path)?;
+    log::info!("finished import of {} records", checkpoint.processed);
+    Ok(())
+}
+
+fn tree_id(tree: &Option<String>) -> DbTrees<'_> {
+    match tree {
+        Some(tree) => DbTrees::Custom(tree),
+        None => DbTrees::Default,
+    }
+}
+
+fn read_records(path: &str, format: Format) -> Result<Box<dyn Iterator<Item = Result<Record>>>> {
+    let file = File::open(path)?;
+    match format {
+        Format::Jsonl => Ok(Box::new(
+            BufReader::new(file)
+                .lines()
+                .filter(|line| !matches!(line, Ok(line) if line.trim().is_empty()))
+                .map(|line| -> Result<Record> { Ok(serde_json::from_str(&line?)?) }),
+        )),
+        Format::Csv => Ok(Box::new(
+            csv::Reader::from_reader(file)
+                .into_deserialize::<Record>()
+                .map(|record| -> Result<Record> { Ok(record?) }),
+        )),
+    }
+}
+
+enum RecordWriter {
+    Jsonl(BufWriter<File>),
+    Csv(csv::Writer<File>),
+}
+
+impl RecordWriter {
+    /// opens the data file for writing, keeping the first `offset` bytes written by
+    /// an interrupted export. if any are kept no csv header is written
+    fn open(path: &str, format: Format, offset: u64) -> Result<Self> {
+        let append = offset > 0;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        file.set_len(offset)?;
+        Ok(match format {
+            Format::Jsonl => Self::Jsonl(BufWriter::new(file)),
+            Format::Csv => Self::Csv(
+                csv::WriterBuilder::new()
+                    .has_headers(!append)
+                    .from_writer(file),
+            ),
+        })
+    }
+    fn write(&mut self, record: &Record) -> Result<()> {
+        match self {
+            Self::Jsonl(writer) => {
+                serde_json::to_writer(&mut *writer, record)?;
+                writer.write_all(b"\n")?;
+            }
+            Self::Csv(writer) => writer.serialize(record)?,
+        }
+        Ok(())
+    }
+    /// flushes buffered records, returning the length of the data file
+    fn flush(&mut self) -> Result<u64> {
+        let file = match self {
+            Self::Jsonl(writer) => {
+                writer.flush()?;
+                writer.get_ref()
+            }
+            Self::Csv(writer) => {
+                writer.flush()?;
+                writer.get_ref()
+            }
+        };
+        Ok(file.metadata()?.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(path: &str, tree: &str, resume: bool) -> TransferOpts {
+        TransferOpts {
+            path: path.to_string(),
+            format: Format::Jsonl,
+            key_encoding: Encoding::Utf8,
+            value_encoding: Encoding::Utf8,
+            batch_size: 2,
+            resume,
+            target: Target::Tree(Some(tree.to_string())),
+        }
+    }
+
+    fn read_keys(path: &str) -> Vec<String> {
+        read_records(path, Format::Jsonl)
+            .unwrap()
+            .map(|record| record.unwrap().key)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_transfer_resume() {
+        let conf = Configuration {
+            db: bonerjams_config::database::DbOpts {
+                path: "test_transfer_resume.db".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let path = "test_transfer_resume.jsonl";
+        {
+            let db = Database::new(&conf.db).unwrap();
+            let tree = db.open_tree(DbTrees::Custom("source")).unwrap();
+            for key in ["a", "b", "c", "d", "e"] {
+                tree.insert_raw(key.as_bytes(), b"value").unwrap();
+            }
+            tree.flush().unwrap();
+        }
+
+        // an export interrupted after its first checkpoint, having written records
+        // past it which are exported again on resume
+        let mut writer = RecordWriter::open(path, Format::Jsonl, 0).unwrap();
+        for key in ["a", "b", "c"] {
+            writer
+                .write(&Record {
+                    key: key.to_string(),
+                    value: "value".to_string(),
+                })
+                .unwrap();
+        }
+        writer.flush().unwrap();
+        let offset = std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .take(2)
+            .map(|line| line.len() as u64 + 1)
+            .sum();
+        Checkpoint {
+            processed: 2,
+            last_key: Some("b".to_string()),
+            offset,
+        }
+        .save(path)
+        .unwrap();
+
+        export(&conf, &opts(path, "source", true)).await.unwrap();
+        assert_eq!(read_keys(path), vec!["a", "b", "c", "d", "e"]);
+        assert_eq!(Checkpoint::load(path).unwrap().processed, 0);
+
+        // a fresh export replaces the file
+        export(&conf, &opts(path, "source", false)).await.unwrap();
+        assert_eq!(read_keys(path), vec!["a", "b", "c", "d", "e"]);
+
+        import(&conf, &opts(path, "destination", false))
+            .await
+            .unwrap();
+        {
+            let db = Database::new(&conf.db).unwrap();
+            let tree = db.open_tree(DbTrees::Custom("destination")).unwrap();
+            assert_eq!(tree.len(), 5);
+        }
+
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_dir_all("test_transfer_resume.db").unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_transfer_namespace() {
+        use bonerjams_db::api::testing::TestClusterBuilder;
+
+        let cluster = TestClusterBuilder::new(1).build().await.unwrap();
+        let url = cluster.node(0).url();
+        let client = KVClient::new(&url).unwrap();
+        client
+            .put_key_values(
+                &["source".to_string()],
+                &mut [vec![KeyValue {
+                    key: "a/key".to_string(),
+                    value: b"value".to_vec(),
+                }]],
+            )
+            .await
+            .unwrap();
+        let path = "test_transfer_namespace.jsonl";
+        let namespace_opts = |namespace: &str| TransferOpts {
+            path: path.to_string(),
+            format: Format::Jsonl,
+            key_encoding: Encoding::Base64,
+            value_encoding: Encoding::Utf8,
+            batch_size: 2,
+            resume: false,
+            target: Target::Namespace {
+                url: url.clone(),
+                namespace: namespace.to_string(),
+                keys: None,
+                insecure: false,
+            },
+        };
+        let conf = Configuration::default();
+
+        // keys are exported with the key encoding, so they can be imported again
+        export(&conf, &namespace_opts("source")).await.unwrap();
+        assert_eq!(read_keys(path), vec![base64::encode("a/key")]);
+        import(&conf, &namespace_opts("destination")).await.unwrap();
+        let res = client
+            .get_key_values(
+                &["destination".to_string()],
+                &mut [vec!["a/key".to_string()]],
+            )
+            .await
+            .unwrap();
+        assert_eq!(res.entries["destination"][0].data, b"value".to_vec());
+
+        std::fs::remove_file(path).unwrap();
+        cluster.shutdown().await.unwrap();
+    }
+}
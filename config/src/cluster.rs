@@ -1,4 +1,9 @@
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// the repair interval used when `repair_interval` isn't set
+pub const DEFAULT_REPAIR_INTERVAL: Duration = Duration::from_secs(10 * 60);
 
 #[derive(Serialize, Deserialize, Default, Clone)]
 pub struct ClusterOpts {
@@ -9,5 +14,60 @@ pub struct ClusterOpts {
     pub cluster_node_endpoint: String,
     pub cluster_id: Option<String>,
     pub data_center: Option<String>,
+    /// how often anti-entropy repair runs, for example `30s`, `15m`, `1h` or `1d`.
+    /// a number without a unit is treated as seconds. defaults to 10 minutes
     pub repair_interval: Option<String>,
 }
+
+impl ClusterOpts {
+    /// parses `repair_interval`, returning `DEFAULT_REPAIR_INTERVAL` if it isn't set
+    pub fn repair_interval(&self) -> anyhow::Result<Duration> {
+        let interval = match self.repair_interval.as_ref() {
+            Some(interval) => interval.trim(),
+            None => return Ok(DEFAULT_REPAIR_INTERVAL),
+        };
+        let (value, unit) = interval.split_at(
+            interval
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(interval.len()),
+        );
+        let value: u64 = value
+            .parse()
+            .map_err(|_| anyhow!("invalid repair interval {}", interval))?;
+        let multiplier: u64 = match unit.trim() {
+            "" | "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 60 * 60 * 24,
+            _ => return Err(anyhow!("invalid repair interval unit {}", unit)),
+        };
+        let seconds = value
+            .checked_mul(multiplier)
+            .ok_or_else(|| anyhow!("repair interval {} is too long", interval))?;
+        if seconds == 0 {
+            return Err(anyhow!("repair interval must be greater than zero"));
+        }
+        Ok(Duration::from_secs(seconds))
+    }
+}
+
+/// the consistency level required for writes and deletes to succeed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsistencyLevel {
+    /// only the local node must acknowledge the operation
+    One,
+    /// a majority of nodes across the cluster must acknowledge the operation
+    Quorum,
+    /// a majority of nodes in the local data center must acknowledge the operation
+    LocalQuorum,
+    /// a majority of nodes in each data center must acknowledge the operation
+    EachQuorum,
+    /// every node must acknowledge the operation
+    All,
+}
+
+impl Default for ConsistencyLevel {
+    fn default() -> Self {
+        Self::EachQuorum
+    }
+}
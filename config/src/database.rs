@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct DbOpts {
     /// if Some, enable compression and set factor to this
     pub compression_factor: Option<i32>,
@@ -12,7 +12,7 @@ pub struct DbOpts {
     pub system_page_cache: Option<u64>,
 }
 
-#[derive(Clone, Copy, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum DbMode {
     LowSpace,
     Fast,
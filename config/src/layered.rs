@@ -0,0 +1,172 @@
+//! building the configuration from layers, each overriding fields of the last. layers
+//! are merged as json values before being deserialized, so a layer need only contain
+//! the fields it overrides
+
+use anyhow::{anyhow, Context};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value};
+
+/// the prefix of environment variables overriding the configuration
+pub const ENV_PREFIX: &str = "BONERJAMS_";
+
+/// separates the fields of a path within an environment variable's name
+const ENV_SEPARATOR: &str = "__";
+
+/// replaces secret values when the configuration is shown
+pub const REDACTED: &str = "<redacted>";
+
+/// suffixes of the names of fields holding secrets
+const SECRET_SUFFIXES: [&str; 4] = ["_key", "password", "secret", "token"];
+
+/// a field set by an environment variable or override, see `check_applied`
+pub(crate) struct Applied {
+    /// the variable or override which set the field
+    source: String,
+    path: Vec<String>,
+}
+
+/// reads a configuration file, which may contain only some fields
+pub(crate) fn read_file(path: &str, from_json: bool) -> anyhow::Result<Value> {
+    let data = std::fs::read(path).with_context(|| format!("failed to read {}", path))?;
+    let value = if from_json {
+        serde_json::from_slice(&data).with_context(|| format!("invalid json in {}", path))?
+    } else {
+        serde_yaml::from_slice(&data).with_context(|| format!("invalid yaml in {}", path))?
+    };
+    Ok(value)
+}
+
+/// merges a layer into the base, replacing every value the layer sets. objects are
+/// merged field by field, while other values including arrays are replaced whole
+pub(crate) fn merge(base: &mut Value, layer: Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer,
+    }
+}
+
+/// applies environment variables such as `BONERJAMS_API__LISTEN_ADDRESS`, which sets
+/// `api.listen_address` of `T`. variables without the prefix are ignored
+pub(crate) fn apply_env<T: DeserializeOwned>(
+    base: &mut Value,
+    vars: impl Iterator<Item = (String, String)>,
+) -> anyhow::Result<Vec<Applied>> {
+    let mut applied = Vec::new();
+    for (name, raw) in vars {
+        let path = match name.strip_prefix(ENV_PREFIX) {
+            Some(path) => path.to_lowercase(),
+            None => continue,
+        };
+        let path: Vec<String> = path.split(ENV_SEPARATOR).map(str::to_string).collect();
+        set::<T>(base, &path, &raw).with_context(|| format!("invalid {}", name))?;
+        applied.push(Applied { source: name, path });
+    }
+    Ok(applied)
+}
+
+/// applies overrides such as `api.listen_address=0.0.0.0:3001` to fields of `T`
+pub(crate) fn apply_overrides<T: DeserializeOwned>(
+    base: &mut Value,
+    overrides: &[String],
+) -> anyhow::Result<Vec<Applied>> {
+    let mut applied = Vec::new();
+    for item in overrides {
+        let (path, raw) = item
+            .split_once('=')
+            .ok_or_else(|| anyhow!("override {} is not of the form path=value", item))?;
+        let path: Vec<String> = path.split('.').map(str::to_string).collect();
+        set::<T>(base, &path, raw).with_context(|| format!("invalid override {}", item))?;
+        applied.push(Applied {
+            source: format!("override {}", item),
+            path,
+        });
+    }
+    Ok(applied)
+}
+
+/// returns an error if a variable or override set a field which isn't part of the
+/// configuration, such as a misspelt field, given the configuration as deserialized.
+/// deserializing ignores unknown fields, so such fields are missing from it
+pub(crate) fn check_applied(config: &Value, applied: &[Applied]) -> anyhow::Result<()> {
+    for applied in applied {
+        let mut field = config;
+        for key in applied.path.iter() {
+            field = field.get(key).ok_or_else(|| {
+                anyhow!(
+                    "{} sets {}, which is not a configuration field",
+                    applied.source,
+                    applied.path.join(".")
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// sets the field at the path, creating objects along the way. values are parsed as
+/// yaml, so `64` is a number and `[a, b]` a list, unless the field is a string. as
+/// fields which are null, such as an unset `tls_cert`, have no type to go by, the
+/// parsed value is only kept if `T` can be deserialized with it
+fn set<T: DeserializeOwned>(base: &mut Value, path: &[String], raw: &str) -> anyhow::Result<()> {
+    let field = field_mut(base, path)?;
+    if field.is_string() {
+        *field = Value::String(raw.to_string());
+        return Ok(());
+    }
+    let was_null = field.is_null();
+    *field = serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+    if !was_null || field.is_string() || accepts::<T>(base) {
+        return Ok(());
+    }
+    *field_mut(base, path)? = Value::String(raw.to_string());
+    if !accepts::<T>(base) {
+        // neither is valid, so the parsed value is left for deserializing to report
+        *field_mut(base, path)? =
+            serde_yaml::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()));
+    }
+    Ok(())
+}
+
+/// returns the field at the path, creating objects along the way
+fn field_mut<'a>(base: &'a mut Value, path: &[String]) -> anyhow::Result<&'a mut Value> {
+    let mut field = base;
+    for key in path {
+        if key.is_empty() {
+            return Err(anyhow!("the path contains an empty field"));
+        }
+        if field.is_null() {
+            *field = Value::Object(Map::new());
+        }
+        field = match field {
+            Value::Object(fields) => fields.entry(key.as_str()).or_insert(Value::Null),
+            _ => return Err(anyhow!("{} is not within an object", key)),
+        };
+    }
+    Ok(field)
+}
+
+/// returns true if `T` can be deserialized from the value
+fn accepts<T: DeserializeOwned>(value: &Value) -> bool {
+    serde_json::from_value::<T>(value.clone()).is_ok()
+}
+
+/// replaces the values of fields holding secrets, if they are set
+pub(crate) fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                if !value.is_null() && SECRET_SUFFIXES.iter().any(|suffix| key.ends_with(suffix)) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
@@ -1,24 +1,78 @@
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 pub mod cluster;
 pub mod database;
-use simplelog::*;
+pub mod layered;
+pub mod logging;
 
 #[derive(Clone, Deserialize, Serialize, Default)]
 pub struct Configuration {
     pub db: database::DbOpts,
     pub api: API,
+    #[serde(default)]
+    pub cluster: cluster::ClusterOpts,
+    #[serde(default)]
+    pub tracing: TracingConfig,
+    #[serde(default)]
+    pub logging: logging::LoggingConfig,
+}
+
+/// configures the export of request spans to an opentelemetry collector, which
+/// requires the `otel` feature of the db and cli crates
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TracingConfig {
+    /// the otlp grpc endpoint of the collector, such as `http://localhost:4317`.
+    /// if None spans are not exported
+    pub otlp_endpoint: Option<String>,
+    /// the `service.name` spans are exported with
+    pub service_name: String,
+    /// the fraction of traces sampled, between 0 and 1. requests continuing a
+    /// sampled trace are always sampled
+    pub sample_ratio: f64,
 }
 
 impl Configuration {
+    /// loads the configuration file, with defaults for any fields it does not set.
+    /// unlike `load_layered` environment variables are not applied
     pub fn load(path: &str, from_json: bool) -> anyhow::Result<Configuration> {
-        let data = std::fs::read(path).expect("failed to read file");
-        let config: Configuration = if from_json {
-            serde_json::from_slice(data.as_slice())?
-        } else {
-            serde_yaml::from_slice(data.as_slice())?
-        };
+        let mut config = serde_json::to_value(Configuration::default())?;
+        layered::merge(&mut config, layered::read_file(path, from_json)?);
+        serde_json::from_value(config).context("invalid configuration")
+    }
+    /// loads the configuration from layers, each overriding the fields set by the
+    /// last: the defaults, the file at `path` if any, `BONERJAMS_*` environment
+    /// variables, and then `overrides` such as `api.listen_address=0.0.0.0:3001`.
+    /// see `layered` for how variables and overrides name fields
+    pub fn load_layered(
+        path: Option<&str>,
+        from_json: bool,
+        overrides: &[String],
+    ) -> anyhow::Result<Configuration> {
+        let mut config = serde_json::to_value(Configuration::default())?;
+        if let Some(path) = path {
+            layered::merge(&mut config, layered::read_file(path, from_json)?);
+        }
+        let mut applied = layered::apply_env::<Configuration>(&mut config, std::env::vars())?;
+        applied.extend(layered::apply_overrides::<Configuration>(
+            &mut config,
+            overrides,
+        )?);
+        let config: Configuration =
+            serde_json::from_value(config).context("invalid configuration")?;
+        layered::check_applied(&serde_json::to_value(&config)?, &applied)?;
         Ok(config)
     }
+    /// returns the configuration as it would be saved, with secrets redacted
+    pub fn to_redacted_string(&self, as_json: bool) -> anyhow::Result<String> {
+        let mut config = serde_json::to_value(self)?;
+        layered::redact(&mut config);
+        Ok(if as_json {
+            serde_json::to_string_pretty(&config)?
+        } else {
+            serde_yaml::to_string(&config)?
+        })
+    }
     /// saves the configuration file to disk.
     /// when `store_market_data`is false, the `Markets.data` field is reset to default
     pub fn save(&self, path: &str, as_json: bool) -> anyhow::Result<()> {
@@ -27,7 +81,7 @@ impl Configuration {
         } else {
             serde_yaml::to_string(self)?
         };
-        std::fs::write(path, data).expect("failed to write to file");
+        std::fs::write(path, data).with_context(|| format!("failed to write {}", path))?;
         Ok(())
     }
 }
@@ -40,6 +94,65 @@ pub struct API {
     pub cors: Option<CORSConfig>,
     pub tls_cert: Option<String>,
     pub tls_key: Option<String>,
+    /// consistency level used for writes and deletes, defaults to `EachQuorum`
+    pub consistency: Option<cluster::ConsistencyLevel>,
+    #[serde(default)]
+    pub limits: RequestLimits,
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    #[serde(default)]
+    pub grpc: GrpcConfig,
+    #[serde(default)]
+    pub blobs: BlobConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+}
+
+/// configures the admin api, which operates the node and the cluster, such as changing
+/// the node's log filter, draining it, repairing or dropping namespaces and collecting
+/// blob garbage. it is unauthenticated, so is served on an address of its own which
+/// should only be reachable by operators
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct AdminConfig {
+    /// if Some, the admin api is served on this address, otherwise it is not served
+    pub listen_address: Option<String>,
+}
+
+/// configures the grpc api, which mirrors the key-value routes of the http api
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GrpcConfig {
+    pub enabled: bool,
+    /// if Some, the grpc api is served on this address, otherwise it is served on
+    /// `API.listen_address` alongside the http api
+    pub listen_address: Option<String>,
+}
+
+/// configures the chunked blob api, used for values too large for a single request
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BlobConfig {
+    /// the size of the chunks blobs are split into in bytes
+    pub chunk_size: usize,
+    /// the maximum size of a blob in bytes
+    pub max_blob_size: u64,
+    /// the number of seconds an upload may go without being appended to before it
+    /// is removed by garbage collection
+    pub upload_ttl: u64,
+    /// the number of seconds between garbage collections of orphaned chunks. chunks
+    /// are only collected once a full interval has passed since they were written
+    pub gc_interval: u64,
+}
+
+/// limits applied to requests, preventing a single client from exhausting memory
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RequestLimits {
+    /// the maximum size of a request body in bytes
+    pub max_body_size: usize,
+    /// the maximum number of keys within a single request, across all namespaces
+    pub max_batch_keys: usize,
+    /// the maximum length of a key in bytes
+    pub max_key_length: usize,
+    /// the maximum size of a value in bytes
+    pub max_value_size: usize,
 }
 #[derive(Serialize, Deserialize, Clone)]
 pub struct CORSConfig {
@@ -59,29 +172,75 @@ impl Default for API {
             }),
             tls_cert: None,
             tls_key: None,
+            consistency: None,
+            limits: RequestLimits::default(),
+            rate_limits: RateLimits::default(),
+            grpc: GrpcConfig::default(),
+            blobs: BlobConfig::default(),
+            admin: AdminConfig::default(),
         }
     }
 }
 
-pub fn init_log(debug_log: bool) -> anyhow::Result<()> {
-    if debug_log {
-        TermLogger::init(
-            LevelFilter::Debug,
-            ConfigBuilder::new()
-                .set_location_level(LevelFilter::Debug)
-                .build(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        )?;
-    } else {
-        TermLogger::init(
-            LevelFilter::Info,
-            ConfigBuilder::new()
-                .set_location_level(LevelFilter::Error)
-                .build(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        )?;
+/// token bucket rate limits, applied to each client separately. clients are identified
+/// by their ip address
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RateLimits {
+    /// if Some, applied to every request
+    pub default: Option<RateLimit>,
+    /// limits applied to specific routes, keyed by path such as `/put`, or
+    /// `/bonerjams.kv.KeyValue/Put` for grpc methods
+    #[serde(default)]
+    pub routes: HashMap<String, RateLimit>,
+    /// limits applied to requests reading or writing specific namespaces
+    #[serde(default)]
+    pub namespaces: HashMap<String, RateLimit>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct RateLimit {
+    /// the rate at which tokens are added to the bucket
+    pub requests_per_second: f64,
+    /// the size of the bucket, which is the number of requests that may be made at once
+    pub burst: u32,
+}
+
+impl Default for RequestLimits {
+    fn default() -> Self {
+        Self {
+            max_body_size: 16 * 1024 * 1024,
+            max_batch_keys: 10_000,
+            max_key_length: 1024,
+            max_value_size: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "bonerjams".to_string(),
+            sample_ratio: 1.0,
+        }
     }
-    Ok(())
+}
+
+impl Default for BlobConfig {
+    fn default() -> Self {
+        Self {
+            chunk_size: 1024 * 1024,
+            max_blob_size: 1024 * 1024 * 1024,
+            upload_ttl: 24 * 60 * 60,
+            gc_interval: 60 * 60,
+        }
+    }
+}
+
+/// logs to stderr at the info level, or the debug level if `debug_log` is true
+pub fn init_log(debug_log: bool) -> anyhow::Result<()> {
+    logging::init(&logging::LoggingConfig {
+        level: if debug_log { "debug" } else { "info" }.to_string(),
+        ..Default::default()
+    })
 }
@@ -0,0 +1,160 @@
+//! logging through `tracing-subscriber`. records of the `log` crate are forwarded to
+//! tracing, so both are filtered and formatted alike. the filter may be replaced while
+//! running through `set_filter`
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::{Layered, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, reload, EnvFilter, Layer, Registry};
+
+/// the subscriber additional layers given to `init_with` are applied to
+pub type Subscriber = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// a layer given to `init_with`, such as one exporting spans
+pub type BoxedLayer = Box<dyn Layer<Subscriber> + Send + Sync>;
+
+/// the filter installed by `init`, replaced by `set_filter`
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoggingConfig {
+    /// the level of records which are logged, such as `info` or `debug`
+    pub level: String,
+    /// levels of specific modules overriding `level`, such as `datacake: warn`
+    #[serde(default)]
+    pub filters: HashMap<String, String>,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// if Some, logs are written to rotated files rather than stderr
+    #[serde(default)]
+    pub file: Option<LogFile>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// a single line of text for each record
+    #[default]
+    Text,
+    /// multiple lines of text for each record, for reading during development
+    Pretty,
+    /// a json object for each record, including the fields of its spans
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LogFile {
+    /// the directory logs are written to
+    pub directory: String,
+    /// the name of log files, which are suffixed by the date they begin
+    pub prefix: String,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    /// if Some, the oldest files are removed once there are more than this many
+    #[serde(default)]
+    pub max_files: Option<usize>,
+}
+
+/// how often a new log file is begun
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    Minutely,
+    Hourly,
+    #[default]
+    Daily,
+    Never,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            filters: HashMap::new(),
+            format: LogFormat::default(),
+            file: None,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// returns the `EnvFilter` directives of the level and module filters
+    pub fn directives(&self) -> String {
+        let mut filters = self.filters.iter().collect::<Vec<_>>();
+        filters.sort();
+        std::iter::once(self.level.clone())
+            .chain(
+                filters
+                    .into_iter()
+                    .map(|(module, level)| format!("{}={}", module, level)),
+            )
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl LogFile {
+    fn appender(&self) -> anyhow::Result<RollingFileAppender> {
+        let rotation = match self.rotation {
+            LogRotation::Minutely => Rotation::MINUTELY,
+            LogRotation::Hourly => Rotation::HOURLY,
+            LogRotation::Daily => Rotation::DAILY,
+            LogRotation::Never => Rotation::NEVER,
+        };
+        let mut builder = RollingFileAppender::builder()
+            .rotation(rotation)
+            .filename_prefix(&self.prefix);
+        if let Some(max_files) = self.max_files {
+            builder = builder.max_log_files(max_files);
+        }
+        Ok(builder.build(&self.directory)?)
+    }
+}
+
+/// installs the global subscriber, failing if one is already installed
+pub fn init(conf: &LoggingConfig) -> anyhow::Result<()> {
+    init_with(conf, Vec::new())
+}
+
+/// installs the global subscriber with additional layers, which are subject to the
+/// same filter
+pub fn init_with(conf: &LoggingConfig, layers: Vec<BoxedLayer>) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_new(conf.directives())?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let (writer, ansi) = match conf.file.as_ref() {
+        Some(file) => (BoxMakeWriter::new(file.appender()?), false),
+        None => (BoxMakeWriter::new(std::io::stderr), true),
+    };
+    let output = fmt::layer().with_writer(writer).with_ansi(ansi);
+    let output: BoxedLayer = match conf.format {
+        LogFormat::Text => output.boxed(),
+        LogFormat::Pretty => output.pretty().boxed(),
+        LogFormat::Json => output.json().boxed(),
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(std::iter::once(output).chain(layers).collect::<Vec<_>>())
+        .try_init()?;
+    let _ = FILTER.set(handle);
+    Ok(())
+}
+
+/// returns the directives of the current filter, or None if logging was not
+/// initialized by `init`
+pub fn filter() -> Option<String> {
+    FILTER.get()?.with_current(|filter| filter.to_string()).ok()
+}
+
+/// replaces the filter with the given `EnvFilter` directives
+pub fn set_filter(directives: &str) -> anyhow::Result<()> {
+    let handle = FILTER
+        .get()
+        .ok_or_else(|| anyhow!("logging was not initialized from the configuration"))?;
+    handle.reload(EnvFilter::try_new(directives)?)?;
+    Ok(())
+}
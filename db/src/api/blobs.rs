@@ -0,0 +1,572 @@
+//! a chunked blob api for values too large for a single request or document. blobs
+//! are split into chunks of `BlobConfig.chunk_size`, which are stored in a system
+//! keyspace under the sha256 digest of their contents so identical chunks are only
+//! stored once. a manifest listing the chunks of a blob is written once every chunk
+//! has been, so readers never observe a partially written blob.
+//!
+//! every write goes through an upload, which records the chunks stored so far so an
+//! interrupted upload can be resumed from its offset. chunks referenced by neither a
+//! manifest nor an upload are deleted by `collect`. chunks are keyed by the epoch of
+//! `gc_interval` seconds they were written in, and only chunks written at least a full
+//! epoch ago are collected, so a chunk being written is never deleted. as a node may
+//! not have received every manifest, each node publishes the chunks it found orphaned,
+//! and a chunk is only deleted once every member of the cluster has found it orphaned.
+//! this assumes clocks are in sync to within `gc_interval`. blobs are not counted
+//! towards namespace quotas
+
+use super::error::Error;
+use super::membership::now_millis;
+use super::ApiState;
+use crate::prelude::{
+    BlobChunk, BlobInfo, CreateUploadRequest, GcResponse, MemberInfo, UploadStatus, WrappedDocument,
+};
+use axum::body::Bytes;
+use datacake::cluster::{Consistency, Storage};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+/// the keyspace chunks are stored in, keyed by their digest
+pub const CHUNKS_KEYSPACE: &str = "__bonerjams_blob_chunks";
+
+/// the keyspace blob manifests are stored in
+pub const MANIFESTS_KEYSPACE: &str = "__bonerjams_blobs";
+
+/// the keyspace uploads in progress are stored in
+pub const UPLOADS_KEYSPACE: &str = "__bonerjams_uploads";
+
+/// the keyspace the chunks each node last found orphaned are stored in, keyed by the
+/// node's id
+pub const GC_KEYSPACE: &str = "__bonerjams_blob_gc";
+
+/// the header giving the offset an append begins at
+pub const UPLOAD_OFFSET: &str = "upload-offset";
+
+/// the number of chunks read ahead of a client downloading a blob
+const READ_AHEAD: usize = 2;
+
+/// the chunks a node found orphaned by its last collection
+#[derive(Serialize, Deserialize)]
+struct GcReport {
+    /// unix timestamp in milliseconds of when the chunks were found orphaned
+    marked_at: u64,
+    /// the document ids of the orphaned chunks
+    orphaned: HashSet<u64>,
+}
+
+/// returns the manifest of a blob, or None if it does not exist or has expired
+pub async fn info<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    key: &str,
+) -> Result<Option<BlobInfo>, Error> {
+    read_json(handle, MANIFESTS_KEYSPACE, &manifest_key(namespace, key)).await
+}
+
+/// deletes a blob. its chunks are deleted by garbage collection
+pub async fn delete<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    key: &str,
+) -> Result<(), Error> {
+    let settings = super::namespaces::settings(handle, namespace)
+        .await
+        .map_err(server_error)?;
+    handle
+        .cluster_api
+        .del_many(
+            MANIFESTS_KEYSPACE,
+            std::iter::once(super::hash_key(&manifest_key(namespace, key))),
+            super::namespaces::consistency(handle, &settings),
+        )
+        .await
+        .map_err(server_error)
+}
+
+/// deletes every blob within a namespace, returning the number deleted
+pub async fn drop_namespace<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+) -> Result<u64, Error> {
+    let doc_ids = super::namespaces::documents(handle, MANIFESTS_KEYSPACE)
+        .await
+        .map_err(server_error)?
+        .into_iter()
+        .filter_map(|doc| serde_json::from_slice::<BlobInfo>(&doc.data).ok())
+        .filter(|blob| blob.namespace == namespace)
+        .map(|blob| super::hash_key(&manifest_key(&blob.namespace, &blob.key)))
+        .collect::<Vec<_>>();
+    let deleted = doc_ids.len() as u64;
+    handle
+        .cluster_api
+        .del_many(
+            MANIFESTS_KEYSPACE,
+            doc_ids.into_iter(),
+            handle.consistency(),
+        )
+        .await
+        .map_err(server_error)?;
+    Ok(deleted)
+}
+
+/// streams the chunks of a blob in order. each chunk is checked against its digest,
+/// and the stream ends with an error if a chunk is missing or corrupt
+pub fn read<S: Storage + Send + Sync + 'static>(
+    handle: Arc<ApiState<S>>,
+    blob: BlobInfo,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    let (tx, rx) = mpsc::channel(READ_AHEAD);
+    tokio::spawn(async move {
+        for chunk in blob.chunks.iter() {
+            let chunk = read_chunk(&handle, chunk).await;
+            let failed = chunk.is_err();
+            // sending fails once the client has gone away
+            if tx.send(chunk).await.is_err() || failed {
+                return;
+            }
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+async fn read_chunk<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    chunk: &BlobChunk,
+) -> Result<Bytes, Error> {
+    let doc = handle
+        .cluster_api
+        .get_many(
+            CHUNKS_KEYSPACE,
+            std::iter::once(super::hash_key(&chunk_key(chunk))),
+        )
+        .await
+        .map_err(server_error)?
+        .next()
+        .ok_or_else(|| server_error(format!("chunk {} is missing", chunk.digest)))?;
+    let doc = super::document::decode(&doc.data[..]).map_err(server_error)?;
+    if digest(&doc.data) != chunk.digest {
+        return Err(server_error(format!("chunk {} is corrupt", chunk.digest)));
+    }
+    Ok(Bytes::from(doc.data))
+}
+
+/// starts an upload of a blob, which is written once the upload is completed
+pub async fn create_upload<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    request: CreateUploadRequest,
+) -> Result<UploadStatus, Error> {
+    super::namespaces::check_name(&request.namespace)?;
+    super::limits::check_keys(&handle.limits, std::iter::once(&request.key))?;
+    if let Some(content_type) = request.content_type.as_ref() {
+        if content_type.len() > super::document::MAX_CONTENT_TYPE_LENGTH {
+            return Err(Error::CustomError("invalid content type".to_string()));
+        }
+    }
+    // RandomState is randomly seeded, so ids are unique across nodes
+    let random = RandomState::new().build_hasher().finish();
+    let upload = UploadStatus {
+        id: format!("{:016x}{:016x}", now_millis(), random),
+        namespace: request.namespace,
+        key: request.key,
+        content_type: request.content_type,
+        offset: 0,
+        chunks: Vec::new(),
+        updated_at: now_millis(),
+    };
+    save_upload(handle, &upload).await?;
+    Ok(upload)
+}
+
+/// returns an upload in progress, or None if it does not exist
+pub async fn upload<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    id: &str,
+) -> Result<Option<UploadStatus>, Error> {
+    read_json(handle, UPLOADS_KEYSPACE, id).await
+}
+
+/// appends a body to an upload, which must begin at the upload's offset. the body is
+/// split into chunks as it is received and the upload is saved after each chunk is
+/// stored, so an interrupted append may be resumed from the offset of the last chunk
+pub async fn append<S, B, E>(
+    handle: &ApiState<S>,
+    mut upload: UploadStatus,
+    offset: u64,
+    mut body: B,
+) -> Result<UploadStatus, Error>
+where
+    S: Storage + Send + Sync + 'static,
+    B: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    if offset != upload.offset {
+        return Err(Error::PreconditionFailed(format!(
+            "upload {} is at offset {}, not {}",
+            upload.id, upload.offset, offset
+        )));
+    }
+    let settings = super::namespaces::settings(handle, &upload.namespace)
+        .await
+        .map_err(server_error)?;
+    let consistency = super::namespaces::consistency(handle, &settings);
+    let chunk_size = handle.blobs.chunk_size.max(1);
+    let mut buf = Vec::with_capacity(chunk_size);
+    while let Some(bytes) = body.next().await {
+        let mut bytes =
+            bytes.map_err(|err| Error::CustomError(format!("failed to read body {}", err)))?;
+        let size = upload.offset + (buf.len() + bytes.len()) as u64;
+        if size > handle.blobs.max_blob_size {
+            return Err(Error::PayloadTooLarge(format!(
+                "blob exceeds max_blob_size of {}",
+                handle.blobs.max_blob_size
+            )));
+        }
+        while !bytes.is_empty() {
+            let take = (chunk_size - buf.len()).min(bytes.len());
+            buf.extend_from_slice(&bytes.split_to(take));
+            if buf.len() == chunk_size {
+                write_chunk(handle, &mut upload, &buf, consistency).await?;
+                buf.clear();
+            }
+        }
+    }
+    if !buf.is_empty() {
+        write_chunk(handle, &mut upload, &buf, consistency).await?;
+    }
+    Ok(upload)
+}
+
+/// writes the manifest of an upload, replacing any existing blob under its key
+pub async fn complete<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    upload: UploadStatus,
+) -> Result<BlobInfo, Error> {
+    let settings = super::namespaces::settings(handle, &upload.namespace)
+        .await
+        .map_err(server_error)?;
+    let expires_at = match settings.ttl {
+        Some(ttl) => Some(
+            super::namespaces::expires_at(ttl)
+                .ok_or_else(|| Error::CustomError(format!("ttl of {} seconds is too long", ttl)))?,
+        ),
+        None => None,
+    };
+    let blob = BlobInfo {
+        namespace: upload.namespace,
+        key: upload.key,
+        size: upload.offset,
+        content_type: upload.content_type,
+        chunks: upload.chunks,
+        created_at: now_millis(),
+    };
+    write_json(
+        handle,
+        MANIFESTS_KEYSPACE,
+        &manifest_key(&blob.namespace, &blob.key),
+        &blob,
+        expires_at,
+        super::namespaces::consistency(handle, &settings),
+    )
+    .await?;
+    abort_upload(handle, &upload.id).await?;
+    Ok(blob)
+}
+
+/// removes an upload. chunks it has stored are deleted by garbage collection
+pub async fn abort_upload<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    id: &str,
+) -> Result<(), Error> {
+    handle
+        .cluster_api
+        .del_many(
+            UPLOADS_KEYSPACE,
+            std::iter::once(super::hash_key(id)),
+            handle.consistency(),
+        )
+        .await
+        .map_err(server_error)
+}
+
+/// removes uploads idle for longer than `upload_ttl`, and deletes chunks which this
+/// node and every other member of the cluster found orphaned. a chunk is orphaned once
+/// it is referenced by neither a blob nor an upload and was written at least a full
+/// epoch ago. only the documents stored by each node are considered
+pub async fn collect<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+) -> Result<GcResponse, Error> {
+    let node_id = handle.node_id()?;
+    let now = now_millis();
+    let idle_since = now.saturating_sub(handle.blobs.upload_ttl.saturating_mul(1000));
+    let mut referenced = HashSet::new();
+    let mut expired = Vec::new();
+    for doc in super::namespaces::documents(handle, UPLOADS_KEYSPACE)
+        .await
+        .map_err(server_error)?
+    {
+        let upload: UploadStatus = match serde_json::from_slice(&doc.data) {
+            Ok(upload) => upload,
+            Err(_) => continue,
+        };
+        if upload.updated_at < idle_since {
+            expired.push(super::hash_key(&upload.id));
+        } else {
+            referenced.extend(upload.chunks.iter().map(chunk_id));
+        }
+    }
+    for doc in super::namespaces::documents(handle, MANIFESTS_KEYSPACE)
+        .await
+        .map_err(server_error)?
+    {
+        if let Ok(blob) = serde_json::from_slice::<BlobInfo>(&doc.data) {
+            referenced.extend(blob.chunks.iter().map(chunk_id));
+        }
+    }
+    let unreferenced = handle
+        .storage()?
+        .iter_metadata(CHUNKS_KEYSPACE)
+        .await
+        .map_err(server_error)?
+        .filter(|(doc_id, _, is_tombstone)| !is_tombstone && !referenced.contains(doc_id))
+        .map(|(doc_id, _, _)| doc_id)
+        .collect::<Vec<_>>();
+    // the epoch of a chunk is part of its key, so the unreferenced chunks are read
+    let collectable = epoch(handle, now).saturating_sub(1);
+    let orphaned = handle
+        .cluster_api
+        .get_many(CHUNKS_KEYSPACE, unreferenced.iter().copied())
+        .await
+        .map_err(server_error)?
+        .filter_map(|doc| super::document::decode(&doc.data[..]).ok())
+        .filter(|doc| matches!(chunk_epoch(&doc.key), Some(epoch) if epoch < collectable))
+        .map(|doc| super::hash_key(&doc.key))
+        .collect::<HashSet<_>>();
+    let report = GcReport {
+        marked_at: now,
+        orphaned,
+    };
+    // reports expire so that only recent collections are relied upon
+    write_json(
+        handle,
+        GC_KEYSPACE,
+        node_id,
+        &report,
+        Some(now.saturating_add(gc_interval_millis(handle).saturating_mul(2))),
+        Consistency::One,
+    )
+    .await?;
+    let deleted = confirm_orphaned(handle, node_id, report.orphaned).await?;
+    let response = GcResponse {
+        chunks_deleted: deleted.len() as u64,
+        chunks_pending: (unreferenced.len() - deleted.len()) as u64,
+        uploads_expired: expired.len() as u64,
+    };
+    handle
+        .cluster_api
+        .del_many(UPLOADS_KEYSPACE, expired.into_iter(), handle.consistency())
+        .await
+        .map_err(server_error)?;
+    handle
+        .cluster_api
+        .del_many(CHUNKS_KEYSPACE, deleted.into_iter(), handle.consistency())
+        .await
+        .map_err(server_error)?;
+    Ok(response)
+}
+
+/// returns the chunks this node found orphaned which every other member also found
+/// orphaned, so that a member which hasn't received a manifest yet can't have the
+/// chunks of the blob deleted. no chunks are deleted while a member has no report
+async fn confirm_orphaned<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    node_id: &str,
+    mut orphaned: HashSet<u64>,
+) -> Result<HashSet<u64>, Error> {
+    let reports = super::namespaces::documents(handle, GC_KEYSPACE)
+        .await
+        .map_err(server_error)?
+        .into_iter()
+        .filter_map(|doc| {
+            let report = serde_json::from_slice::<GcReport>(&doc.data).ok()?;
+            Some((doc.key, report))
+        })
+        .collect::<HashMap<_, _>>();
+    for doc in super::namespaces::documents(handle, super::membership::MEMBERSHIP_KEYSPACE)
+        .await
+        .map_err(server_error)?
+    {
+        let member = match serde_json::from_slice::<MemberInfo>(&doc.data) {
+            Ok(member) if member.node_id != node_id => member,
+            _ => continue,
+        };
+        match reports.get(&member.node_id) {
+            Some(report) => orphaned.retain(|doc_id| report.orphaned.contains(doc_id)),
+            None => orphaned.clear(),
+        }
+    }
+    Ok(orphaned)
+}
+
+/// spawns a task which collects garbage every `BlobConfig.gc_interval` seconds
+pub fn start_gc<S: Storage + Send + Sync + 'static>(
+    handle: Arc<ApiState<S>>,
+) -> tokio::task::JoinHandle<()> {
+    let interval = Duration::from_secs(handle.blobs.gc_interval.max(1));
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if handle.drain.is_draining() {
+                continue;
+            }
+            match collect(&handle).await {
+                Ok(res) => log::debug!("collected blob garbage {:?}", res),
+                Err(err) => log::error!("failed to collect blob garbage {:#?}", err),
+            }
+        }
+    })
+}
+
+/// stores a chunk, then saves the upload with the chunk appended
+async fn write_chunk<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    upload: &mut UploadStatus,
+    data: &[u8],
+    consistency: Consistency,
+) -> Result<(), Error> {
+    let chunk = BlobChunk {
+        digest: digest(data),
+        size: data.len() as u64,
+        epoch: epoch(handle, now_millis()),
+    };
+    let document = super::document::encode(&WrappedDocument {
+        key: chunk_key(&chunk),
+        data: data.to_vec(),
+        expires_at: None,
+        content_type: None,
+    });
+    handle
+        .cluster_api
+        .put_many(
+            CHUNKS_KEYSPACE,
+            std::iter::once((chunk_id(&chunk), document)),
+            consistency,
+        )
+        .await
+        .map_err(server_error)?;
+    upload.chunks.push(chunk);
+    upload.offset += data.len() as u64;
+    upload.updated_at = now_millis();
+    save_upload(handle, upload).await
+}
+
+async fn save_upload<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    upload: &UploadStatus,
+) -> Result<(), Error> {
+    write_json(
+        handle,
+        UPLOADS_KEYSPACE,
+        &upload.id,
+        upload,
+        None,
+        handle.consistency(),
+    )
+    .await
+}
+
+async fn read_json<S: Storage + Send + Sync + 'static, T: DeserializeOwned>(
+    handle: &ApiState<S>,
+    keyspace: &str,
+    key: &str,
+) -> Result<Option<T>, Error> {
+    let doc = handle
+        .cluster_api
+        .get_many(keyspace, std::iter::once(super::hash_key(key)))
+        .await
+        .map_err(server_error)?
+        .next();
+    let doc = match doc {
+        Some(doc) => super::document::decode(&doc.data[..]).map_err(server_error)?,
+        None => return Ok(None),
+    };
+    if doc.key != key || doc.is_expired() {
+        return Ok(None);
+    }
+    serde_json::from_slice(&doc.data)
+        .map(Some)
+        .map_err(server_error)
+}
+
+async fn write_json<S: Storage + Send + Sync + 'static, T: Serialize>(
+    handle: &ApiState<S>,
+    keyspace: &str,
+    key: &str,
+    value: &T,
+    expires_at: Option<u64>,
+    consistency: Consistency,
+) -> Result<(), Error> {
+    let document = super::document::encode(&WrappedDocument {
+        key: key.to_string(),
+        data: serde_json::to_vec(value).map_err(server_error)?,
+        expires_at,
+        content_type: None,
+    });
+    handle
+        .cluster_api
+        .put_many(
+            keyspace,
+            std::iter::once((super::hash_key(key), document)),
+            consistency,
+        )
+        .await
+        .map_err(server_error)
+}
+
+/// the key of a blob's manifest. the namespace is length prefixed so that keys and
+/// namespaces containing `/` can't collide
+fn manifest_key(namespace: &str, key: &str) -> String {
+    format!("{}:{}/{}", namespace.len(), namespace, key)
+}
+
+/// the key of a chunk, made of the epoch it was written in and its digest. identical
+/// chunks written within an epoch are only stored once
+fn chunk_key(chunk: &BlobChunk) -> String {
+    format!("{}/{}", chunk.epoch, chunk.digest)
+}
+
+/// the document id of a chunk
+fn chunk_id(chunk: &BlobChunk) -> u64 {
+    super::hash_key(&chunk_key(chunk))
+}
+
+/// the epoch of a chunk's key
+fn chunk_epoch(key: &str) -> Option<u64> {
+    key.split_once('/')?.0.parse().ok()
+}
+
+/// the garbage collection epoch of a unix timestamp in milliseconds
+fn epoch<S: Storage + Send + Sync + 'static>(handle: &ApiState<S>, millis: u64) -> u64 {
+    millis / gc_interval_millis(handle)
+}
+
+fn gc_interval_millis<S: Storage + Send + Sync + 'static>(handle: &ApiState<S>) -> u64 {
+    handle.blobs.gc_interval.max(1).saturating_mul(1000)
+}
+
+/// the hex encoded sha256 digest of a chunk
+fn digest(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+fn server_error(err: impl std::fmt::Display) -> Error {
+    Error::CustomServerError(err.to_string())
+}
@@ -0,0 +1,149 @@
+//! a synchronous client for the key-value server, for programs without a tokio
+//! runtime. it wraps `api::client::KVClient`, and so has the same retries, failover
+//! and tls options, driving each request to completion on a runtime owned by the
+//! client. like `reqwest::blocking` the client must not be used from within an async
+//! context, where it panics
+
+use super::client::{self, KVBatch, KVClientBuilder};
+use crate::api::codec::Format;
+use crate::prelude::{
+    ClusterKeyspacesResponse, ClusterMembersResponse, ClusterStatistics, DrainStatus,
+    GetKVsResponse, HealthStatus, KeyValue, NamespaceInfo, NamespaceSettings, RepairResponse,
+    ScanKVsResponse, SyncState,
+};
+use crate::types::DbKey;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::runtime::{Builder, Runtime};
+
+pub struct KVClient {
+    inner: client::KVClient,
+    runtime: Runtime,
+}
+
+impl KVClientBuilder {
+    /// builds a synchronous client
+    pub fn build_blocking(self) -> Result<KVClient> {
+        KVClient::from_async(self.build()?)
+    }
+}
+
+impl KVClient {
+    /// returns a client for a single node with the default retry policy
+    pub fn new(url: &str) -> Result<Self> {
+        Self::builder().url(url).build_blocking()
+    }
+    /// returns the builder of the async client, whose `build_blocking` returns a
+    /// synchronous client
+    pub fn builder() -> KVClientBuilder {
+        KVClientBuilder::default()
+    }
+    /// wraps an async client, which must not have been used yet as its connections
+    /// are bound to the runtime that opened them
+    pub fn from_async(inner: client::KVClient) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        Ok(Self { inner, runtime })
+    }
+    /// sets the encoding used by the key-value requests, which defaults to json
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.inner = self.inner.with_format(format);
+        self
+    }
+    pub fn put_key_values(&self, namespaces: &[String], kvs: &mut [Vec<KeyValue>]) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.put_key_values(namespaces, kvs))
+    }
+    pub fn get_key_values(
+        &self,
+        namespaces: &[String],
+        keys: &mut [Vec<String>],
+    ) -> Result<GetKVsResponse> {
+        self.runtime
+            .block_on(self.inner.get_key_values(namespaces, keys))
+    }
+    pub fn delete_key_values(&self, namespaces: &[String], keys: &mut [Vec<String>]) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.delete_key_values(namespaces, keys))
+    }
+    /// writes a value under its `DbKey`, encoded as json as by the embedded database
+    pub fn put<T: DbKey + Serialize>(&self, namespace: &str, value: &T) -> Result<()> {
+        self.runtime.block_on(self.inner.put(namespace, value))
+    }
+    /// reads and decodes the value of a key, returning None if it does not exist
+    pub fn get<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> Result<Option<T>> {
+        self.runtime.block_on(self.inner.get(namespace, key))
+    }
+    pub fn delete(&self, namespace: &str, key: &str) -> Result<()> {
+        self.runtime.block_on(self.inner.delete(namespace, key))
+    }
+    /// writes and deletes the keys within the batch, leaving it empty. writes are sent
+    /// before deletes, and the two are not applied atomically
+    pub fn apply_batch(&self, batch: &mut KVBatch) -> Result<()> {
+        self.runtime.block_on(self.inner.apply_batch(batch))
+    }
+    /// lists the keys within a namespace, optionally filtered by prefix
+    pub fn scan_keys(
+        &self,
+        namespace: &str,
+        prefix: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<ScanKVsResponse> {
+        self.runtime
+            .block_on(self.inner.scan_keys(namespace, prefix, limit))
+    }
+    pub fn cluster_stats(&self) -> Result<ClusterStatistics> {
+        self.runtime.block_on(self.inner.cluster_stats())
+    }
+    pub fn health(&self) -> Result<HealthStatus> {
+        self.runtime.block_on(self.inner.health())
+    }
+    pub fn cluster_members(&self) -> Result<ClusterMembersResponse> {
+        self.runtime.block_on(self.inner.cluster_members())
+    }
+    pub fn cluster_keyspaces(&self) -> Result<ClusterKeyspacesResponse> {
+        self.runtime.block_on(self.inner.cluster_keyspaces())
+    }
+    pub fn drain(&self) -> Result<DrainStatus> {
+        self.runtime.block_on(self.inner.drain())
+    }
+    pub fn drain_status(&self) -> Result<DrainStatus> {
+        self.runtime.block_on(self.inner.drain_status())
+    }
+    pub fn repair(&self, namespace: &str) -> Result<RepairResponse> {
+        self.runtime.block_on(self.inner.repair(namespace))
+    }
+    pub fn repair_status(&self) -> Result<SyncState> {
+        self.runtime.block_on(self.inner.repair_status())
+    }
+    pub fn list_namespaces(&self) -> Result<Vec<String>> {
+        self.runtime.block_on(self.inner.list_namespaces())
+    }
+    pub fn namespace_info(&self, namespace: &str) -> Result<NamespaceInfo> {
+        self.runtime.block_on(self.inner.namespace_info(namespace))
+    }
+    pub fn drop_namespace(&self, namespace: &str) -> Result<u64> {
+        self.runtime.block_on(self.inner.drop_namespace(namespace))
+    }
+    pub fn namespace_settings(&self, namespace: &str) -> Result<NamespaceSettings> {
+        self.runtime
+            .block_on(self.inner.namespace_settings(namespace))
+    }
+    pub fn set_namespace_settings(
+        &self,
+        namespace: &str,
+        settings: &NamespaceSettings,
+    ) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.set_namespace_settings(namespace, settings))
+    }
+    /// returns the filter applied to the first node's logs
+    pub fn log_filter(&self) -> Result<String> {
+        self.runtime.block_on(self.inner.log_filter())
+    }
+    /// replaces the filter applied to the first node's logs, given as `EnvFilter`
+    /// directives such as `info,datacake=warn`
+    pub fn set_log_filter(&self, filter: &str) -> Result<()> {
+        self.runtime.block_on(self.inner.set_log_filter(filter))
+    }
+}
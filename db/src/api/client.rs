@@ -1,73 +1,346 @@
+//! an http client for the key-value server. the client is given the urls of one or
+//! more nodes, and sends each request to a healthy node. requests failing due to a
+//! connection error, timeout or transient server error are retried with exponential
+//! backoff, and the node is marked unhealthy for a cooldown so later requests are sent
+//! elsewhere. requests concerning a particular node, such as draining it, are always
+//! sent to the first url
+//!
+//! each request is sent with an `X-Request-Id`, kept across its retries and included
+//! in errors, so it can be found in the server's logs. with the `otel` feature requests
+//! made within a traced span also carry a w3c `traceparent`, continuing its trace
+
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use reqwest;
 
+use crate::api::codec::Format;
+use crate::api::trace::{self, TraceParent};
 use crate::prelude::{
-    ClusterStatistics, GetKVsRequest, GetKVsResponse, KeyValue, PutKVsRequest, Status,
-};
-use anyhow::{anyhow, Result};
-use axum::{
-    body::Body,
-    http::{self, Request, StatusCode},
+    ClusterKeyspacesResponse, ClusterMembersResponse, ClusterStatistics, DrainStatus,
+    DropNamespaceResponse, GetKVsRequest, GetKVsResponse, HealthStatus, KeyValue,
+    ListNamespacesResponse, LogFilter, NamespaceInfo, NamespaceSettings, PutKVsRequest,
+    RepairRequest, RepairResponse, ScanKVsRequest, ScanKVsResponse, Status, SyncState,
 };
+use crate::types::DbKey;
+use anyhow::{anyhow, Context, Result};
+use axum::http::{self, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub struct KVClientBuilder {
+    urls: Vec<String>,
+    format: Format,
+    policy: RetryPolicy,
+    request_timeout: Option<Duration>,
+    accept_invalid_certs: bool,
+}
+
+impl Default for KVClientBuilder {
+    fn default() -> Self {
+        Self {
+            urls: Vec::new(),
+            format: Format::Json,
+            policy: RetryPolicy::default(),
+            request_timeout: Some(Duration::from_secs(30)),
+            accept_invalid_certs: false,
+        }
+    }
+}
+
+impl KVClientBuilder {
+    /// adds the url of a node, the first url added is used for requests concerning
+    /// a particular node
+    pub fn url(mut self, url: &str) -> Self {
+        self.urls.push(url.trim_end_matches('/').to_string());
+        self
+    }
+    pub fn urls(mut self, urls: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        for url in urls {
+            self = self.url(url.as_ref());
+        }
+        self
+    }
+    /// sets the encoding used by the key-value requests, which defaults to json
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+    /// the number of times a failed request is retried, defaults to 3
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.policy.max_retries = max_retries;
+        self
+    }
+    /// the delay before the first retry, which doubles with each retry up to
+    /// `max_backoff`. defaults to 50ms
+    pub fn initial_backoff(mut self, backoff: Duration) -> Self {
+        self.policy.initial_backoff = backoff;
+        self
+    }
+    /// the maximum delay between retries, defaults to 2s
+    pub fn max_backoff(mut self, backoff: Duration) -> Self {
+        self.policy.max_backoff = backoff;
+        self
+    }
+    /// if true, the default, each delay is chosen at random between zero and the
+    /// backoff so that clients retrying at once are spread out
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.policy.jitter = jitter;
+        self
+    }
+    /// how long a node is avoided after a request to it fails, defaults to 5s
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.policy.cooldown = cooldown;
+        self
+    }
+    /// the timeout of each attempt of a request, defaults to 30s. None disables it
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+    /// if true, invalid tls certificates are accepted. defaults to false
+    pub fn accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+    pub fn build(self) -> Result<KVClient> {
+        if self.urls.is_empty() {
+            return Err(anyhow!("at least one url is required"));
+        }
+        let mut client =
+            reqwest::Client::builder().danger_accept_invalid_certs(self.accept_invalid_certs);
+        if let Some(timeout) = self.request_timeout {
+            client = client.timeout(timeout);
+        }
+        Ok(KVClient {
+            client: client.build()?,
+            endpoints: self
+                .urls
+                .into_iter()
+                .map(|url| Endpoint {
+                    url,
+                    unhealthy_until: Mutex::new(None),
+                })
+                .collect(),
+            current: AtomicUsize::new(0),
+            format: self.format,
+            policy: self.policy,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    cooldown: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            jitter: true,
+            cooldown: Duration::from_secs(5),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// the delay before the given retry, counting from zero
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(retry))
+            .min(self.max_backoff);
+        if !self.jitter {
+            return backoff;
+        }
+        // RandomState is randomly seeded, which is enough randomness for jitter
+        let random = RandomState::new().build_hasher().finish();
+        backoff.mul_f64(random as f64 / u64::MAX as f64)
+    }
+}
+
+struct Endpoint {
+    url: String,
+    /// if Some, requests avoid the endpoint until then
+    unhealthy_until: Mutex<Option<Instant>>,
+}
+
+impl Endpoint {
+    fn is_healthy(&self, now: Instant) -> bool {
+        match *self.unhealthy_until.lock().unwrap() {
+            Some(until) => until <= now,
+            None => true,
+        }
+    }
+    fn mark_healthy(&self) {
+        *self.unhealthy_until.lock().unwrap() = None;
+    }
+    fn mark_unhealthy(&self, cooldown: Duration) {
+        *self.unhealthy_until.lock().unwrap() = Some(Instant::now() + cooldown);
+    }
+}
+
+/// where a request is sent, and whether it is retried
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    /// any healthy node, retrying on failure
+    Any,
+    /// the first node, retrying on failure, as the request concerns that node
+    Node,
+    /// the first node, without retrying as the request is not idempotent, or as a
+    /// failure is its answer
+    NodeOnce,
+}
+
 pub struct KVClient {
     client: reqwest::Client,
-    url: String,
+    endpoints: Vec<Endpoint>,
+    /// the index of the endpoint which last succeeded, which is preferred
+    current: AtomicUsize,
+    /// the encoding of key-value requests and responses
+    format: Format,
+    policy: RetryPolicy,
 }
 
 impl KVClient {
+    /// returns a client for a single node with the default retry policy, which
+    /// verifies tls certificates. use the builder to accept invalid certificates
     pub fn new(url: &str) -> anyhow::Result<Self> {
-        Ok(Self {
-            client: reqwest::Client::builder()
-                .danger_accept_invalid_certs(true)
-                .build()?,
-            url: url.to_string(),
+        Self::builder().url(url).build()
+    }
+    pub fn builder() -> KVClientBuilder {
+        KVClientBuilder::default()
+    }
+    /// sets the encoding used by the key-value requests, which defaults to json
+    pub fn with_format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+    /// sends a request built by `request` from the url of a node, retrying it
+    /// according to the client's policy. the response of the last attempt is
+    /// returned even if it failed, leaving its status to the caller
+    async fn send(
+        &self,
+        target: Target,
+        request: impl Fn(&str) -> reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response> {
+        let attempts = match target {
+            Target::NodeOnce => 1,
+            Target::Any | Target::Node => self.policy.max_retries + 1,
+        };
+        let request_id = trace::generate_request_id();
+        let traceparent = TraceParent::current().map(|parent| parent.to_string());
+        let mut attempt = 0;
+        loop {
+            let idx = match target {
+                Target::Any => self.pick(),
+                Target::Node | Target::NodeOnce => 0,
+            };
+            let endpoint = &self.endpoints[idx];
+            attempt += 1;
+            let mut attempted = request(&endpoint.url).header(trace::REQUEST_ID, &request_id);
+            if let Some(traceparent) = traceparent.as_ref() {
+                attempted = attempted.header(trace::TRACEPARENT, traceparent);
+            }
+            let failure = match attempted.send().await {
+                Ok(response) if !is_transient(response.status()) => {
+                    endpoint.mark_healthy();
+                    self.current.store(idx, Ordering::Relaxed);
+                    return Ok(response);
+                }
+                Ok(response) => Ok(response),
+                Err(err) if err.is_connect() || err.is_timeout() || err.is_request() => Err(err),
+                Err(err) => return Err(anyhow!("request {} failed {:#?}", request_id, err)),
+            };
+            endpoint.mark_unhealthy(self.policy.cooldown);
+            if attempt >= attempts {
+                return failure.map_err(|err| {
+                    anyhow!(
+                        "request {} failed after {} attempts {:#?}",
+                        request_id,
+                        attempts,
+                        err
+                    )
+                });
+            }
+            let backoff = self.policy.backoff(attempt - 1);
+            log::debug!(
+                "retrying request {} to {} in {:?}",
+                request_id,
+                endpoint.url,
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+        }
+    }
+    /// returns the index of the endpoint to use, preferring the endpoint which last
+    /// succeeded. if every endpoint is unhealthy, the one recovering soonest is used
+    fn pick(&self) -> usize {
+        let now = Instant::now();
+        let start = self.current.load(Ordering::Relaxed);
+        let len = self.endpoints.len();
+        (0..len)
+            .map(|offset| (start + offset) % len)
+            .find(|idx| self.endpoints[*idx].is_healthy(now))
+            .or_else(|| {
+                (0..len).min_by_key(|idx| *self.endpoints[*idx].unhealthy_until.lock().unwrap())
+            })
+            .unwrap_or(start)
+    }
+    /// sends a key-value request encoded in the client's format
+    async fn send_encoded<T: serde::Serialize>(
+        &self,
+        path: &str,
+        request: &T,
+    ) -> Result<reqwest::Response> {
+        let body = self.format.encode(request)?;
+        self.send(Target::Any, |url| {
+            self.client
+                .post(format!("{}{}", url, path))
+                .header(http::header::CONTENT_TYPE, self.format.content_type())
+                .header(http::header::ACCEPT, self.format.content_type())
+                .body(body.clone())
         })
+        .await
+    }
+    /// decodes a key-value response encoded in the client's format
+    async fn decode<T: serde::de::DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<T> {
+        self.format.decode(&response.bytes().await?)
     }
     pub async fn put_key_values(
         &self,
         namespaces: &[String],
         kvs: &mut [Vec<KeyValue>],
     ) -> Result<()> {
-        let mut entries = HashMap::with_capacity(namespaces.len());
-        for (ns, kvs) in namespaces.into_iter().zip(kvs.iter_mut()) {
-            entries.insert(ns.clone(), std::mem::take(kvs));
-        }
-        let response: Status = self
-            .client
-            .post(format!("{}/put", self.url))
-            .header(http::header::CONTENT_TYPE, "application/json")
-            .body(Body::from(serde_json::to_vec(&PutKVsRequest { entries })?))
-            .send()
-            .await?
-            .json()
-            .await?;
-        if response.msg.eq_ignore_ascii_case("ok") {
-            return Ok(());
-        } else {
-            return Err(anyhow!("failed to send request {}", response.msg));
-        }
+        self.put_entries(by_namespace(namespaces, kvs)?).await
     }
     pub async fn get_key_values(
         &self,
         namespaces: &[String],
         keys: &mut [Vec<String>],
     ) -> Result<GetKVsResponse> {
-        let mut entries = HashMap::with_capacity(namespaces.len());
-        for (ns, kvs) in namespaces.into_iter().zip(keys.iter_mut()) {
-            entries.insert(ns.clone(), std::mem::take(kvs));
-        }
+        let entries = by_namespace(namespaces, keys)?;
         let response = self
-            .client
-            .post(format!("{}/get", self.url))
-            .header(http::header::CONTENT_TYPE, "application/json")
-            .body(Body::from(serde_json::to_vec(&GetKVsRequest { entries })?))
-            .send()
+            .send_encoded("/get", &GetKVsRequest { entries })
             .await?;
         if response.status().ne(&StatusCode::OK) {
-            return Err(anyhow!("invalid status code"));
+            return Err(status_error(&response));
         }
-        let response: GetKVsResponse = response.json().await?;
+        let response: GetKVsResponse = self.decode(response).await?;
         Ok(response)
     }
     pub async fn delete_key_values(
@@ -75,38 +348,597 @@ impl KVClient {
         namespaces: &[String],
         keys: &mut [Vec<String>],
     ) -> Result<()> {
-        let mut entries = HashMap::with_capacity(namespaces.len());
-        for (ns, kvs) in namespaces.into_iter().zip(keys.iter_mut()) {
-            entries.insert(ns.clone(), std::mem::take(kvs));
+        self.delete_entries(by_namespace(namespaces, keys)?).await
+    }
+    /// writes a value under its `DbKey`, encoded as json as by the embedded database
+    pub async fn put<T: DbKey + Serialize>(&self, namespace: &str, value: &T) -> Result<()> {
+        let mut batch = KVBatch::new();
+        batch.insert(namespace, value)?;
+        self.apply_batch(&mut batch).await
+    }
+    /// reads and decodes the value of a key, returning None if it does not exist
+    pub async fn get<T: DeserializeOwned>(&self, namespace: &str, key: &str) -> Result<Option<T>> {
+        let mut response = self
+            .get_key_values(&[namespace.to_string()], &mut [vec![key.to_string()]])
+            .await?;
+        match response
+            .entries
+            .remove(namespace)
+            .and_then(|docs| docs.into_iter().find(|doc| doc.key == key))
+        {
+            Some(doc) => Ok(Some(doc.value()?)),
+            None => Ok(None),
+        }
+    }
+    pub async fn delete(&self, namespace: &str, key: &str) -> Result<()> {
+        self.delete_entries(HashMap::from([(
+            namespace.to_string(),
+            vec![key.to_string()],
+        )]))
+        .await
+    }
+    /// writes and deletes the keys within the batch, leaving it empty. writes are sent
+    /// before deletes, and the two are not applied atomically
+    pub async fn apply_batch(&self, batch: &mut KVBatch) -> Result<()> {
+        let mut puts: HashMap<String, Vec<KeyValue>> = HashMap::new();
+        let mut deletes: HashMap<String, Vec<String>> = HashMap::new();
+        for (namespace, entries) in std::mem::take(&mut batch.entries) {
+            for (key, value) in entries {
+                match value {
+                    Some(value) => puts
+                        .entry(namespace.clone())
+                        .or_default()
+                        .push(KeyValue { key, value }),
+                    None => deletes.entry(namespace.clone()).or_default().push(key),
+                }
+            }
         }
+        if !puts.is_empty() {
+            self.put_entries(puts).await?;
+        }
+        if !deletes.is_empty() {
+            self.delete_entries(deletes).await?;
+        }
+        Ok(())
+    }
+    async fn put_entries(&self, entries: HashMap<String, Vec<KeyValue>>) -> Result<()> {
         let response = self
-            .client
-            .post(format!("{}/delete", self.url))
-            .header(http::header::CONTENT_TYPE, "application/json")
-            .body(Body::from(serde_json::to_vec(&GetKVsRequest { entries })?))
-            .send()
+            .send_encoded("/put", &PutKVsRequest { entries })
             .await?;
         if response.status().ne(&StatusCode::OK) {
-            return Err(anyhow!("invalid status code"));
+            return Err(status_error(&response));
         }
-        let response: Status = response.json().await?;
+        let response: Status = self.decode(response).await?;
         if response.msg.eq_ignore_ascii_case("ok") {
             return Ok(());
         } else {
             return Err(anyhow!("failed to send request {}", response.msg));
         }
     }
+    async fn delete_entries(&self, entries: HashMap<String, Vec<String>>) -> Result<()> {
+        let response = self
+            .send_encoded("/delete", &GetKVsRequest { entries })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: Status = self.decode(response).await?;
+        if response.msg.eq_ignore_ascii_case("ok") {
+            return Ok(());
+        } else {
+            return Err(anyhow!("failed to send request {}", response.msg));
+        }
+    }
+    /// lists the keys within a namespace, optionally filtered by prefix
+    pub async fn scan_keys(
+        &self,
+        namespace: &str,
+        prefix: Option<&str>,
+        limit: Option<usize>,
+    ) -> Result<ScanKVsResponse> {
+        let response = self
+            .send_encoded(
+                "/scan",
+                &ScanKVsRequest {
+                    namespace: namespace.to_string(),
+                    prefix: prefix.map(|prefix| prefix.to_string()),
+                    limit,
+                },
+            )
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: ScanKVsResponse = self.decode(response).await?;
+        Ok(response)
+    }
     pub async fn cluster_stats(&self) -> Result<ClusterStatistics> {
         let response = self
-            .client
-            .post(format!("{}/cluster/stats", self.url))
-            .header(http::header::CONTENT_TYPE, "application/json")
-            .send()
+            .send(Target::Node, |url| {
+                self.client
+                    .post(format!("{}/cluster/stats", url))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+            })
             .await?;
         if response.status().ne(&StatusCode::OK) {
-            return Err(anyhow!("invalid status code"));
+            return Err(status_error(&response));
         }
         let response: ClusterStatistics = response.json().await?;
         Ok(response)
     }
+    /// returns the readiness of the node, including the checks which failed if it is not
+    /// ready. a node which is not ready is reported as is rather than retried
+    pub async fn health(&self) -> Result<HealthStatus> {
+        let response = self
+            .send(Target::NodeOnce, |url| {
+                self.client.get(format!("{}/readyz", url))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK)
+            && response.status().ne(&StatusCode::SERVICE_UNAVAILABLE)
+        {
+            return Err(status_error(&response));
+        }
+        let response: HealthStatus = response.json().await?;
+        Ok(response)
+    }
+    pub async fn cluster_members(&self) -> Result<ClusterMembersResponse> {
+        let response = self
+            .send(Target::Any, |url| {
+                self.client
+                    .post(format!("{}/cluster/members", url))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: ClusterMembersResponse = response.json().await?;
+        Ok(response)
+    }
+    pub async fn cluster_keyspaces(&self) -> Result<ClusterKeyspacesResponse> {
+        let response = self
+            .send(Target::Node, |url| {
+                self.client
+                    .post(format!("{}/cluster/keyspaces", url))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: ClusterKeyspacesResponse = response.json().await?;
+        Ok(response)
+    }
+    /// starts draining the first node, see `api::drain`. the admin api is served on an
+    /// address of its own, so as with the other admin methods the first url must be the
+    /// node's admin address
+    pub async fn drain(&self) -> Result<DrainStatus> {
+        let response = self
+            .send(Target::NodeOnce, |url| {
+                self.client.post(format!("{}/cluster/drain", url))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::ACCEPTED) {
+            return Err(anyhow!(
+                "invalid status code {}: {}",
+                response.status(),
+                response.text().await?
+            ));
+        }
+        let response: DrainStatus = response.json().await?;
+        Ok(response)
+    }
+    /// returns the progress of draining the first node, whose url must be its admin address
+    pub async fn drain_status(&self) -> Result<DrainStatus> {
+        let response = self
+            .send(Target::Node, |url| {
+                self.client.get(format!("{}/cluster/drain", url))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: DrainStatus = response.json().await?;
+        Ok(response)
+    }
+    /// triggers anti-entropy repair of a namespace on the first node, see `api::repair`.
+    /// the first url must be the node's admin address
+    pub async fn repair(&self, namespace: &str) -> Result<RepairResponse> {
+        let body = serde_json::to_vec(&RepairRequest {
+            namespace: namespace.to_string(),
+        })?;
+        let response = self
+            .send(Target::Node, |url| {
+                self.client
+                    .post(format!("{}/cluster/repair", url))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+            })
+            .await?;
+        if response.status().ne(&StatusCode::ACCEPTED) {
+            return Err(status_error(&response));
+        }
+        let response: RepairResponse = response.json().await?;
+        Ok(response)
+    }
+    /// returns the state of the first node's synchronisation tasks, whose url must be its
+    /// admin address
+    pub async fn repair_status(&self) -> Result<SyncState> {
+        let response = self
+            .send(Target::Node, |url| {
+                self.client.get(format!("{}/cluster/repair", url))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: SyncState = response.json().await?;
+        Ok(response)
+    }
+    pub async fn list_namespaces(&self) -> Result<Vec<String>> {
+        let response = self
+            .send(Target::Any, |url| {
+                self.client.get(format!("{}/namespaces", url))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: ListNamespacesResponse = response.json().await?;
+        Ok(response.namespaces)
+    }
+    pub async fn namespace_info(&self, namespace: &str) -> Result<NamespaceInfo> {
+        let response = self
+            .send(Target::Any, |url| {
+                self.client
+                    .get(format!("{}/namespaces/{}", url, encode_segment(namespace)))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: NamespaceInfo = response.json().await?;
+        Ok(response)
+    }
+    /// deletes every key within the namespace across the cluster, returning the number of
+    /// keys deleted. the first url must be the admin address of a node
+    pub async fn drop_namespace(&self, namespace: &str) -> Result<u64> {
+        let response = self
+            .send(Target::Node, |url| {
+                self.client
+                    .delete(format!("{}/namespaces/{}", url, encode_segment(namespace)))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: DropNamespaceResponse = response.json().await?;
+        Ok(response.deleted)
+    }
+    pub async fn namespace_settings(&self, namespace: &str) -> Result<NamespaceSettings> {
+        let response = self
+            .send(Target::Any, |url| {
+                self.client.get(format!(
+                    "{}/namespaces/{}/settings",
+                    url,
+                    encode_segment(namespace)
+                ))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: NamespaceSettings = response.json().await?;
+        Ok(response)
+    }
+    pub async fn set_namespace_settings(
+        &self,
+        namespace: &str,
+        settings: &NamespaceSettings,
+    ) -> Result<()> {
+        let body = serde_json::to_vec(settings)?;
+        let response = self
+            .send(Target::Any, |url| {
+                self.client
+                    .put(format!(
+                        "{}/namespaces/{}/settings",
+                        url,
+                        encode_segment(namespace)
+                    ))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        Ok(())
+    }
+    /// returns the filter applied to the first node's logs. the first url must be the
+    /// node's admin address
+    pub async fn log_filter(&self) -> Result<String> {
+        let response = self
+            .send(Target::Node, |url| {
+                self.client.get(format!("{}/admin/log-filter", url))
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        let response: LogFilter = response.json().await?;
+        Ok(response.filter)
+    }
+    /// replaces the filter applied to the first node's logs, given as `EnvFilter`
+    /// directives such as `info,datacake=warn`. as with `log_filter` the first url must
+    /// be the node's admin address
+    pub async fn set_log_filter(&self, filter: &str) -> Result<()> {
+        let body = serde_json::to_vec(&LogFilter {
+            filter: filter.to_string(),
+        })?;
+        let response = self
+            .send(Target::Node, |url| {
+                self.client
+                    .put(format!("{}/admin/log-filter", url))
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(body.clone())
+            })
+            .await?;
+        if response.status().ne(&StatusCode::OK) {
+            return Err(status_error(&response));
+        }
+        Ok(())
+    }
+}
+
+/// a batch of writes and deletes grouped by namespace, applied by
+/// `KVClient::apply_batch`. when a key is written or deleted more than once, the
+/// last operation wins
+#[derive(Default, Debug)]
+pub struct KVBatch {
+    /// namespace => key => value, or None if the key is deleted
+    entries: HashMap<String, HashMap<String, Option<Vec<u8>>>>,
+}
+
+impl KVBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// writes a value under its `DbKey`, encoded as json as by the embedded database
+    pub fn insert<T: DbKey + Serialize>(&mut self, namespace: &str, value: &T) -> Result<()> {
+        let key = String::from_utf8(value.key()?).context("namespace keys must be valid utf8")?;
+        self.insert_raw(namespace, &key, serde_json::to_vec(value)?);
+        Ok(())
+    }
+    /// writes raw, untyped bytes
+    pub fn insert_raw(&mut self, namespace: &str, key: &str, value: Vec<u8>) {
+        self.entries
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_string(), Some(value));
+    }
+    /// deletes the value under its `DbKey`
+    pub fn remove<T: DbKey>(&mut self, namespace: &str, value: &T) -> Result<()> {
+        let key = String::from_utf8(value.key()?).context("namespace keys must be valid utf8")?;
+        self.remove_raw(namespace, &key);
+        Ok(())
+    }
+    pub fn remove_raw(&mut self, namespace: &str, key: &str) {
+        self.entries
+            .entry(namespace.to_string())
+            .or_default()
+            .insert(key.to_string(), None);
+    }
+    /// the number of keys written or deleted by the batch
+    pub fn len(&self) -> usize {
+        self.entries.values().map(HashMap::len).sum()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// groups the entries given for each namespace, returning an error rather than
+/// dropping entries if the number of namespaces and entries differ
+fn by_namespace<T>(
+    namespaces: &[String],
+    entries: &mut [Vec<T>],
+) -> Result<HashMap<String, Vec<T>>> {
+    if namespaces.len() != entries.len() {
+        return Err(anyhow!(
+            "got {} namespaces but entries for {}",
+            namespaces.len(),
+            entries.len()
+        ));
+    }
+    let mut grouped: HashMap<String, Vec<T>> = HashMap::with_capacity(namespaces.len());
+    for (namespace, entries) in namespaces.iter().zip(entries.iter_mut()) {
+        grouped
+            .entry(namespace.clone())
+            .or_default()
+            .append(entries);
+    }
+    Ok(grouped)
+}
+
+/// describes a response with an unexpected status, including the id of its request
+fn status_error(response: &reqwest::Response) -> anyhow::Error {
+    let request_id = response
+        .headers()
+        .get(trace::REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or("unknown");
+    anyhow!(
+        "invalid status code {} for request {}",
+        response.status(),
+        request_id
+    )
+}
+
+/// returns true if the status indicates a failure which may not recur, such as the
+/// node being overloaded or draining
+fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// percent-encodes a value used as a single path segment, such as a namespace
+/// containing `/`, `?` or `#`
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use std::sync::Arc;
+
+    fn policy(jitter: bool) -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+            jitter,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = policy(false);
+        let backoffs = (0..8)
+            .map(|retry| policy.backoff(retry))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            backoffs,
+            [50, 100, 200, 400, 800, 1600, 2000, 2000].map(Duration::from_millis)
+        );
+        // the backoff saturates rather than overflowing
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_backoff_jitter() {
+        let jittered = policy(true);
+        for retry in 0..8 {
+            let max = policy(false).backoff(retry);
+            let backoffs = (0..100)
+                .map(|_| jittered.backoff(retry))
+                .collect::<Vec<_>>();
+            assert!(backoffs.iter().all(|backoff| *backoff <= max));
+            // the delays are spread out rather than all being the same
+            assert!(backoffs.iter().any(|backoff| *backoff != backoffs[0]));
+        }
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let client = KVClient::builder()
+            .urls(["http://node-0", "http://node-1"])
+            .cooldown(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        assert_eq!(client.pick(), 0);
+        // an unhealthy node is avoided until its cooldown has elapsed
+        client.endpoints[0].mark_unhealthy(client.policy.cooldown);
+        assert_eq!(client.pick(), 1);
+        std::thread::sleep(Duration::from_millis(150));
+        assert_eq!(client.pick(), 0);
+        // once every node is unhealthy, the one recovering soonest is used
+        client.endpoints[1].mark_unhealthy(Duration::from_secs(1));
+        client.endpoints[0].mark_unhealthy(Duration::from_secs(2));
+        assert_eq!(client.pick(), 1);
+        client.endpoints[1].mark_healthy();
+        assert_eq!(client.pick(), 1);
+    }
+
+    /// serves `/get`, answering with 503 until `unavailable` requests have been served
+    async fn flaky_node(unavailable: usize) -> (String, Arc<AtomicUsize>) {
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = requests.clone();
+        let router = Router::new().route(
+            "/get",
+            post(move || {
+                let served = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if served < unavailable {
+                        return Err(StatusCode::SERVICE_UNAVAILABLE);
+                    }
+                    Ok(Json(GetKVsResponse {
+                        entries: Default::default(),
+                    }))
+                }
+            }),
+        );
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(super::super::serve(
+            listener,
+            router,
+            std::future::pending(),
+        ));
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        let namespaces = ["retry".to_string()];
+        let (url, requests) = flaky_node(2).await;
+        let client = KVClient::builder()
+            .url(&url)
+            .initial_backoff(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        client
+            .get_key_values(&namespaces, &mut [vec!["key".to_string()]])
+            .await
+            .unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // requests fail once the retries are exhausted
+        let (url, requests) = flaky_node(usize::MAX).await;
+        let client = KVClient::builder()
+            .url(&url)
+            .max_retries(2)
+            .initial_backoff(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        assert!(client
+            .get_key_values(&namespaces, &mut [vec!["key".to_string()]])
+            .await
+            .is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // a node answering with 503 is avoided by later requests during its cooldown
+        let (unavailable, unavailable_requests) = flaky_node(usize::MAX).await;
+        let (available, available_requests) = flaky_node(0).await;
+        let client = KVClient::builder()
+            .urls([unavailable, available])
+            .initial_backoff(Duration::from_millis(1))
+            .build()
+            .unwrap();
+        for _ in 0..3 {
+            client
+                .get_key_values(&namespaces, &mut [vec!["key".to_string()]])
+                .await
+                .unwrap();
+        }
+        assert_eq!(unavailable_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(available_requests.load(Ordering::SeqCst), 3);
+    }
 }
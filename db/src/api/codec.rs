@@ -0,0 +1,210 @@
+//! content negotiation for the key-value routes. request bodies are decoded according
+//! to their `Content-Type`, and responses are encoded in the first supported format
+//! listed by the `Accept` header, defaulting to json. values are sent as base64 in
+//! json, and as raw bytes in messagepack and cbor
+
+use super::error::Error;
+use axum::async_trait;
+use axum::body::{Bytes, HttpBody};
+use axum::extract::{FromRequest, FromRequestParts};
+use axum::http::header::{self, HeaderMap, HeaderValue};
+use axum::http::request::Parts;
+use axum::http::{Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use bonerjams_config::RequestLimits;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::sync::Arc;
+
+pub const JSON: &str = "application/json";
+pub const MSGPACK: &str = "application/msgpack";
+pub const CBOR: &str = "application/cbor";
+
+/// an encoding supported for request and response bodies
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+impl Format {
+    /// parses a media type, ignoring any parameters
+    pub fn from_mime(mime: &str) -> Option<Self> {
+        match mime.split(';').next().unwrap_or_default().trim() {
+            JSON => Some(Self::Json),
+            MSGPACK | "application/x-msgpack" => Some(Self::MessagePack),
+            CBOR => Some(Self::Cbor),
+            _ => None,
+        }
+    }
+    /// returns the first supported format listed by the `Accept` header
+    pub fn from_accept(headers: &HeaderMap) -> Self {
+        headers
+            .get_all(header::ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .find_map(Self::from_mime)
+            .unwrap_or_default()
+    }
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Json => JSON,
+            Self::MessagePack => MSGPACK,
+            Self::Cbor => CBOR,
+        }
+    }
+    pub fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Self::Json => serde_json::to_vec(value)?,
+            // structs are encoded as maps, as fields may be skipped when serializing
+            Self::MessagePack => rmp_serde::to_vec_named(value)?,
+            Self::Cbor => {
+                let mut buf = Vec::new();
+                ciborium::ser::into_writer(value, &mut buf)?;
+                buf
+            }
+        })
+    }
+    pub fn decode<T: DeserializeOwned>(&self, body: &[u8]) -> anyhow::Result<T> {
+        Ok(match self {
+            Self::Json => serde_json::from_slice(body)?,
+            Self::MessagePack => rmp_serde::from_slice(body)?,
+            Self::Cbor => ciborium::de::from_reader(body)?,
+        })
+    }
+}
+
+/// extracts the response format requested by the `Accept` header
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Format {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self::from_accept(&parts.headers))
+    }
+}
+
+/// a request body decoded according to its `Content-Type`, or a response body
+/// encoded in the given format
+pub struct Encoded<T>(pub Format, pub T);
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for Encoded<T>
+where
+    T: DeserializeOwned,
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let format = match req.headers().get(header::CONTENT_TYPE) {
+            Some(content_type) => content_type
+                .to_str()
+                .ok()
+                .and_then(Format::from_mime)
+                .ok_or_else(|| {
+                    Error::UnsupportedMediaType(format!(
+                        "expected a content type of {}, {} or {}",
+                        JSON, MSGPACK, CBOR
+                    ))
+                    .into_response()
+                })?,
+            None => Format::Json,
+        };
+        let limits = req.extensions().get::<Arc<RequestLimits>>().cloned();
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let decoded = match limits {
+            Some(limits) => super::limits::decode(limits, || format.decode(&body))
+                .map_err(IntoResponse::into_response)?,
+            None => format.decode(&body),
+        };
+        match decoded {
+            Ok(value) => Ok(Self(format, value)),
+            Err(err) => Err(
+                Error::CustomError(format!("failed to decode request body {}", err))
+                    .into_response(),
+            ),
+        }
+    }
+}
+
+impl<T: Serialize> IntoResponse for Encoded<T> {
+    fn into_response(self) -> Response {
+        match self.0.encode(&self.1) {
+            Ok(body) => (
+                [(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static(self.0.content_type()),
+                )],
+                body,
+            )
+                .into_response(),
+            Err(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to encode response {}", err),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// serializes bytes as base64 in human readable formats such as json, and as raw
+/// bytes otherwise. arrays of numbers are still accepted when deserializing, as
+/// written by earlier versions
+pub mod bytes {
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&base64::encode(bytes))
+        } else {
+            serializer.serialize_bytes(bytes)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        deserializer.deserialize_any(BytesVisitor)
+    }
+
+    struct BytesVisitor;
+
+    impl<'de> Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a base64 string, bytes, or an array of bytes")
+        }
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            base64::decode(value).map_err(E::custom)
+        }
+        fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Self::Value, E> {
+            Ok(value.to_vec())
+        }
+        fn visit_byte_buf<E: de::Error>(self, value: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(value)
+        }
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or_default());
+            while let Some(byte) = seq.next_element::<u8>()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+}
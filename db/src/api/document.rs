@@ -0,0 +1,123 @@
+//! the format of documents stored within datacake. a document is encoded as
+//!
+//! | field               | size         | notes                                      |
+//! |---------------------|--------------|--------------------------------------------|
+//! | version             | 1 byte       | `FORMAT_VERSION`                           |
+//! | flags               | 1 byte       | `FLAG_EXPIRES` and `FLAG_CONTENT_TYPE`     |
+//! | key length          | 4 bytes (le) |                                            |
+//! | key                 | key length   | utf8                                       |
+//! | expires at          | 8 bytes (le) | only present if `FLAG_EXPIRES` is set      |
+//! | content type length | 2 bytes (le) | only present if `FLAG_CONTENT_TYPE` is set |
+//! | content type        | its length   | utf8                                       |
+//! | data                | remainder    | the value, stored as is                    |
+//!
+//! documents written by earlier versions are json encoded `WrappedDocument`s, which
+//! are still decoded. as json documents always begin with `{` they can't be mistaken
+//! for a version byte, and they are replaced by the binary format when next written
+
+use crate::prelude::WrappedDocument;
+use anyhow::{anyhow, Context};
+
+/// the version of the binary format written by `encode`
+pub const FORMAT_VERSION: u8 = 1;
+
+/// set when the document has an expiry
+const FLAG_EXPIRES: u8 = 0b0000_0001;
+
+/// set when the document has a content type
+const FLAG_CONTENT_TYPE: u8 = 0b0000_0010;
+
+/// the size of the version, flags and key length fields
+const HEADER_SIZE: usize = 6;
+
+/// the maximum length of a content type in bytes
+pub const MAX_CONTENT_TYPE_LENGTH: usize = u16::MAX as usize;
+
+/// encodes a document in the binary format. content types longer than
+/// `MAX_CONTENT_TYPE_LENGTH` must be rejected before encoding
+pub fn encode(doc: &WrappedDocument) -> Vec<u8> {
+    let content_type = doc.content_type.as_deref().unwrap_or_default();
+    let mut buf = Vec::with_capacity(
+        HEADER_SIZE + doc.key.len() + 8 + 2 + content_type.len() + doc.data.len(),
+    );
+    let mut flags = 0;
+    if doc.expires_at.is_some() {
+        flags |= FLAG_EXPIRES;
+    }
+    if doc.content_type.is_some() {
+        flags |= FLAG_CONTENT_TYPE;
+    }
+    buf.push(FORMAT_VERSION);
+    buf.push(flags);
+    buf.extend_from_slice(&(doc.key.len() as u32).to_le_bytes());
+    buf.extend_from_slice(doc.key.as_bytes());
+    if let Some(expires_at) = doc.expires_at {
+        buf.extend_from_slice(&expires_at.to_le_bytes());
+    }
+    if doc.content_type.is_some() {
+        buf.extend_from_slice(&(content_type.len() as u16).to_le_bytes());
+        buf.extend_from_slice(content_type.as_bytes());
+    }
+    buf.extend_from_slice(&doc.data);
+    buf
+}
+
+/// decodes a document in the binary format, or the json format of earlier versions
+pub fn decode(bytes: &[u8]) -> anyhow::Result<WrappedDocument> {
+    match bytes.first() {
+        Some(&FORMAT_VERSION) => decode_v1(bytes),
+        Some(b'{') => serde_json::from_slice(bytes).context("invalid json document"),
+        Some(version) => Err(anyhow!("unsupported document version {}", version)),
+        None => Err(anyhow!("empty document")),
+    }
+}
+
+fn decode_v1(bytes: &[u8]) -> anyhow::Result<WrappedDocument> {
+    if bytes.len() < HEADER_SIZE {
+        return Err(anyhow!("document header is truncated"));
+    }
+    let flags = bytes[1];
+    if flags & !(FLAG_EXPIRES | FLAG_CONTENT_TYPE) != 0 {
+        return Err(anyhow!("unsupported document flags {:#010b}", flags));
+    }
+    let key_len = u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]) as usize;
+    let rest = &bytes[HEADER_SIZE..];
+    if rest.len() < key_len {
+        return Err(anyhow!("document key is truncated"));
+    }
+    let (key, rest) = rest.split_at(key_len);
+    let key = String::from_utf8(key.to_vec()).context("document key is not utf8")?;
+    let (expires_at, rest) = if flags & FLAG_EXPIRES != 0 {
+        if rest.len() < 8 {
+            return Err(anyhow!("document expiry is truncated"));
+        }
+        let (expires_at, rest) = rest.split_at(8);
+        let mut buf = [0_u8; 8];
+        buf.copy_from_slice(expires_at);
+        (Some(u64::from_le_bytes(buf)), rest)
+    } else {
+        (None, rest)
+    };
+    let (content_type, data) = if flags & FLAG_CONTENT_TYPE != 0 {
+        if rest.len() < 2 {
+            return Err(anyhow!("document content type is truncated"));
+        }
+        let len = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+        let rest = &rest[2..];
+        if rest.len() < len {
+            return Err(anyhow!("document content type is truncated"));
+        }
+        let (content_type, data) = rest.split_at(len);
+        let content_type = String::from_utf8(content_type.to_vec())
+            .context("document content type is not utf8")?;
+        (Some(content_type), data)
+    } else {
+        (None, rest)
+    };
+    Ok(WrappedDocument {
+        key,
+        data: data.to_vec(),
+        expires_at,
+        content_type,
+    })
+}
@@ -0,0 +1,233 @@
+//! graceful decommissioning of a node. once a drain begins the node rejects writes
+//! and waits for its peers to catch up on its documents through anti-entropy. the node
+//! is drained once every live peer reports the same checksum as the node for each of
+//! its keyspaces. documents are never rewritten, so their timestamps are kept and newer
+//! writes elsewhere in the cluster are not overwritten. the owner of the
+//! `DatacakeCluster` then leaves the cluster, see `server::run`
+
+use super::error::Error;
+use super::ApiState;
+use crate::prelude::{
+    ClusterKeyspacesResponse, DrainPhase, DrainStatus, KeyspaceProgress, MemberInfo,
+};
+use datacake::cluster::Storage;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// how often the node's keyspaces are compared with its peers'
+const SYNC_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// keyspaces every node writes for itself, such as heartbeats, which are not handed off
+const NODE_KEYSPACES: [&str; 2] = [
+    super::membership::MEMBERSHIP_KEYSPACE,
+    super::blobs::GC_KEYSPACE,
+];
+
+/// tracks the progress of draining a node
+pub struct Drain {
+    status: Mutex<DrainStatus>,
+    drained_tx: watch::Sender<bool>,
+    // held so that sending never fails due to there being no receivers
+    drained_rx: watch::Receiver<bool>,
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        let (drained_tx, drained_rx) = watch::channel(false);
+        Self {
+            status: Default::default(),
+            drained_tx,
+            drained_rx,
+        }
+    }
+}
+
+impl Drain {
+    pub fn status(&self) -> DrainStatus {
+        self.status.lock().unwrap().clone()
+    }
+    /// returns true once a drain has begun, after which writes are rejected. writes are
+    /// accepted again if the drain fails
+    pub fn is_draining(&self) -> bool {
+        matches!(
+            self.status.lock().unwrap().phase,
+            DrainPhase::Draining | DrainPhase::Drained
+        )
+    }
+    /// waits until the node has been drained
+    pub async fn drained(&self) {
+        let mut drained = self.drained_rx.clone();
+        while !*drained.borrow() {
+            if drained.changed().await.is_err() {
+                return;
+            }
+        }
+    }
+    /// marks the drain as started, returning an error if it is already in progress or done
+    fn begin(&self) -> Result<(), Error> {
+        let mut status = self.status.lock().unwrap();
+        match status.phase {
+            DrainPhase::Serving | DrainPhase::Failed => {
+                *status = DrainStatus {
+                    phase: DrainPhase::Draining,
+                    ..Default::default()
+                };
+                Ok(())
+            }
+            phase => Err(Error::CustomError(format!("node is already {:?}", phase))),
+        }
+    }
+    fn update(&self, f: impl FnOnce(&mut DrainStatus)) {
+        f(&mut self.status.lock().unwrap());
+    }
+}
+
+/// starts draining the node in the background
+pub fn start<S: Storage + Send + Sync + 'static>(handle: Arc<ApiState<S>>) -> Result<(), Error> {
+    handle.drain.begin()?;
+    tokio::spawn(async move {
+        match wait_for_peers(&handle).await {
+            Ok(()) => {
+                handle
+                    .drain
+                    .update(|status| status.phase = DrainPhase::Drained);
+                let _ = handle.drain.drained_tx.send(true);
+                log::info!("node drained");
+            }
+            Err(err) => {
+                log::error!("failed to drain node {:#?}", err);
+                handle.drain.update(|status| {
+                    status.phase = DrainPhase::Failed;
+                    status.error = Some(err.to_string());
+                });
+            }
+        }
+    });
+    Ok(())
+}
+
+/// waits for every live peer to hold the same documents as the node in each of its
+/// keyspaces, which anti-entropy brings about once they see the node's keyspaces
+/// differ from their own. keyspaces are compared through the checksums the peers
+/// report from `/cluster/keyspaces`
+async fn wait_for_peers<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+) -> anyhow::Result<()> {
+    let node_id = handle.node_id()?.to_string();
+    let client = hyper::Client::new();
+    loop {
+        let peers = live_peers(handle, &node_id).await?;
+        if peers.is_empty() {
+            return Err(anyhow::anyhow!(
+                "there are no live peers to hand the node's documents off to"
+            ));
+        }
+        let mut peer_keyspaces = Vec::with_capacity(peers.len());
+        for peer in peers {
+            match keyspace_checksums(&client, &peer).await {
+                Ok(checksums) => peer_keyspaces.push((peer.node_id, Some(checksums))),
+                Err(err) => {
+                    log::warn!(
+                        "failed to compare keyspaces with {} {:#?}",
+                        peer.node_id,
+                        err
+                    );
+                    peer_keyspaces.push((peer.node_id, None));
+                }
+            }
+        }
+        let keyspaces = super::kv_server::keyspaces(handle, true)
+            .await?
+            .into_iter()
+            .filter(|keyspace| !NODE_KEYSPACES.contains(&keyspace.name.as_str()))
+            .map(|keyspace| {
+                let (synced, pending) = peer_keyspaces.iter().fold(
+                    (Vec::new(), Vec::new()),
+                    |(mut synced, mut pending), (peer, checksums)| {
+                        // a peer without the keyspace holds none of its documents
+                        let checksum = checksums
+                            .as_ref()
+                            .map(|checksums| checksums.get(&keyspace.name).copied().unwrap_or(0));
+                        if checksum == Some(keyspace.checksum) {
+                            synced.push(peer.clone());
+                        } else {
+                            pending.push(peer.clone());
+                        }
+                        (synced, pending)
+                    },
+                );
+                KeyspaceProgress {
+                    name: keyspace.name,
+                    synced,
+                    pending,
+                }
+            })
+            .collect::<Vec<_>>();
+        let caught_up = keyspaces.iter().all(|keyspace| keyspace.pending.is_empty());
+        handle.drain.update(|status| status.keyspaces = keyspaces);
+        // synchronisation which is still running may yet change the keyspaces
+        if caught_up && handle.cluster_api.statistics().num_ongoing_sync_tasks() == 0 {
+            return Ok(());
+        }
+        tokio::time::sleep(SYNC_POLL_INTERVAL).await;
+    }
+}
+
+/// returns the live members of the cluster other than this node
+async fn live_peers<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    node_id: &str,
+) -> anyhow::Result<Vec<MemberInfo>> {
+    Ok(
+        super::namespaces::documents(handle, super::membership::MEMBERSHIP_KEYSPACE)
+            .await?
+            .into_iter()
+            .filter_map(|doc| serde_json::from_slice::<MemberInfo>(&doc.data).ok())
+            .filter(|member| member.node_id != node_id && super::membership::is_live(member))
+            .collect(),
+    )
+}
+
+/// returns the checksum of each keyspace stored by the peer
+async fn keyspace_checksums(
+    client: &hyper::Client<hyper::client::HttpConnector>,
+    peer: &MemberInfo,
+) -> anyhow::Result<HashMap<String, u64>> {
+    let address =
+        api_address(peer).ok_or_else(|| anyhow::anyhow!("{} has no api address", peer.node_id))?;
+    let request = hyper::Request::post(format!("http://{}/cluster/keyspaces?system=true", address))
+        .body(hyper::Body::empty())?;
+    let response = client.request(request).await?;
+    if !response.status().is_success() {
+        return Err(anyhow::anyhow!(
+            "{} responded with {}",
+            peer.node_id,
+            response.status()
+        ));
+    }
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    let response: ClusterKeyspacesResponse = serde_json::from_slice(&body)?;
+    Ok(response
+        .keyspaces
+        .into_iter()
+        .map(|keyspace| (keyspace.name, keyspace.checksum))
+        .collect())
+}
+
+/// returns the address of the member's http api. members listening on every interface,
+/// such as `0.0.0.0`, are reached through the host of their rpc address
+fn api_address(member: &MemberInfo) -> Option<String> {
+    let address = member.api_address.as_ref()?;
+    match (
+        address.parse::<SocketAddr>(),
+        member.rpc_address.parse::<SocketAddr>(),
+    ) {
+        (Ok(api), Ok(rpc)) if api.ip().is_unspecified() => {
+            Some(SocketAddr::new(rpc.ip(), api.port()).to_string())
+        }
+        _ => Some(address.clone()),
+    }
+}
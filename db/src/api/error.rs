@@ -8,6 +8,30 @@ pub enum Error {
     CustomError(String),
     #[error("{0}")]
     CustomServerError(String),
+    /// the node is temporarily unable to serve the request
+    #[error("{0}")]
+    Unavailable(String),
+    /// the server was built without the parts needed to serve the request
+    #[error("{0}")]
+    Unsupported(String),
+    /// the request exceeds a configured size limit
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    /// the client exceeded a rate limit
+    #[error("{0}")]
+    TooManyRequests(String),
+    /// the write would exceed a namespace's storage quota
+    #[error("{0}")]
+    QuotaExceeded(String),
+    /// the request body is in an unsupported format
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    /// the key does not exist
+    #[error("{0}")]
+    NotFound(String),
+    /// a conditional request's precondition does not hold
+    #[error("{0}")]
+    PreconditionFailed(String),
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -19,8 +43,19 @@ impl From<Error> for ApiError {
         let (status, msg) = match err {
             Error::CustomError(err_msg) => (StatusCode::BAD_REQUEST, err_msg),
             Error::CustomServerError(err_msg) => (StatusCode::BAD_REQUEST, err_msg),
+            Error::Unavailable(err_msg) => (StatusCode::SERVICE_UNAVAILABLE, err_msg),
+            Error::Unsupported(err_msg) => (StatusCode::NOT_IMPLEMENTED, err_msg),
+            Error::PayloadTooLarge(err_msg) => (StatusCode::PAYLOAD_TOO_LARGE, err_msg),
+            Error::TooManyRequests(err_msg) => (StatusCode::TOO_MANY_REQUESTS, err_msg),
+            Error::QuotaExceeded(err_msg) => (StatusCode::INSUFFICIENT_STORAGE, err_msg),
+            Error::UnsupportedMediaType(err_msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, err_msg),
+            Error::NotFound(err_msg) => (StatusCode::NOT_FOUND, err_msg),
+            Error::PreconditionFailed(err_msg) => (StatusCode::PRECONDITION_FAILED, err_msg),
         };
-        let payload = json!({ "message": msg });
+        let mut payload = json!({ "message": msg });
+        if let Some(request_id) = super::trace::current_request_id() {
+            payload["request_id"] = request_id.into();
+        }
         (status, Json(payload))
     }
 }
@@ -30,13 +65,24 @@ impl IntoResponse for Error {
         let (status, msg) = match self {
             Error::CustomError(err_msg) => (StatusCode::BAD_REQUEST, err_msg),
             Error::CustomServerError(err_msg) => (StatusCode::BAD_REQUEST, err_msg),
+            Error::Unavailable(err_msg) => (StatusCode::SERVICE_UNAVAILABLE, err_msg),
+            Error::Unsupported(err_msg) => (StatusCode::NOT_IMPLEMENTED, err_msg),
+            Error::PayloadTooLarge(err_msg) => (StatusCode::PAYLOAD_TOO_LARGE, err_msg),
+            Error::TooManyRequests(err_msg) => (StatusCode::TOO_MANY_REQUESTS, err_msg),
+            Error::QuotaExceeded(err_msg) => (StatusCode::INSUFFICIENT_STORAGE, err_msg),
+            Error::UnsupportedMediaType(err_msg) => (StatusCode::UNSUPPORTED_MEDIA_TYPE, err_msg),
+            Error::NotFound(err_msg) => (StatusCode::NOT_FOUND, err_msg),
+            Error::PreconditionFailed(err_msg) => (StatusCode::PRECONDITION_FAILED, err_msg),
         };
 
-        let body = Json(json!({
+        let mut body = json!({
             "error": msg,
-        }));
+        });
+        if let Some(request_id) = super::trace::current_request_id() {
+            body["request_id"] = request_id.into();
+        }
 
-        (status, body).into_response()
+        (status, Json(body)).into_response()
     }
 }
 
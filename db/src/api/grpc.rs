@@ -0,0 +1,252 @@
+//! a grpc api mirroring the key-value routes of the http api, defined by
+//! `proto/kv.proto`. it shares `ApiState` with the http api so limits, namespace
+//! settings and draining apply to both. the api is served alongside the http api
+//! by `new_router_with_state`, or on its own address by `serve`. either way it is
+//! wrapped in the same load shedding, timeout, tracing and metrics layers
+
+use super::error::Error;
+use super::rate_limit::ClientId;
+use super::ApiState;
+use crate::prelude::{
+    DeleteKVsRequest, Exists, ExistsKVsRequest, GetKVsRequest, KeyValue, PutKVsRequest,
+    ScanKVsRequest,
+};
+use axum::extract::ConnectInfo;
+use datacake::cluster::Storage;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status};
+
+pub mod proto {
+    tonic::include_proto!("bonerjams.kv");
+}
+
+pub use proto::key_value_client::KeyValueClient;
+use proto::key_value_server::{KeyValue as KeyValueService, KeyValueServer};
+
+/// the path prefix of the grpc methods, used as the route when applying rate limits
+const SERVICE_PATH: &str = "/bonerjams.kv.KeyValue";
+
+pub struct GrpcApi<S: Storage + Send + Sync + 'static> {
+    handle: Arc<ApiState<S>>,
+}
+
+/// returns the grpc service, backed by the given state
+pub fn new_service<S: Storage + Send + Sync + 'static>(
+    handle: Arc<ApiState<S>>,
+) -> KeyValueServer<GrpcApi<S>> {
+    KeyValueServer::new(GrpcApi { handle })
+}
+
+/// serves the grpc api on `listener` until `shutdown` completes
+pub async fn serve<S: Storage + Send + Sync + 'static>(
+    handle: Arc<ApiState<S>>,
+    api_conf: &bonerjams_config::API,
+    listener: std::net::TcpListener,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    super::serve(listener, super::new_grpc_router(handle, api_conf), shutdown).await
+}
+
+impl<S: Storage + Send + Sync + 'static> GrpcApi<S> {
+    /// identifies the client and applies the rate limits of the method
+    fn check_route<T>(&self, request: &Request<T>, method: &str) -> Result<ClientId, Status> {
+        // ConnectInfo is present as the api is served by `api::serve`
+        let addr = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| *addr)
+            .or_else(|| request.remote_addr());
+        let client = ClientId::new(addr);
+        self.handle
+            .rate_limiter
+            .check_route(&format!("{}/{}", SERVICE_PATH, method), &client)?;
+        Ok(client)
+    }
+}
+
+#[tonic::async_trait]
+impl<S: Storage + Send + Sync + 'static> KeyValueService for GrpcApi<S> {
+    type WatchStream = Pin<Box<dyn Stream<Item = Result<proto::WatchEvent, Status>> + Send>>;
+
+    async fn put(
+        &self,
+        request: Request<proto::PutRequest>,
+    ) -> Result<Response<proto::PutResponse>, Status> {
+        let client = self.check_route(&request, "Put")?;
+        let entries = request
+            .into_inner()
+            .entries
+            .into_iter()
+            .map(|(namespace, kvs)| {
+                let kvs = kvs
+                    .entries
+                    .into_iter()
+                    .map(|kv| KeyValue {
+                        key: kv.key,
+                        value: kv.value,
+                    })
+                    .collect();
+                (namespace, kvs)
+            })
+            .collect();
+        super::kv_server::put(&self.handle, &client, PutKVsRequest { entries }).await?;
+        Ok(Response::new(proto::PutResponse {
+            msg: "ok".to_string(),
+        }))
+    }
+
+    async fn get(
+        &self,
+        request: Request<proto::GetRequest>,
+    ) -> Result<Response<proto::GetResponse>, Status> {
+        let client = self.check_route(&request, "Get")?;
+        let entries = keys(request.into_inner().entries);
+        let response =
+            super::kv_server::get(&self.handle, &client, GetKVsRequest { entries }).await?;
+        let entries = response
+            .entries
+            .into_iter()
+            .map(|(namespace, docs)| {
+                let documents = docs
+                    .into_iter()
+                    .map(|doc| proto::Document {
+                        key: doc.key,
+                        data: doc.data,
+                        expires_at: doc.expires_at,
+                    })
+                    .collect();
+                (namespace, proto::Documents { documents })
+            })
+            .collect();
+        Ok(Response::new(proto::GetResponse { entries }))
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteRequest>,
+    ) -> Result<Response<proto::DeleteResponse>, Status> {
+        let client = self.check_route(&request, "Delete")?;
+        let entries = keys(request.into_inner().entries);
+        super::kv_server::delete(&self.handle, &client, DeleteKVsRequest { entries }).await?;
+        Ok(Response::new(proto::DeleteResponse {
+            msg: "ok".to_string(),
+        }))
+    }
+
+    async fn exists(
+        &self,
+        request: Request<proto::ExistsRequest>,
+    ) -> Result<Response<proto::ExistsResponse>, Status> {
+        let client = self.check_route(&request, "Exists")?;
+        let entries = keys(request.into_inner().entries);
+        let response =
+            super::kv_server::exists(&self.handle, &client, ExistsKVsRequest { entries }).await?;
+        let entries = response
+            .entries
+            .into_iter()
+            .map(|(namespace, keys)| {
+                let keys = keys
+                    .into_iter()
+                    .map(|(key, exists)| (key, matches!(exists, Exists::Found)))
+                    .collect();
+                (namespace, proto::Existence { keys })
+            })
+            .collect();
+        Ok(Response::new(proto::ExistsResponse { entries }))
+    }
+
+    async fn scan(
+        &self,
+        request: Request<proto::ScanRequest>,
+    ) -> Result<Response<proto::ScanResponse>, Status> {
+        let client = self.check_route(&request, "Scan")?;
+        let request = request.into_inner();
+        let response = super::kv_server::scan(
+            &self.handle,
+            &client,
+            ScanKVsRequest {
+                namespace: request.namespace,
+                prefix: request.prefix,
+                limit: request.limit.map(|limit| limit as usize),
+            },
+        )
+        .await?;
+        Ok(Response::new(proto::ScanResponse {
+            keys: response.keys,
+        }))
+    }
+
+    async fn watch(
+        &self,
+        request: Request<proto::WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let client = self.check_route(&request, "Watch")?;
+        let request = request.into_inner();
+        // changes are only published by the node they are made through, so a watcher
+        // would silently miss changes made through its peers
+        if !request.node_local {
+            return Err(Status::failed_precondition(
+                "watch only streams changes made through this node, set node_local to \
+                 acknowledge this",
+            ));
+        }
+        self.handle
+            .rate_limiter
+            .check_namespace(&request.namespace, &client)?;
+        let changes = BroadcastStream::new(self.handle.changes.subscribe());
+        let stream = changes.filter_map(move |change| match change {
+            Ok(change) => {
+                if change.namespace != request.namespace {
+                    return None;
+                }
+                if let Some(prefix) = request.prefix.as_ref() {
+                    if !change.key.starts_with(prefix) {
+                        return None;
+                    }
+                }
+                Some(Ok(proto::WatchEvent {
+                    namespace: change.namespace,
+                    key: change.key,
+                    value: change.value,
+                }))
+            }
+            Err(BroadcastStreamRecvError::Lagged(missed)) => Some(Err(Status::data_loss(format!(
+                "watcher fell behind and missed {} changes",
+                missed
+            )))),
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+impl From<Error> for Status {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::CustomError(msg) => Status::invalid_argument(msg),
+            Error::CustomServerError(msg) => Status::internal(msg),
+            Error::Unavailable(msg) => Status::unavailable(msg),
+            Error::PayloadTooLarge(msg) => Status::out_of_range(msg),
+            Error::TooManyRequests(msg) => Status::resource_exhausted(msg),
+            Error::QuotaExceeded(msg) => Status::resource_exhausted(msg),
+            Error::UnsupportedMediaType(msg) => Status::invalid_argument(msg),
+            Error::NotFound(msg) => Status::not_found(msg),
+            Error::PreconditionFailed(msg) => Status::failed_precondition(msg),
+            Error::Unsupported(msg) => Status::unimplemented(msg),
+        }
+    }
+}
+
+/// converts the keys of a request, grouped by namespace
+fn keys(entries: HashMap<String, proto::Keys>) -> HashMap<String, Vec<String>> {
+    entries
+        .into_iter()
+        .map(|(namespace, keys)| (namespace, keys.keys))
+        .collect()
+}
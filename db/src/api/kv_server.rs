@@ -1,119 +1,1436 @@
-use std::hash::Hasher;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
 
 use crate::api::error::Error;
-use crate::prelude::{ClusterStatistics, GetKVsResponse, Status, WrappedDocument};
-use axum::extract::State;
-use axum::http::StatusCode;
+use crate::prelude::{
+    BlobInfo, ChangeEvent, ClusterKeyspacesQuery, ClusterKeyspacesResponse, ClusterMember,
+    ClusterMembersResponse, ClusterStatistics, CreateUploadRequest, DeleteKVsRequest, DrainStatus,
+    DropNamespaceResponse, ExistKVsResponse, Exists, ExistsKVsRequest, GcResponse, GetKVsRequest,
+    GetKVsResponse, HealthCheck, HealthStatus, KeyValue, KeyspaceInfo, ListNamespacesResponse,
+    LogFilter, MemberInfo, NamespaceInfo, NamespaceSettings, PutKVsRequest, RepairRequest,
+    RepairResponse, ScanKVsRequest, ScanKVsResponse, Status, SyncState, UploadStatus,
+    WrappedDocument,
+};
+use axum::body::StreamBody;
+use axum::extract::{BodyStream, Path, Query, State};
+use axum::http::header::{self, HeaderName};
+use axum::http::{HeaderMap, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Extension;
 use axum::Json;
-use datacake::cluster::{Consistency, Storage};
+use bonerjams_config::cluster::ConsistencyLevel;
+use bonerjams_config::RequestLimits;
+use datacake::cluster::{DatacakeHandle, Storage};
+use tokio::sync::OwnedMutexGuard;
+use tokio_stream::StreamExt;
 
+use super::codec::{Encoded, Format};
+use super::document::MAX_CONTENT_TYPE_LENGTH;
 use super::error::ApiResult;
+use super::rate_limit::ClientId;
 use super::ApiState;
+
+/// the content type of values written without one
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// the number of documents read at once by a scan
+const SCAN_BATCH_SIZE: usize = 256;
+
+/// the locks of keys and uploads being written, so that checking the preconditions or
+/// the offset of a request and writing can't interleave with another request to the
+/// same key or upload. the locks are local to this node
+#[derive(Default)]
+pub struct KeyLocks {
+    locks: Mutex<HashMap<(String, String), Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl KeyLocks {
+    async fn lock(&self, namespace: &str, key: &str) -> OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.locks.lock().unwrap();
+            // locks which are neither held nor awaited are discarded
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks
+                .entry((namespace.to_string(), key.to_string()))
+                .or_default()
+                .clone()
+        };
+        lock.lock_owned().await
+    }
+}
+
+#[utoipa::path(
+    post, path = "/get", tag = "kv",
+    request_body = GetKVsRequest,
+    responses(
+        (status = 200, description = "the live documents under the keys", body = GetKVsResponse),
+        (status = 400, description = "the request is invalid"),
+        (status = 415, description = "the body is not json, messagepack or cbor"),
+        (status = 429, description = "a rate limit was exceeded"),
+    ),
+)]
 pub async fn get_value<S: Storage + Send + Sync + 'static>(
     State(handle): State<Arc<ApiState<S>>>,
-    Json(mut input): Json<crate::api::types::GetKVsRequest>,
-) -> ApiResult<(StatusCode, Json<GetKVsResponse>)> {
+    Extension(client): Extension<ClientId>,
+    format: Format,
+    Encoded(_, input): Encoded<GetKVsRequest>,
+) -> ApiResult<(StatusCode, Encoded<GetKVsResponse>)> {
+    let response = get(&handle, &client, input).await?;
+    Ok((StatusCode::OK, Encoded(format, response)))
+}
+
+#[utoipa::path(
+    post, path = "/put", tag = "kv",
+    request_body = PutKVsRequest,
+    responses(
+        (status = 200, description = "the key-values were written", body = Status),
+        (status = 400, description = "the request is invalid"),
+        (status = 413, description = "the request or a value is too large"),
+        (status = 415, description = "the body is not json, messagepack or cbor"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+        (status = 507, description = "a namespace quota would be exceeded"),
+    ),
+)]
+pub async fn put_value<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    format: Format,
+    Encoded(_, input): Encoded<PutKVsRequest>,
+) -> ApiResult<(StatusCode, Encoded<Status>)> {
+    put(&handle, &client, input).await?;
+    Ok((
+        StatusCode::OK,
+        Encoded(
+            format,
+            Status {
+                msg: "ok".to_string(),
+            },
+        ),
+    ))
+}
+
+#[utoipa::path(
+    post, path = "/delete", tag = "kv",
+    request_body = DeleteKVsRequest,
+    responses(
+        (status = 200, description = "the keys were deleted", body = Status),
+        (status = 400, description = "the request is invalid"),
+        (status = 415, description = "the body is not json, messagepack or cbor"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn remove_value<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    format: Format,
+    Encoded(_, input): Encoded<DeleteKVsRequest>,
+) -> ApiResult<(StatusCode, Encoded<Status>)> {
+    delete(&handle, &client, input).await?;
+    Ok((
+        StatusCode::OK,
+        Encoded(
+            format,
+            Status {
+                msg: "ok".to_string(),
+            },
+        ),
+    ))
+}
+
+/// lists the keys within a namespace. as documents are stored under the hash of
+/// their key, every live document in the namespace must be read to recover the keys
+#[utoipa::path(
+    post, path = "/scan", tag = "kv",
+    request_body = ScanKVsRequest,
+    responses(
+        (status = 200, description = "the matching keys", body = ScanKVsResponse),
+        (status = 400, description = "the request is invalid"),
+        (status = 415, description = "the body is not json, messagepack or cbor"),
+        (status = 429, description = "a rate limit was exceeded"),
+    ),
+)]
+pub async fn scan_keys<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    format: Format,
+    Encoded(_, input): Encoded<ScanKVsRequest>,
+) -> ApiResult<(StatusCode, Encoded<ScanKVsResponse>)> {
+    let response = scan(&handle, &client, input).await?;
+    Ok((StatusCode::OK, Encoded(format, response)))
+}
+
+/// reads a single key, returning its value as is with the content type it was written
+/// with. requests with `If-None-Match` are answered with 304 if the value is unchanged
+#[utoipa::path(
+    get, path = "/kv/{namespace}/{key}", tag = "kv",
+    params(
+        ("namespace" = String, Path, description = "the name of the namespace"),
+        ("key" = String, Path, description = "the key, percent-encoded"),
+        ("If-None-Match" = Option<String>, Header, description = "entity tags to compare"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "the value as written, also served for HEAD requests",
+            body = String,
+            content_type = "application/octet-stream",
+            headers(("ETag" = String, description = "the entity tag of the value")),
+        ),
+        (status = 304, description = "the value matches `If-None-Match`"),
+        (status = 404, description = "the key does not exist"),
+        (status = 429, description = "a rate limit was exceeded"),
+    ),
+)]
+pub async fn get_key<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path((namespace, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    let doc = match read_key(&handle, &client, &namespace, &key).await? {
+        Some(doc) => doc,
+        None => {
+            return Err(Error::NotFound(format!("{} does not exist in {}", key, namespace)).into())
+        }
+    };
+    let etag = etag(&doc.data, doc.content_type.as_deref());
+    if let Some(tags) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(tags, &etag) {
+            return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+        }
+    }
+    let content_type = doc
+        .content_type
+        .unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string());
+    Ok((
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, content_type), (header::ETAG, etag)],
+        doc.data,
+    )
+        .into_response())
+}
+
+/// writes a single key, streaming the value from the body. the `Content-Type` of the
+/// request is returned when the key is read. preconditions are checked while holding
+/// the key's lock, so concurrent conditional writes through this node can't both succeed
+#[utoipa::path(
+    put, path = "/kv/{namespace}/{key}", tag = "kv",
+    params(
+        ("namespace" = String, Path, description = "the name of the namespace"),
+        ("key" = String, Path, description = "the key, percent-encoded"),
+        ("If-Match" = Option<String>, Header, description = "entity tags the value must match"),
+        ("If-None-Match" = Option<String>, Header, description = "`*` to only create the key"),
+    ),
+    request_body(
+        content = String,
+        content_type = "application/octet-stream",
+        description = "the value, stored as is along with its content type",
+    ),
+    responses(
+        (
+            status = 204,
+            description = "the value was written",
+            headers(("ETag" = String, description = "the entity tag of the value")),
+        ),
+        (status = 400, description = "the request is invalid"),
+        (status = 412, description = "a precondition failed"),
+        (status = 413, description = "the value is too large"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+        (status = 507, description = "a namespace quota would be exceeded"),
+    ),
+)]
+pub async fn put_key<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path((namespace, key)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> ApiResult<Response> {
+    reject_if_draining(&handle)?;
+    super::limits::check_keys(&handle.limits, std::iter::once(&key))?;
+    super::namespaces::check_name(&namespace)?;
+    handle.rate_limiter.check_namespace(&namespace, &client)?;
+    let content_type = content_type(&headers)?;
+    let value = read_value(&handle.limits, &key, body).await?;
+    let _lock = handle.key_locks.lock(&namespace, &key).await;
+    check_preconditions(&handle, &client, &namespace, &key, &headers).await?;
+    // a write without a content type keeps the one the key was written with
+    let content_type = match content_type {
+        Some(content_type) => Some(content_type),
+        None => stored_content_type(&handle, &namespace, &key).await?,
+    };
+    let etag = etag(&value, content_type.as_deref());
+    handle.metrics.observe_batch("put", 1);
+    let mut key_values = [KeyValue { key, value }];
+    write(&handle, &namespace, &mut key_values, content_type).await?;
+    Ok((StatusCode::NO_CONTENT, [(header::ETAG, etag)]).into_response())
+}
+
+/// deletes a single key, which succeeds whether or not the key exists. preconditions
+/// are checked while holding the key's lock, as with `put_key`
+#[utoipa::path(
+    delete, path = "/kv/{namespace}/{key}", tag = "kv",
+    params(
+        ("namespace" = String, Path, description = "the name of the namespace"),
+        ("key" = String, Path, description = "the key, percent-encoded"),
+        ("If-Match" = Option<String>, Header, description = "entity tags the value must match"),
+    ),
+    responses(
+        (status = 204, description = "the key was deleted"),
+        (status = 400, description = "the request is invalid"),
+        (status = 412, description = "a precondition failed"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn delete_key<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path((namespace, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> ApiResult<StatusCode> {
+    reject_if_draining(&handle)?;
+    let _lock = handle.key_locks.lock(&namespace, &key).await;
+    check_preconditions(&handle, &client, &namespace, &key, &headers).await?;
+    delete(
+        &handle,
+        &client,
+        DeleteKVsRequest {
+            entries: HashMap::from([(namespace, vec![key])]),
+        },
+    )
+    .await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// writes a blob, streaming the body into chunks as it is received. as the request is
+/// subject to the api's timeout, large blobs should be written with an upload, which
+/// may be resumed if interrupted
+#[utoipa::path(
+    put, path = "/blobs/{namespace}/{key}", tag = "blobs",
+    params(
+        ("namespace" = String, Path, description = "the name of the namespace"),
+        ("key" = String, Path, description = "the key, percent-encoded"),
+    ),
+    request_body(
+        content = String,
+        content_type = "application/octet-stream",
+        description = "the blob, stored along with its content type",
+    ),
+    responses(
+        (status = 201, description = "the blob was written", body = BlobInfo),
+        (status = 400, description = "the request is invalid"),
+        (status = 413, description = "the blob exceeds max_blob_size"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn put_blob<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path((namespace, key)): Path<(String, String)>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> ApiResult<(StatusCode, Json<BlobInfo>)> {
+    reject_if_draining(&handle)?;
+    handle.rate_limiter.check_namespace(&namespace, &client)?;
+    let request = CreateUploadRequest {
+        namespace,
+        key,
+        content_type: content_type(&headers)?,
+    };
+    let upload = super::blobs::create_upload(&handle, request).await?;
+    let upload = super::blobs::append(&handle, upload, 0, body).await?;
+    let blob = super::blobs::complete(&handle, upload).await?;
+    Ok((StatusCode::CREATED, Json(blob)))
+}
+
+/// streams a blob with the content type it was written with
+#[utoipa::path(
+    get, path = "/blobs/{namespace}/{key}", tag = "blobs",
+    params(
+        ("namespace" = String, Path, description = "the name of the namespace"),
+        ("key" = String, Path, description = "the key, percent-encoded"),
+        ("If-None-Match" = Option<String>, Header, description = "entity tags to compare"),
+    ),
+    responses(
+        (
+            status = 200,
+            description = "the blob as written, also served for HEAD requests",
+            body = String,
+            content_type = "application/octet-stream",
+            headers(("ETag" = String, description = "the entity tag of the blob")),
+        ),
+        (status = 304, description = "the blob matches `If-None-Match`"),
+        (status = 404, description = "the blob does not exist"),
+        (status = 429, description = "a rate limit was exceeded"),
+    ),
+)]
+pub async fn get_blob<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path((namespace, key)): Path<(String, String)>,
+    method: Method,
+    headers: HeaderMap,
+) -> ApiResult<Response> {
+    handle.rate_limiter.check_namespace(&namespace, &client)?;
+    let blob = match super::blobs::info(&handle, &namespace, &key).await? {
+        Some(blob) => blob,
+        None => {
+            return Err(
+                Error::NotFound(format!("blob {} does not exist in {}", key, namespace)).into(),
+            )
+        }
+    };
+    // chunks are content addressed, so the digests identify the contents of the blob
+    let digests = blob
+        .chunks
+        .iter()
+        .map(|chunk| chunk.digest.as_str())
+        .collect::<String>();
+    let etag = etag(digests.as_bytes(), blob.content_type.as_deref());
+    if let Some(tags) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(tags, &etag) {
+            return Ok((StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response());
+        }
+    }
+    let headers = [
+        (
+            header::CONTENT_TYPE,
+            blob.content_type
+                .clone()
+                .unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string()),
+        ),
+        (header::CONTENT_LENGTH, blob.size.to_string()),
+        (header::ETAG, etag),
+    ];
+    if method == Method::HEAD {
+        return Ok((StatusCode::OK, headers).into_response());
+    }
+    let body = StreamBody::new(super::blobs::read(handle.clone(), blob));
+    Ok((StatusCode::OK, headers, body).into_response())
+}
+
+/// deletes a blob, which succeeds whether or not the blob exists
+#[utoipa::path(
+    delete, path = "/blobs/{namespace}/{key}", tag = "blobs",
+    params(
+        ("namespace" = String, Path, description = "the name of the namespace"),
+        ("key" = String, Path, description = "the key, percent-encoded"),
+    ),
+    responses(
+        (status = 204, description = "the blob was deleted"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn delete_blob<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path((namespace, key)): Path<(String, String)>,
+) -> ApiResult<StatusCode> {
+    reject_if_draining(&handle)?;
+    handle.rate_limiter.check_namespace(&namespace, &client)?;
+    super::blobs::delete(&handle, &namespace, &key).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// starts a resumable upload of a blob
+#[utoipa::path(
+    post, path = "/uploads", tag = "blobs",
+    request_body = CreateUploadRequest,
+    responses(
+        (status = 201, description = "the upload was started", body = UploadStatus),
+        (status = 400, description = "the request is invalid"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn create_upload<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Json(request): Json<CreateUploadRequest>,
+) -> ApiResult<(StatusCode, Json<UploadStatus>)> {
+    reject_if_draining(&handle)?;
+    handle
+        .rate_limiter
+        .check_namespace(&request.namespace, &client)?;
+    let upload = super::blobs::create_upload(&handle, request).await?;
+    Ok((StatusCode::CREATED, Json(upload)))
+}
+
+/// returns the progress of an upload, giving the offset to resume it from
+#[utoipa::path(
+    get, path = "/uploads/{upload_id}", tag = "blobs",
+    params(("upload_id" = String, Path, description = "the id of the upload")),
+    responses(
+        (status = 200, description = "the upload", body = UploadStatus),
+        (status = 404, description = "the upload does not exist"),
+        (status = 429, description = "a rate limit was exceeded"),
+    ),
+)]
+pub async fn get_upload<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path(upload_id): Path<String>,
+) -> ApiResult<(StatusCode, Json<UploadStatus>)> {
+    let upload = find_upload(&handle, &client, &upload_id).await?;
+    Ok((StatusCode::OK, Json(upload)))
+}
+
+/// appends the body to an upload, beginning at the offset given by `Upload-Offset`.
+/// if the request is interrupted, the upload may be resumed from its current offset
+#[utoipa::path(
+    patch, path = "/uploads/{upload_id}", tag = "blobs",
+    params(
+        ("upload_id" = String, Path, description = "the id of the upload"),
+        ("Upload-Offset" = u64, Header, description = "the offset the body begins at"),
+    ),
+    request_body(
+        content = String,
+        content_type = "application/octet-stream",
+        description = "the next part of the blob",
+    ),
+    responses(
+        (status = 200, description = "the body was appended", body = UploadStatus),
+        (status = 400, description = "the request is invalid"),
+        (status = 404, description = "the upload does not exist"),
+        (status = 412, description = "the offset is not the upload's current offset"),
+        (status = 413, description = "the blob exceeds max_blob_size"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn append_upload<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path(upload_id): Path<String>,
+    headers: HeaderMap,
+    body: BodyStream,
+) -> ApiResult<(StatusCode, Json<UploadStatus>)> {
+    reject_if_draining(&handle)?;
+    let offset = headers
+        .get(super::blobs::UPLOAD_OFFSET)
+        .and_then(|offset| offset.to_str().ok())
+        .and_then(|offset| offset.parse::<u64>().ok())
+        .ok_or_else(|| Error::CustomError("expected an Upload-Offset header".to_string()))?;
+    // the upload is read under its lock, so that its offset can't change before the
+    // append is checked against it
+    let _lock = handle
+        .key_locks
+        .lock(super::blobs::UPLOADS_KEYSPACE, &upload_id)
+        .await;
+    let upload = find_upload(&handle, &client, &upload_id).await?;
+    let upload = super::blobs::append(&handle, upload, offset, body).await?;
+    Ok((StatusCode::OK, Json(upload)))
+}
+
+/// completes an upload, writing the blob
+#[utoipa::path(
+    post, path = "/uploads/{upload_id}/complete", tag = "blobs",
+    params(("upload_id" = String, Path, description = "the id of the upload")),
+    responses(
+        (status = 201, description = "the blob was written", body = BlobInfo),
+        (status = 404, description = "the upload does not exist"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn complete_upload<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path(upload_id): Path<String>,
+) -> ApiResult<(StatusCode, Json<BlobInfo>)> {
+    reject_if_draining(&handle)?;
+    let _lock = handle
+        .key_locks
+        .lock(super::blobs::UPLOADS_KEYSPACE, &upload_id)
+        .await;
+    let upload = find_upload(&handle, &client, &upload_id).await?;
+    let blob = super::blobs::complete(&handle, upload).await?;
+    Ok((StatusCode::CREATED, Json(blob)))
+}
+
+/// abandons an upload
+#[utoipa::path(
+    delete, path = "/uploads/{upload_id}", tag = "blobs",
+    params(("upload_id" = String, Path, description = "the id of the upload")),
+    responses(
+        (status = 204, description = "the upload was removed"),
+        (status = 404, description = "the upload does not exist"),
+        (status = 429, description = "a rate limit was exceeded"),
+        (status = 503, description = "the node is draining"),
+    ),
+)]
+pub async fn abort_upload<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Extension(client): Extension<ClientId>,
+    Path(upload_id): Path<String>,
+) -> ApiResult<StatusCode> {
+    reject_if_draining(&handle)?;
+    find_upload(&handle, &client, &upload_id).await?;
+    super::blobs::abort_upload(&handle, &upload_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// removes idle uploads and deletes orphaned blob chunks stored by this node, served by
+/// the admin router. chunks are only deleted once every member of the cluster has found
+/// them orphaned. fails with 503 while the node is draining
+pub async fn collect_garbage<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> ApiResult<(StatusCode, Json<GcResponse>)> {
+    reject_if_draining(&handle)?;
+    let response = super::blobs::collect(&handle).await?;
+    Ok((StatusCode::OK, Json(response)))
+}
+
+/// reads a single live, unexpired document
+async fn read_key<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    namespace: &str,
+    key: &str,
+) -> super::error::Result<Option<WrappedDocument>> {
+    let mut response = get(
+        handle,
+        client,
+        GetKVsRequest {
+            entries: HashMap::from([(namespace.to_string(), vec![key.to_string()])]),
+        },
+    )
+    .await?;
+    Ok(response
+        .entries
+        .remove(namespace)
+        .and_then(|docs| docs.into_iter().find(|doc| doc.key == key)))
+}
+
+/// returns the `Content-Type` of a request, if it has one
+fn content_type(headers: &HeaderMap) -> super::error::Result<Option<String>> {
+    match headers.get(header::CONTENT_TYPE) {
+        Some(content_type) => match content_type.to_str() {
+            Ok(content_type) if content_type.len() <= MAX_CONTENT_TYPE_LENGTH => {
+                Ok(Some(content_type.to_string()))
+            }
+            _ => Err(Error::CustomError("invalid content type".to_string())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// reads a value from the body as it is received, failing once it exceeds
+/// `max_value_size` rather than after reading the whole body
+async fn read_value(
+    limits: &RequestLimits,
+    key: &str,
+    mut body: BodyStream,
+) -> super::error::Result<Vec<u8>> {
+    let mut value = Vec::new();
+    while let Some(bytes) = body.next().await {
+        let bytes =
+            bytes.map_err(|err| Error::CustomError(format!("failed to read body {}", err)))?;
+        value.extend_from_slice(&bytes);
+        super::limits::check_value(limits, key, &value)?;
+    }
+    Ok(value)
+}
+
+/// returns the content type of the live document stored under `key`, if it has one
+async fn stored_content_type<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    key: &str,
+) -> super::error::Result<Option<String>> {
+    Ok(handle
+        .cluster_api
+        .get_many(namespace, std::iter::once(super::hash_key(key)))
+        .await
+        .map_err(|err| Error::CustomServerError(err.to_string()))?
+        .filter_map(|doc| super::document::decode(&doc.data[..]).ok())
+        .find(|doc| doc.key == key && !doc.is_expired())
+        .and_then(|doc| doc.content_type))
+}
+
+/// returns an upload, applying the rate limits of its namespace
+async fn find_upload<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    upload_id: &str,
+) -> super::error::Result<UploadStatus> {
+    let upload = super::blobs::upload(handle, upload_id)
+        .await?
+        .ok_or_else(|| Error::NotFound(format!("upload {} does not exist", upload_id)))?;
+    handle
+        .rate_limiter
+        .check_namespace(&upload.namespace, client)?;
+    Ok(upload)
+}
+
+/// checks `If-Match` and `If-None-Match` against the current value of the key
+async fn check_preconditions<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    namespace: &str,
+    key: &str,
+    headers: &HeaderMap,
+) -> super::error::Result<()> {
+    let if_match = headers.get(header::IF_MATCH);
+    let if_none_match = headers.get(header::IF_NONE_MATCH);
+    if if_match.is_none() && if_none_match.is_none() {
+        return Ok(());
+    }
+    let current = read_key(handle, client, namespace, key)
+        .await?
+        .map(|doc| etag(&doc.data, doc.content_type.as_deref()));
+    if let Some(tags) = if_match {
+        if !matches!(current.as_ref(), Some(etag) if etag_matches(tags, etag)) {
+            return Err(Error::PreconditionFailed(
+                "If-Match does not match the current value".to_string(),
+            ));
+        }
+    }
+    if let Some(tags) = if_none_match {
+        if matches!(current.as_ref(), Some(etag) if etag_matches(tags, etag)) {
+            return Err(Error::PreconditionFailed(
+                "If-None-Match matches the current value".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// the strong entity tag of a value, derived from its data and content type
+fn etag(data: &[u8], content_type: Option<&str>) -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    data.hash(&mut hasher);
+    content_type.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// returns true if the list of entity tags contains `etag` or is `*`. weak tags are
+/// compared as if they were strong, as every tag we issue is strong
+fn etag_matches(tags: &HeaderValue, etag: &str) -> bool {
+    let tags = match tags.to_str() {
+        Ok(tags) => tags,
+        Err(_) => return false,
+    };
+    tags.split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+/// reads the live, unexpired documents stored under the requested keys. shared by
+/// the http and grpc apis
+pub(crate) async fn get<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    mut input: GetKVsRequest,
+) -> super::error::Result<GetKVsResponse> {
+    super::limits::check_keys(&handle.limits, input.entries.values().flatten())?;
     let mut response = GetKVsResponse {
         entries: Default::default(),
     };
     for (namespace, keys) in input.entries.iter_mut() {
-        let hashed_keys = keys.iter_mut().map(|key| {
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            hasher.write(key.as_bytes());
-            hasher.finish()
-        });
+        handle.metrics.observe_batch("get", keys.len());
+        handle.rate_limiter.check_namespace(namespace, client)?;
+        let hashed_keys = keys.iter().map(|key| super::hash_key(key));
         match handle.cluster_api.get_many(namespace, hashed_keys).await {
             Ok(res) => {
                 response.entries.insert(
                     namespace.clone(),
                     res.filter_map(|doc| {
-                        let wrapped_document: WrappedDocument =
-                            match serde_json::from_slice(&doc.data[..]) {
-                                Ok(doc) => doc,
-                                Err(_) => return None,
-                            };
+                        let wrapped_document = match super::document::decode(&doc.data[..]) {
+                            Ok(doc) => doc,
+                            Err(_) => return None,
+                        };
+                        if wrapped_document.is_expired() {
+                            return None;
+                        }
                         Some(wrapped_document)
                     })
                     .collect::<Vec<_>>(),
                 );
             }
             Err(err) => {
-                return Err(Error::CustomServerError(err.to_string()).into());
+                return Err(Error::CustomServerError(err.to_string()));
             }
         }
     }
-    Ok((StatusCode::OK, Json(response)))
+    Ok(response)
 }
 
-pub async fn put_value<S: Storage + Send + Sync + 'static>(
-    State(handle): State<Arc<ApiState<S>>>,
-    Json(mut input): Json<crate::api::types::PutKVsRequest>,
-) -> ApiResult<(StatusCode, Json<Status>)> {
+/// reports which of the requested keys hold a live, unexpired document
+pub(crate) async fn exists<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    input: ExistsKVsRequest,
+) -> super::error::Result<ExistKVsResponse> {
+    let found = get(
+        handle,
+        client,
+        GetKVsRequest {
+            entries: input.entries.clone(),
+        },
+    )
+    .await?;
+    let entries = input
+        .entries
+        .into_iter()
+        .map(|(namespace, keys)| {
+            let docs = found.entries.get(&namespace);
+            let keys = keys
+                .into_iter()
+                .map(|key| {
+                    let exists = docs
+                        .map(|docs| docs.iter().any(|doc| doc.key == key))
+                        .unwrap_or_default();
+                    let exists = if exists {
+                        Exists::Found
+                    } else {
+                        Exists::NotFound
+                    };
+                    (key, exists)
+                })
+                .collect();
+            (namespace, keys)
+        })
+        .collect();
+    Ok(ExistKVsResponse { entries })
+}
+
+pub(crate) async fn put<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    mut input: PutKVsRequest,
+) -> super::error::Result<()> {
+    reject_if_draining(handle)?;
+    super::limits::check_keys(
+        &handle.limits,
+        input.entries.values().flatten().map(|kv| &kv.key),
+    )?;
+    for kv in input.entries.values().flatten() {
+        super::limits::check_value(&handle.limits, &kv.key, &kv.value)?;
+    }
     for (namespace, key_values) in input.entries.iter_mut() {
-        let key_values = key_values.iter_mut().filter_map(|kv| {
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            hasher.write((&kv.key).as_bytes());
-            let wrapped_document = match serde_json::to_vec(&WrappedDocument {
-                key: std::mem::take(&mut kv.key),
-                data: std::mem::take(&mut kv.value),
-            }) {
-                Ok(doc) => doc,
-                Err(err) => {
-                    log::error!("failed to serialize document {:#?}", err);
-                    return None;
-                }
-            };
-            Some((hasher.finish(), wrapped_document))
+        handle.metrics.observe_batch("put", key_values.len());
+        super::namespaces::check_name(namespace)?;
+        handle.rate_limiter.check_namespace(namespace, client)?;
+        write(handle, namespace, key_values, None).await?;
+    }
+    Ok(())
+}
+
+/// applies the namespace's settings to the key-values and writes them, giving each
+/// document `content_type`. the request limits, namespace name and rate limits must
+/// already have been checked
+async fn write<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    key_values: &mut [KeyValue],
+    content_type: Option<String>,
+) -> super::error::Result<()> {
+    let settings = match super::namespaces::settings(handle, namespace).await {
+        Ok(settings) => settings,
+        Err(err) => {
+            return Err(Error::CustomServerError(err.to_string()));
+        }
+    };
+    for kv in key_values.iter() {
+        super::namespaces::check_value(&settings, kv)?;
+    }
+    let expires_at = match settings.ttl {
+        Some(ttl) => Some(
+            super::namespaces::expires_at(ttl)
+                .ok_or_else(|| Error::CustomError(format!("ttl of {} seconds is too long", ttl)))?,
+        ),
+        None => None,
+    };
+    super::namespaces::check_quota(handle, namespace, &settings, key_values).await?;
+    // values are only copied when a watcher will receive them
+    let changes = if handle.changes.receiver_count() > 0 {
+        key_values
+            .iter()
+            .map(|kv| ChangeEvent {
+                namespace: namespace.to_string(),
+                key: kv.key.clone(),
+                value: Some(kv.value.clone()),
+            })
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let key_values = key_values.iter_mut().map(|kv| {
+        let doc_id = super::hash_key(&kv.key);
+        let wrapped_document = super::document::encode(&WrappedDocument {
+            content_type: content_type.clone(),
+            key: std::mem::take(&mut kv.key),
+            data: std::mem::take(&mut kv.value),
+            expires_at,
         });
+        (doc_id, wrapped_document)
+    });
+    if let Err(err) = handle
+        .cluster_api
+        .put_many(
+            namespace,
+            key_values,
+            super::namespaces::consistency(handle, &settings),
+        )
+        .await
+    {
+        handle.namespace_usage.invalidate(namespace);
+        return Err(Error::CustomServerError(err.to_string()));
+    }
+    publish_changes(handle, changes);
+    Ok(())
+}
+
+pub(crate) async fn delete<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    mut input: DeleteKVsRequest,
+) -> super::error::Result<()> {
+    reject_if_draining(handle)?;
+    super::limits::check_keys(&handle.limits, input.entries.values().flatten())?;
+    for (namespace, keys) in input.entries.iter_mut() {
+        handle.metrics.observe_batch("delete", keys.len());
+        handle.rate_limiter.check_namespace(namespace, client)?;
+        let settings = match super::namespaces::settings(handle, namespace).await {
+            Ok(settings) => settings,
+            Err(err) => {
+                return Err(Error::CustomServerError(err.to_string()));
+            }
+        };
+        super::namespaces::record_delete(handle, namespace, keys).await?;
+        let hashed_keys = keys.iter().map(|key| super::hash_key(key));
         if let Err(err) = handle
             .cluster_api
-            .put_many(namespace, key_values, Consistency::EachQuorum)
+            .del_many(
+                namespace,
+                hashed_keys,
+                super::namespaces::consistency(handle, &settings),
+            )
             .await
         {
-            return Err(Error::CustomServerError(err.to_string()).into());
+            handle.namespace_usage.invalidate(namespace);
+            return Err(Error::CustomServerError(err.to_string()));
+        }
+        publish_changes(
+            handle,
+            keys.iter()
+                .map(|key| ChangeEvent {
+                    namespace: namespace.clone(),
+                    key: key.clone(),
+                    value: None,
+                })
+                .collect(),
+        );
+    }
+    Ok(())
+}
+
+pub(crate) async fn scan<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    client: &ClientId,
+    input: ScanKVsRequest,
+) -> super::error::Result<ScanKVsResponse> {
+    handle
+        .rate_limiter
+        .check_namespace(&input.namespace, client)?;
+    let doc_ids = match handle.storage()?.iter_metadata(&input.namespace).await {
+        Ok(metadata) => metadata
+            .filter_map(|(doc_id, _, is_tombstone)| if is_tombstone { None } else { Some(doc_id) })
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            return Err(Error::CustomServerError(err.to_string()));
         }
+    };
+    let mut keys = Vec::new();
+    // documents are stored by the hash of their key, so every document is read to find
+    // the first keys even if the scan is limited
+    for batch in doc_ids.chunks(SCAN_BATCH_SIZE) {
+        let docs = match handle
+            .cluster_api
+            .get_many(&input.namespace, batch.iter().copied())
+            .await
+        {
+            Ok(docs) => docs,
+            Err(err) => {
+                return Err(Error::CustomServerError(err.to_string()));
+            }
+        };
+        keys.extend(
+            docs.filter_map(|doc| {
+                let wrapped_document = super::document::decode(&doc.data[..]).ok()?;
+                if wrapped_document.is_expired() {
+                    return None;
+                }
+                Some(wrapped_document.key)
+            })
+            .filter(|key| match input.prefix.as_ref() {
+                Some(prefix) => key.starts_with(prefix),
+                None => true,
+            }),
+        );
+    }
+    keys.sort();
+    keys.truncate(input.limit.unwrap_or(usize::MAX));
+    Ok(ScanKVsResponse { keys })
+}
+
+/// sends changes made through this node to any watchers
+fn publish_changes<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    changes: Vec<ChangeEvent>,
+) {
+    for change in changes {
+        // sending only fails when there are no watchers
+        let _ = handle.changes.send(change);
+    }
+}
+
+/// lists every namespace which holds keys or has settings
+#[utoipa::path(
+    get, path = "/namespaces", tag = "namespaces",
+    responses((status = 200, description = "the namespaces", body = ListNamespacesResponse)),
+)]
+pub async fn list_namespaces<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> ApiResult<(StatusCode, Json<ListNamespacesResponse>)> {
+    match super::namespaces::list(&handle).await {
+        Ok(namespaces) => Ok((StatusCode::OK, Json(ListNamespacesResponse { namespaces }))),
+        Err(err) => Err(Error::CustomServerError(err.to_string()).into()),
+    }
+}
+
+/// returns the key count, size and settings of a namespace
+#[utoipa::path(
+    get, path = "/namespaces/{namespace}", tag = "namespaces",
+    params(("namespace" = String, Path, description = "the name of the namespace")),
+    responses(
+        (status = 200, description = "the namespace", body = NamespaceInfo),
+        (status = 400, description = "the namespace is reserved"),
+    ),
+)]
+pub async fn namespace_info<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Path(namespace): Path<String>,
+) -> ApiResult<(StatusCode, Json<NamespaceInfo>)> {
+    super::namespaces::check_name(&namespace)?;
+    match super::namespaces::info(&handle, &namespace).await {
+        Ok(info) => Ok((StatusCode::OK, Json(info))),
+        Err(err) => Err(Error::CustomServerError(err.to_string()).into()),
+    }
+}
+
+/// deletes every key within a namespace across the cluster, along with its settings,
+/// served by the admin router. fails with 400 if the namespace is reserved, and with 503
+/// while the node is draining
+pub async fn drop_namespace<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Path(namespace): Path<String>,
+) -> ApiResult<(StatusCode, Json<DropNamespaceResponse>)> {
+    reject_if_draining(&handle)?;
+    super::namespaces::check_name(&namespace)?;
+    match super::namespaces::drop(&handle, &namespace).await {
+        Ok(deleted) => Ok((
+            StatusCode::OK,
+            Json(DropNamespaceResponse {
+                name: namespace,
+                deleted,
+            }),
+        )),
+        Err(err) => Err(Error::CustomServerError(err.to_string()).into()),
+    }
+}
+
+#[utoipa::path(
+    get, path = "/namespaces/{namespace}/settings", tag = "namespaces",
+    params(("namespace" = String, Path, description = "the name of the namespace")),
+    responses(
+        (status = 200, description = "the settings of the namespace", body = NamespaceSettings),
+        (status = 400, description = "the namespace is reserved"),
+    ),
+)]
+pub async fn get_namespace_settings<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Path(namespace): Path<String>,
+) -> ApiResult<(StatusCode, Json<NamespaceSettings>)> {
+    super::namespaces::check_name(&namespace)?;
+    match super::namespaces::settings(&handle, &namespace).await {
+        Ok(settings) => Ok((StatusCode::OK, Json(settings))),
+        Err(err) => Err(Error::CustomServerError(err.to_string()).into()),
+    }
+}
+
+/// replaces the settings of a namespace. settings only apply to subsequent writes
+#[utoipa::path(
+    put, path = "/namespaces/{namespace}/settings", tag = "namespaces",
+    params(("namespace" = String, Path, description = "the name of the namespace")),
+    request_body = NamespaceSettings,
+    responses(
+        (status = 200, description = "the settings were stored", body = NamespaceSettings),
+        (status = 400, description = "the namespace is reserved or the settings are invalid"),
+    ),
+)]
+pub async fn put_namespace_settings<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Path(namespace): Path<String>,
+    Json(settings): Json<NamespaceSettings>,
+) -> ApiResult<(StatusCode, Json<NamespaceSettings>)> {
+    reject_if_draining(&handle)?;
+    super::namespaces::check_name(&namespace)?;
+    super::namespaces::check_settings(&settings)?;
+    match super::namespaces::set_settings(&handle, &namespace, &settings).await {
+        Ok(()) => Ok((StatusCode::OK, Json(settings))),
+        Err(err) => Err(Error::CustomServerError(err.to_string()).into()),
     }
+}
+
+#[utoipa::path(
+    post, path = "/cluster/stats", tag = "cluster",
+    responses(
+        (status = 200, description = "the statistics of the cluster", body = ClusterStatistics),
+    ),
+)]
+pub async fn cluster_stat<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> ApiResult<(StatusCode, Json<ClusterStatistics>)> {
     Ok((
         StatusCode::OK,
-        Json(Status {
-            msg: "ok".to_string(),
-        }),
+        Json(cluster_statistics(&handle.cluster_api)),
     ))
 }
 
-pub async fn remove_value<S: Storage + Send + Sync + 'static>(
+/// lists the members of the cluster from the heartbeats they have written
+#[utoipa::path(
+    post, path = "/cluster/members", tag = "cluster",
+    responses(
+        (status = 200, description = "the members of the cluster", body = ClusterMembersResponse),
+    ),
+)]
+pub async fn cluster_members<S: Storage + Send + Sync + 'static>(
     State(handle): State<Arc<ApiState<S>>>,
-    Json(mut input): Json<crate::api::types::DeleteKVsRequest>,
-) -> ApiResult<(StatusCode, Json<Status>)> {
-    for (namespace, keys) in input.entries.iter_mut() {
-        let hashed_keys = keys.iter_mut().map(|key| {
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            hasher.write(key.as_bytes());
-            hasher.finish()
-        });
-        if let Err(err) = handle
-            .cluster_api
-            .del_many(namespace, hashed_keys, Consistency::EachQuorum)
-            .await
-        {
+) -> ApiResult<(StatusCode, Json<ClusterMembersResponse>)> {
+    let doc_ids = match handle
+        .storage()?
+        .iter_metadata(super::membership::MEMBERSHIP_KEYSPACE)
+        .await
+    {
+        Ok(metadata) => metadata
+            .filter_map(|(doc_id, _, is_tombstone)| if is_tombstone { None } else { Some(doc_id) })
+            .collect::<Vec<_>>(),
+        Err(err) => {
             return Err(Error::CustomServerError(err.to_string()).into());
         }
-    }
+    };
+    let mut members = match handle
+        .cluster_api
+        .get_many(super::membership::MEMBERSHIP_KEYSPACE, doc_ids.into_iter())
+        .await
+    {
+        Ok(res) => res
+            .filter_map(|doc| {
+                let wrapped_document = super::document::decode(&doc.data[..]).ok()?;
+                if wrapped_document.is_expired() {
+                    return None;
+                }
+                let info: MemberInfo = serde_json::from_slice(&wrapped_document.data).ok()?;
+                Some(ClusterMember {
+                    live: super::membership::is_live(&info),
+                    info,
+                })
+            })
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            return Err(Error::CustomServerError(err.to_string()).into());
+        }
+    };
+    members.sort_by(|a, b| a.info.node_id.cmp(&b.info.node_id));
+    Ok((StatusCode::OK, Json(ClusterMembersResponse { members })))
+}
+
+/// lists the keyspaces stored by this node along with their document counts
+#[utoipa::path(
+    post, path = "/cluster/keyspaces", tag = "cluster",
+    params(ClusterKeyspacesQuery),
+    responses(
+        (status = 200, description = "the keyspaces of this node", body = ClusterKeyspacesResponse),
+    ),
+)]
+pub async fn cluster_keyspaces<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+    Query(query): Query<ClusterKeyspacesQuery>,
+) -> ApiResult<(StatusCode, Json<ClusterKeyspacesResponse>)> {
+    let keyspaces = keyspaces(&handle, query.system).await?;
+    let stats = cluster_statistics(&handle.cluster_api);
     Ok((
         StatusCode::OK,
-        Json(Status {
-            msg: "ok".to_string(),
+        Json(ClusterKeyspacesResponse {
+            keyspaces,
+            sync: super::repair::sync_state(&stats),
         }),
     ))
 }
 
-pub async fn cluster_stat<S: Storage + Send + Sync + 'static>(
+/// returns the keyspaces stored by this node sorted by name, including the system
+/// keyspaces if `system` is true
+pub(crate) async fn keyspaces<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    system: bool,
+) -> Result<Vec<KeyspaceInfo>, Error> {
+    let names = match handle.storage()?.get_keyspace_list().await {
+        Ok(names) => names,
+        Err(err) => {
+            return Err(Error::CustomServerError(err.to_string()));
+        }
+    };
+    let mut keyspaces = Vec::with_capacity(names.len());
+    for name in names {
+        if !system && name.starts_with(super::membership::SYSTEM_KEYSPACE_PREFIX) {
+            continue;
+        }
+        let (mut documents, mut tombstones, mut checksum) = (0, 0, 0u64);
+        match handle.storage()?.iter_metadata(&name).await {
+            Ok(metadata) => metadata.for_each(|(doc_id, timestamp, is_tombstone)| {
+                if is_tombstone {
+                    tombstones += 1;
+                } else {
+                    documents += 1;
+                    // summed so that the order documents are listed in doesn't matter.
+                    // tombstones are left out as each node purges them on its own
+                    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+                    (doc_id, timestamp).hash(&mut hasher);
+                    checksum = checksum.wrapping_add(hasher.finish());
+                }
+            }),
+            Err(err) => {
+                return Err(Error::CustomServerError(err.to_string()));
+            }
+        }
+        keyspaces.push(KeyspaceInfo {
+            name,
+            documents,
+            tombstones,
+            checksum,
+        });
+    }
+    keyspaces.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(keyspaces)
+}
+
+/// triggers anti-entropy repair of a namespace, served by the admin router, see
+/// `api::repair`. fails with 400 if the namespace is reserved
+pub async fn repair_namespace<S: Storage + Send + Sync + 'static>(
     State(handle): State<Arc<ApiState<S>>>,
-) -> ApiResult<(StatusCode, Json<ClusterStatistics>)> {
-    let stats = handle.cluster_api.statistics();
+    Json(input): Json<RepairRequest>,
+) -> ApiResult<(StatusCode, Json<RepairResponse>)> {
+    super::namespaces::check_name(&input.namespace)?;
+    if let Err(err) = super::repair::trigger(&handle, &input.namespace).await {
+        return Err(Error::CustomServerError(err.to_string()).into());
+    }
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(RepairResponse {
+            namespace: input.namespace,
+            sync: super::repair::sync_state(&cluster_statistics(&handle.cluster_api)),
+        }),
+    ))
+}
+
+/// returns the state of the synchronisation tasks used by repair, served by the admin
+/// router
+pub async fn repair_status<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> (StatusCode, Json<SyncState>) {
+    (
+        StatusCode::OK,
+        Json(super::repair::sync_state(&cluster_statistics(
+            &handle.cluster_api,
+        ))),
+    )
+}
+
+/// starts draining the node, after which writes are rejected, served by the admin
+/// router. fails with 400 if the node is already draining or drained
+pub async fn drain_node<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> ApiResult<(StatusCode, Json<DrainStatus>)> {
+    super::drain::start(handle.clone())?;
+    Ok((StatusCode::ACCEPTED, Json(handle.drain.status())))
+}
+
+/// returns the progress of draining the node, served by the admin router
+pub async fn drain_status<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> (StatusCode, Json<DrainStatus>) {
+    (StatusCode::OK, Json(handle.drain.status()))
+}
+
+/// returns the filter applied to the node's logs, served by the admin router. fails
+/// with 503 if logging was not initialized from the configuration
+pub async fn get_log_filter() -> ApiResult<(StatusCode, Json<LogFilter>)> {
+    match bonerjams_config::logging::filter() {
+        Some(filter) => Ok((StatusCode::OK, Json(LogFilter { filter }))),
+        None => Err(logging_unavailable().into()),
+    }
+}
+
+/// replaces the filter applied to the node's logs until it is restarted, served by the
+/// admin router
+pub async fn put_log_filter(
+    Json(input): Json<LogFilter>,
+) -> ApiResult<(StatusCode, Json<LogFilter>)> {
+    if bonerjams_config::logging::filter().is_none() {
+        return Err(logging_unavailable().into());
+    }
+    if let Err(err) = bonerjams_config::logging::set_filter(&input.filter) {
+        return Err(Error::CustomError(format!("invalid filter {:#?}", err)).into());
+    }
+    Ok((StatusCode::OK, Json(input)))
+}
+
+fn logging_unavailable() -> Error {
+    Error::Unavailable("logging was not initialized from the configuration".to_string())
+}
+
+/// liveness check, returning ok as long as the process is able to serve requests
+#[utoipa::path(
+    get, path = "/healthz", tag = "health",
+    responses((status = 200, description = "the node is running", body = HealthStatus)),
+)]
+pub async fn healthz() -> (StatusCode, Json<HealthStatus>) {
+    (
+        StatusCode::OK,
+        Json(HealthStatus {
+            healthy: true,
+            checks: vec![],
+        }),
+    )
+}
 
-    let res_stats = ClusterStatistics {
+/// readiness check, returning 503 with the failed checks if the node can't serve traffic
+#[utoipa::path(
+    get, path = "/readyz", tag = "health",
+    responses(
+        (status = 200, description = "the node is ready to serve requests", body = HealthStatus),
+        (status = 503, description = "a check failed", body = HealthStatus),
+    ),
+)]
+pub async fn readyz<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> (StatusCode, Json<HealthStatus>) {
+    let stats = cluster_statistics(&handle.cluster_api);
+    let level = handle.consistency_level;
+    let checks = vec![
+        match handle.storage.as_ref() {
+            Some(storage) => match storage.get_keyspace_list().await {
+                Ok(_) => health_check("storage", None),
+                Err(err) => health_check("storage", Some(err.to_string())),
+            },
+            None => health_check(
+                "storage",
+                Some("the server was started without access to its storage".to_string()),
+            ),
+        },
+        health_check(
+            "cluster",
+            if stats.num_live_members > 0 {
+                None
+            } else {
+                Some("node has not joined the cluster".to_string())
+            },
+        ),
+        health_check(
+            "quorum",
+            if quorum_reachable(level, &stats) {
+                None
+            } else {
+                Some(format!(
+                    "{} of {} members are live, which is insufficient for {:?} consistency",
+                    stats.num_live_members,
+                    stats.num_live_members + stats.num_dead_members,
+                    level
+                ))
+            },
+        ),
+        health_check(
+            "drain",
+            if handle.drain.is_draining() {
+                Some(format!("node is {:?}", handle.drain.status().phase))
+            } else {
+                None
+            },
+        ),
+    ];
+    let healthy = checks.iter().all(|check| check.healthy);
+    (
+        if healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Json(HealthStatus { healthy, checks }),
+    )
+}
+
+/// returns metrics in the prometheus text format
+#[utoipa::path(
+    get, path = "/metrics", tag = "health",
+    responses(
+        (
+            status = 200,
+            description = "metrics in the prometheus text format",
+            body = String,
+            content_type = "text/plain",
+        ),
+    ),
+)]
+pub async fn metrics<S: Storage + Send + Sync + 'static>(
+    State(handle): State<Arc<ApiState<S>>>,
+) -> ApiResult<(StatusCode, [(HeaderName, &'static str); 1], String)> {
+    let keyspaces = match handle.storage.as_ref() {
+        Some(storage) => match storage.get_keyspace_list().await {
+            Ok(keyspaces) => keyspaces.len(),
+            Err(err) => {
+                return Err(Error::CustomServerError(err.to_string()).into());
+            }
+        },
+        None => 0,
+    };
+    match handle
+        .metrics
+        .render(&cluster_statistics(&handle.cluster_api), keyspaces)
+    {
+        Ok(body) => Ok((
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        )),
+        Err(err) => Err(Error::CustomServerError(err.to_string()).into()),
+    }
+}
+
+fn reject_if_draining<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+) -> super::error::Result<()> {
+    if handle.drain.is_draining() {
+        return Err(Error::Unavailable(
+            "node is draining and not accepting writes".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn cluster_statistics<S: Storage + Send + Sync + 'static>(
+    cluster_api: &DatacakeHandle<S>,
+) -> ClusterStatistics {
+    let stats = cluster_api.statistics();
+    ClusterStatistics {
         num_live_members: stats.num_live_members(),
         num_data_centers: stats.num_data_centers(),
         num_ongoing_sync_tasks: stats.num_ongoing_sync_tasks(),
@@ -121,6 +1438,27 @@ pub async fn cluster_stat<S: Storage + Send + Sync + 'static>(
         num_failed_sync_tasks: stats.num_failed_sync_tasks(),
         num_keyspace_changes: stats.num_keyspace_changes(),
         num_dead_members: stats.num_dead_members(),
-    };
-    Ok((StatusCode::OK, Json(res_stats)))
+    }
+}
+
+fn health_check(name: &str, failure: Option<String>) -> HealthCheck {
+    HealthCheck {
+        name: name.to_string(),
+        healthy: failure.is_none(),
+        detail: failure,
+    }
+}
+
+/// returns true if enough members are live to satisfy the consistency level. as the
+/// cluster statistics are not broken down by data center, the quorum levels are all
+/// checked against a majority of the whole cluster
+fn quorum_reachable(level: ConsistencyLevel, stats: &ClusterStatistics) -> bool {
+    let total = stats.num_live_members + stats.num_dead_members;
+    match level {
+        ConsistencyLevel::One => stats.num_live_members > 0,
+        ConsistencyLevel::Quorum | ConsistencyLevel::LocalQuorum | ConsistencyLevel::EachQuorum => {
+            stats.num_live_members * 2 > total
+        }
+        ConsistencyLevel::All => stats.num_dead_members == 0,
+    }
 }
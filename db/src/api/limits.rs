@@ -0,0 +1,206 @@
+//! enforces the request limits configured by `API.limits`. the body size is checked
+//! against the `Content-Length` header, or the size of the body if it is known, before
+//! the body is read. streamed bodies are capped by axum's `DefaultBodyLimit`. the
+//! remaining limits are checked as the body is decoded, see `decode`, so that decoding
+//! stops at the first key or value exceeding them. requests which aren't decoded by
+//! `decode`, such as grpc requests, are checked once parsed by `check_keys` and
+//! `check_value`
+
+use super::error::Error;
+use axum::body::HttpBody;
+use axum::extract::State;
+use axum::http::{header, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use bonerjams_config::RequestLimits;
+use serde::de::{self, Deserialize, Deserializer, SeqAccess, Visitor};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+thread_local! {
+    /// the limits of the request body being decoded on this thread, see `decode`
+    static DECODING: RefCell<Option<Decoding>> = RefCell::new(None);
+}
+
+struct Decoding {
+    limits: Arc<RequestLimits>,
+    keys: usize,
+    /// the limit which was exceeded, returned in place of the decoding error
+    error: Option<Error>,
+}
+
+/// middleware rejecting requests whose body is known to exceed `max_body_size`. the
+/// limits are added to the request extensions for `decode`
+pub async fn limit_body_size<B: HttpBody>(
+    State(limits): State<Arc<RequestLimits>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let content_length = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .or_else(|| request.body().size_hint().exact());
+    if let Some(content_length) = content_length {
+        if content_length > limits.max_body_size as u64 {
+            return Error::PayloadTooLarge(format!(
+                "request body of {} bytes exceeds max_body_size of {}",
+                content_length, limits.max_body_size
+            ))
+            .into_response();
+        }
+    }
+    request.extensions_mut().insert(limits);
+    next.run(request).await
+}
+
+/// runs `decode`, checking each key and value which is decoded against `limits`. if a
+/// limit is exceeded decoding fails, and the error describing the limit is returned
+pub fn decode<T>(
+    limits: Arc<RequestLimits>,
+    decode: impl FnOnce() -> anyhow::Result<T>,
+) -> Result<anyhow::Result<T>, Error> {
+    DECODING.with(|decoding| {
+        *decoding.borrow_mut() = Some(Decoding {
+            limits,
+            keys: 0,
+            error: None,
+        })
+    });
+    let res = decode();
+    let decoding = DECODING.with(|decoding| decoding.borrow_mut().take());
+    match decoding.and_then(|decoding| decoding.error) {
+        Some(err) => Err(err),
+        None => Ok(res),
+    }
+}
+
+/// applies `check` to the limits of the request being decoded, if any
+fn check_decoding<E: de::Error>(
+    check: impl FnOnce(&mut Decoding) -> Result<(), Error>,
+) -> Result<(), E> {
+    DECODING.with(|decoding| match decoding.borrow_mut().as_mut() {
+        Some(decoding) => check(decoding).map_err(|err| {
+            let msg = err.to_string();
+            decoding.error = Some(err);
+            E::custom(msg)
+        }),
+        None => Ok(()),
+    })
+}
+
+fn check_decoded_key<E: de::Error>(key: &str) -> Result<(), E> {
+    check_decoding(|decoding| {
+        decoding.keys += 1;
+        check_key(&decoding.limits, decoding.keys, key)
+    })
+}
+
+/// deserializes a key, checking it against the limits of the request being decoded
+pub fn key<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let key = String::deserialize(deserializer)?;
+    check_decoded_key(&key)?;
+    Ok(key)
+}
+
+/// deserializes a value with `codec::bytes`, checking its size against the limits
+/// of the request being decoded
+pub fn value<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let value = super::codec::bytes::deserialize(deserializer)?;
+    check_decoding(|decoding| {
+        if value.len() > decoding.limits.max_value_size {
+            return Err(Error::PayloadTooLarge(format!(
+                "value of {} bytes exceeds max_value_size of {}",
+                value.len(),
+                decoding.limits.max_value_size
+            )));
+        }
+        Ok(())
+    })?;
+    Ok(value)
+}
+
+/// deserializes keys grouped by namespace, checking each key against the limits of
+/// the request being decoded as it is read
+pub fn keys_by_namespace<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<String, Vec<String>>, D::Error> {
+    Ok(HashMap::<String, Keys>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(namespace, keys)| (namespace, keys.0))
+        .collect())
+}
+
+struct Keys(Vec<String>);
+
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(KeysVisitor)
+    }
+}
+
+struct KeysVisitor;
+
+impl<'de> Visitor<'de> for KeysVisitor {
+    type Value = Keys;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a list of keys")
+    }
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut keys = Vec::new();
+        while let Some(key) = seq.next_element::<String>()? {
+            check_decoded_key(&key)?;
+            keys.push(key);
+        }
+        Ok(Keys(keys))
+    }
+}
+
+/// checks the number of keys within a request, and the length of each key
+pub fn check_keys<'a>(
+    limits: &RequestLimits,
+    keys: impl Iterator<Item = &'a String>,
+) -> Result<(), Error> {
+    for (idx, key) in keys.enumerate() {
+        check_key(limits, idx + 1, key)?;
+    }
+    Ok(())
+}
+
+/// checks the length of a key, and that it is within `max_batch_keys` given it is
+/// key number `count` of the request. the key of repair markers is reserved
+fn check_key(limits: &RequestLimits, count: usize, key: &str) -> Result<(), Error> {
+    if key == super::repair::MARKER_KEY {
+        return Err(Error::CustomError(format!("key {} is reserved", key)));
+    }
+    if count > limits.max_batch_keys {
+        return Err(Error::CustomError(format!(
+            "request exceeds max_batch_keys of {}",
+            limits.max_batch_keys
+        )));
+    }
+    if key.len() > limits.max_key_length {
+        return Err(Error::CustomError(format!(
+            "key of {} bytes exceeds max_key_length of {}",
+            key.len(),
+            limits.max_key_length
+        )));
+    }
+    Ok(())
+}
+
+pub fn check_value(limits: &RequestLimits, key: &str, value: &[u8]) -> Result<(), Error> {
+    if value.len() > limits.max_value_size {
+        return Err(Error::PayloadTooLarge(format!(
+            "value of {} is {} bytes which exceeds max_value_size of {}",
+            key,
+            value.len(),
+            limits.max_value_size
+        )));
+    }
+    Ok(())
+}
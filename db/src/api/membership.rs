@@ -0,0 +1,107 @@
+//! tracks cluster membership by having every node periodically write a heartbeat
+//! into a replicated system keyspace, as datacake's handle only exposes the number of
+//! live and dead members. heartbeats expire so that members which left without calling
+//! `leave` are eventually forgotten
+
+use crate::prelude::{MemberInfo, WrappedDocument};
+use datacake::cluster::{Consistency, DatacakeCluster, DatacakeHandle, Storage};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// the keyspace heartbeats are stored in
+pub const MEMBERSHIP_KEYSPACE: &str = "__bonerjams_members";
+
+/// prefix of keyspaces used internally, which are hidden from keyspace listings
+pub const SYSTEM_KEYSPACE_PREFIX: &str = "__bonerjams";
+
+/// the default interval between heartbeats
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// a member is considered dead once this many heartbeat intervals have been missed
+pub const MISSED_HEARTBEATS: u32 = 3;
+
+/// a dead member is removed from the member list once this many heartbeat intervals
+/// have been missed
+pub const EXPIRED_HEARTBEATS: u32 = 60;
+
+/// spawns a task which writes a heartbeat for the given member every `interval`
+pub fn start_heartbeat<S: Storage + Send + Sync + 'static>(
+    cluster: &DatacakeCluster<S>,
+    member: MemberInfo,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    let handle = cluster.handle();
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            if let Err(err) = write_heartbeat(&handle, &member, interval).await {
+                log::error!("failed to write heartbeat {:#?}", err);
+            }
+        }
+    })
+}
+
+async fn write_heartbeat<S: Storage + Send + Sync + 'static>(
+    handle: &DatacakeHandle<S>,
+    member: &MemberInfo,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let member = MemberInfo {
+        last_seen: now_millis(),
+        heartbeat_interval: interval.as_millis() as u64,
+        ..member.clone()
+    };
+    let document = super::document::encode(&WrappedDocument {
+        key: member.node_id.clone(),
+        data: serde_json::to_vec(&member)?,
+        expires_at: Some(
+            member
+                .heartbeat_interval
+                .saturating_mul(EXPIRED_HEARTBEATS as u64)
+                .saturating_add(member.last_seen),
+        ),
+        content_type: None,
+    });
+    // heartbeats only need to reach the local node, replication distributes them
+    handle
+        .put_many(
+            MEMBERSHIP_KEYSPACE,
+            std::iter::once((super::hash_key(&member.node_id), document)),
+            Consistency::One,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    Ok(())
+}
+
+/// removes a member from the member list, called once its heartbeat has been stopped
+/// when the node leaves the cluster
+pub async fn leave<S: Storage + Send + Sync + 'static>(
+    cluster: &DatacakeCluster<S>,
+    node_id: &str,
+) -> anyhow::Result<()> {
+    cluster
+        .handle()
+        .del_many(
+            MEMBERSHIP_KEYSPACE,
+            std::iter::once(super::hash_key(node_id)),
+            Consistency::One,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    Ok(())
+}
+
+/// returns true if the member's last heartbeat is recent enough for it to be live
+pub fn is_live(member: &MemberInfo) -> bool {
+    let window = member.heartbeat_interval * MISSED_HEARTBEATS as u64;
+    now_millis().saturating_sub(member.last_seen) <= window
+}
+
+/// returns the current unix timestamp in milliseconds
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as u64)
+        .unwrap_or_default()
+}
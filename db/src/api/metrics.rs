@@ -0,0 +1,193 @@
+//! prometheus metrics for the key-value server
+
+use crate::prelude::ClusterStatistics;
+use axum::extract::{MatchedPath, State};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use prometheus::{
+    exponential_buckets, Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// the metrics collected by a single router. each router uses its own registry
+/// so that multiple nodes can run within the same process
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    batch_size: HistogramVec,
+    load_shed: IntCounter,
+    timeouts: IntCounter,
+    storage_size: IntGauge,
+    keyspaces: IntGauge,
+    live_members: IntGauge,
+    dead_members: IntGauge,
+    ongoing_sync_tasks: IntGauge,
+    slow_sync_tasks: IntGauge,
+    failed_sync_tasks: IntGauge,
+    keyspace_changes: IntGauge,
+    data_centers: IntGauge,
+    /// if Some, the size of this database is reported as the storage size
+    db: Option<sled::Db>,
+}
+
+impl Metrics {
+    pub fn new(db: Option<sled::Db>) -> prometheus::Result<Self> {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("bonerjams_http_requests_total", "number of http requests"),
+            &["route", "method", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "bonerjams_http_request_duration_seconds",
+                "http request latencies in seconds",
+            ),
+            &["route", "method", "status"],
+        )?;
+        let batch_size = HistogramVec::new(
+            HistogramOpts::new(
+                "bonerjams_batch_size",
+                "number of keys within a single request",
+            )
+            .buckets(exponential_buckets(1.0, 4.0, 8)?),
+            &["operation"],
+        )?;
+        let gauge = |name: &str, help: &str| -> prometheus::Result<IntGauge> {
+            let gauge = IntGauge::new(name, help)?;
+            registry.register(Box::new(gauge.clone()))?;
+            Ok(gauge)
+        };
+        let metrics = Self {
+            load_shed: IntCounter::new(
+                "bonerjams_requests_load_shed_total",
+                "number of requests rejected as the concurrency limit was reached",
+            )?,
+            timeouts: IntCounter::new(
+                "bonerjams_requests_timed_out_total",
+                "number of requests which exceeded the request timeout",
+            )?,
+            storage_size: gauge(
+                "bonerjams_storage_size_bytes",
+                "size of the node's storage on disk",
+            )?,
+            keyspaces: gauge("bonerjams_storage_keyspaces", "number of stored keyspaces")?,
+            live_members: gauge(
+                "bonerjams_cluster_live_members",
+                "number of currently alive members the node is aware of",
+            )?,
+            dead_members: gauge(
+                "bonerjams_cluster_dead_members",
+                "number of members the node currently believes are dead",
+            )?,
+            ongoing_sync_tasks: gauge(
+                "bonerjams_cluster_ongoing_sync_tasks",
+                "number of synchronisation tasks currently running",
+            )?,
+            slow_sync_tasks: gauge(
+                "bonerjams_cluster_slow_sync_tasks",
+                "number of synchronisation tasks that took longer than the timeout",
+            )?,
+            failed_sync_tasks: gauge(
+                "bonerjams_cluster_failed_sync_tasks",
+                "number of synchronisation tasks that failed",
+            )?,
+            keyspace_changes: gauge(
+                "bonerjams_cluster_keyspace_changes",
+                "number of remote keyspace changes observed",
+            )?,
+            data_centers: gauge(
+                "bonerjams_cluster_data_centers",
+                "number of data centers the cluster belongs to",
+            )?,
+            requests,
+            request_duration,
+            batch_size,
+            registry,
+            db,
+        };
+        metrics
+            .registry
+            .register(Box::new(metrics.requests.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.request_duration.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.batch_size.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.load_shed.clone()))?;
+        metrics
+            .registry
+            .register(Box::new(metrics.timeouts.clone()))?;
+        Ok(metrics)
+    }
+    /// records the number of keys within a batch operation
+    pub fn observe_batch(&self, operation: &str, size: usize) {
+        self.batch_size
+            .with_label_values(&[operation])
+            .observe(size as f64);
+    }
+    /// records errors returned by the middleware in the `ServiceBuilder` stack
+    pub fn observe_middleware_error(&self, error: &axum::BoxError) {
+        if error.is::<tower::load_shed::error::Overloaded>() {
+            self.load_shed.inc();
+        } else if error.is::<tower::timeout::error::Elapsed>() {
+            self.timeouts.inc();
+        }
+    }
+    /// updates the gauges and encodes all metrics in the prometheus text format
+    pub fn render(
+        &self,
+        stats: &ClusterStatistics,
+        keyspaces: usize,
+    ) -> prometheus::Result<String> {
+        self.keyspaces.set(keyspaces as i64);
+        if let Some(db) = self.db.as_ref() {
+            match db.size_on_disk() {
+                Ok(size) => self.storage_size.set(size as i64),
+                Err(err) => log::warn!("failed to calculate storage size {:#?}", err),
+            }
+        }
+        self.live_members.set(stats.num_live_members as i64);
+        self.dead_members.set(stats.num_dead_members as i64);
+        self.ongoing_sync_tasks
+            .set(stats.num_ongoing_sync_tasks as i64);
+        self.slow_sync_tasks.set(stats.num_slow_sync_tasks as i64);
+        self.failed_sync_tasks
+            .set(stats.num_failed_sync_tasks as i64);
+        self.keyspace_changes.set(stats.num_keyspace_changes as i64);
+        self.data_centers.set(stats.num_data_centers as i64);
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8_lossy(&buffer).to_string())
+    }
+}
+
+/// middleware recording the count and latency of requests by route, method and status
+pub async fn track_requests<B>(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = request.method().to_string();
+    let start = Instant::now();
+    let response = next.run(request).await;
+    let status = response.status();
+    let labels = [route.as_str(), method.as_str(), status.as_str()];
+    metrics.requests.with_label_values(&labels).inc();
+    metrics
+        .request_duration
+        .with_label_values(&labels)
+        .observe(start.elapsed().as_secs_f64());
+    response
+}
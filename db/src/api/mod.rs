@@ -1,44 +1,244 @@
 //! Provides an api for a distributed, replicated key-value store built upon datacake
 
+pub mod blobs;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 #[cfg(feature = "client")]
 pub mod client;
+pub mod codec;
+pub mod document;
 
+pub mod drain;
 pub mod error;
+pub mod grpc;
 pub mod kv_server;
+pub mod limits;
+pub mod membership;
+pub mod metrics;
+pub mod namespaces;
+pub mod openapi;
+pub mod rate_limit;
+pub mod repair;
+pub mod server;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+pub mod trace;
 pub mod types;
+use crate::prelude::ChangeEvent;
 use crate::types::DbKey;
 use axum::error_handling::HandleErrorLayer;
+use axum::extract::DefaultBodyLimit;
 use axum::http::HeaderValue;
 use axum::http::StatusCode;
+use axum::middleware;
 use axum::response::IntoResponse;
-use axum::routing::post;
+use axum::routing::{delete, get, post, MethodRouter};
 use axum::BoxError;
 use axum::Router;
-use bonerjams_config::API;
+use bonerjams_config::cluster::{ClusterOpts, ConsistencyLevel};
+use bonerjams_config::{BlobConfig, RequestLimits, API};
+use datacake::cluster::ClusterOptions;
+use datacake::cluster::Consistency;
 use datacake::cluster::DatacakeCluster;
 use datacake::cluster::DatacakeHandle;
 use datacake::cluster::Storage;
+use std::hash::Hasher;
 use std::sync::Arc;
+use tokio::sync::broadcast;
+use tonic::transport::server::Routes;
 use tower::ServiceBuilder;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 
+/// the number of changes buffered for each watcher before it falls behind
+const CHANGES_CAPACITY: usize = 1024;
+
 #[derive(Clone)]
 pub struct ApiState<S: Storage + Send + Sync + 'static> {
     cluster_api: Arc<DatacakeHandle<S>>,
+    /// the storage backing the cluster, used for operations datacake does
+    /// not expose through its handle such as listing documents. None if the
+    /// router was built without it, see `RouterBuilder::storage`
+    storage: Option<Arc<S>>,
+    metrics: Arc<self::metrics::Metrics>,
+    consistency_level: ConsistencyLevel,
+    drain: Arc<self::drain::Drain>,
+    limits: Arc<RequestLimits>,
+    rate_limiter: Arc<self::rate_limit::RateLimiter>,
+    /// changes made through this node, streamed by the grpc `Watch` rpc
+    changes: broadcast::Sender<ChangeEvent>,
+    blobs: Arc<BlobConfig>,
+    /// the id the node's membership is recorded under, see `RouterBuilder::node_id`
+    node_id: Option<String>,
+    namespace_settings: Arc<self::namespaces::SettingsCache>,
+    namespace_usage: Arc<self::namespaces::UsageCounters>,
+    key_locks: Arc<self::kv_server::KeyLocks>,
+}
+
+impl<S: Storage + Send + Sync + 'static> ApiState<S> {
+    /// builds the state shared by the http and grpc apis
+    fn new(builder: &RouterBuilder<'_, S>) -> Arc<Self> {
+        let api_conf = &builder.api_conf;
+        // metrics are statically defined so this can only fail due to a programming error
+        let metrics =
+            self::metrics::Metrics::new(builder.db.clone()).expect("failed to create metrics");
+        Arc::new(Self {
+            cluster_api: Arc::new(builder.cluster.handle()),
+            storage: builder.storage.clone(),
+            metrics: Arc::new(metrics),
+            consistency_level: api_conf.consistency.unwrap_or_default(),
+            drain: builder.drain.clone().unwrap_or_default(),
+            limits: Arc::new(api_conf.limits.clone()),
+            rate_limiter: Arc::new(self::rate_limit::RateLimiter::new(
+                api_conf.rate_limits.clone(),
+            )),
+            changes: broadcast::channel(CHANGES_CAPACITY).0,
+            blobs: Arc::new(api_conf.blobs.clone()),
+            node_id: builder.node_id.clone(),
+            namespace_settings: Default::default(),
+            namespace_usage: Default::default(),
+            key_locks: Default::default(),
+        })
+    }
+    /// the datacake consistency used for writes and deletes
+    fn consistency(&self) -> Consistency {
+        consistency(self.consistency_level)
+    }
+    /// the storage backing the cluster, or an error if the router was built without it
+    fn storage(&self) -> self::error::Result<&Arc<S>> {
+        self.storage.as_ref().ok_or_else(|| {
+            self::error::Error::Unsupported(
+                "the server was started without access to its storage".to_string(),
+            )
+        })
+    }
+    /// the id of the node, or an error if the router was built without it
+    fn node_id(&self) -> self::error::Result<&str> {
+        self.node_id.as_deref().ok_or_else(|| {
+            self::error::Error::Unsupported("the server was started without a node id".to_string())
+        })
+    }
+}
+
+/// builds the datacake cluster options from the cluster configuration
+pub fn cluster_options(opts: &ClusterOpts) -> anyhow::Result<ClusterOptions> {
+    let mut options = ClusterOptions::default();
+    if let Some(cluster_id) = opts.cluster_id.as_ref() {
+        options = options.with_cluster_id(cluster_id);
+    }
+    if let Some(data_center) = opts.data_center.as_ref() {
+        options = options.with_data_center(data_center);
+    }
+    Ok(options.with_repair_interval(opts.repair_interval()?))
+}
+
+/// converts the configured consistency level into the datacake consistency
+pub(crate) fn consistency(level: ConsistencyLevel) -> Consistency {
+    match level {
+        ConsistencyLevel::One => Consistency::One,
+        ConsistencyLevel::Quorum => Consistency::Quorum,
+        ConsistencyLevel::LocalQuorum => Consistency::LocalQuorum,
+        ConsistencyLevel::EachQuorum => Consistency::EachQuorum,
+        ConsistencyLevel::All => Consistency::All,
+    }
 }
 
 /// while the router accepts keys as string's, the underlying datastore (datacake) requires
 /// keys be `u64`, therefore we use the Sip24 hasher used by `HashMap` to hash the keys before
-/// insertion into the database
+/// insertion into the database.
+///
+/// routes which list documents, such as `/scan` and `/namespaces`, require the cluster's
+/// storage and respond with 501 Not Implemented, use `RouterBuilder` to provide it
 pub fn new_router<S: Storage + Send + Sync + 'static>(
     cluster: &DatacakeCluster<S>,
     api_conf: API,
 ) -> Router {
-    let cluster_api = cluster.handle();
-    let handle = Arc::new(ApiState {
-        cluster_api: Arc::new(cluster_api),
-    });
+    RouterBuilder::new(cluster, api_conf).build()
+}
+
+/// builds the router along with the optional parts of its state
+pub struct RouterBuilder<'a, S: Storage + Send + Sync + 'static> {
+    cluster: &'a DatacakeCluster<S>,
+    api_conf: API,
+    storage: Option<Arc<S>>,
+    db: Option<sled::Db>,
+    drain: Option<Arc<self::drain::Drain>>,
+    node_id: Option<String>,
+}
+
+impl<'a, S: Storage + Send + Sync + 'static> RouterBuilder<'a, S> {
+    pub fn new(cluster: &'a DatacakeCluster<S>, api_conf: API) -> Self {
+        Self {
+            cluster,
+            api_conf,
+            storage: None,
+            db: None,
+            drain: None,
+            node_id: None,
+        }
+    }
+    /// sets the storage the cluster was connected with, enabling the routes which
+    /// list documents
+    pub fn storage(mut self, storage: Arc<S>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+    /// sets the sled database backing the storage, whose size on disk is reported by
+    /// `/metrics`
+    pub fn db(mut self, db: sled::Db) -> Self {
+        self.db = Some(db);
+        self
+    }
+    /// sets the drain tracked by `/cluster/drain`. once it reports the node as drained
+    /// the owner of the cluster should shut it down, as `server::run` does
+    pub fn drain(mut self, drain: Arc<self::drain::Drain>) -> Self {
+        self.drain = Some(drain);
+        self
+    }
+    /// sets the id the node's heartbeats are written under, which blob garbage
+    /// collection requires to know which members have found a chunk orphaned
+    pub fn node_id(mut self, node_id: impl Into<String>) -> Self {
+        self.node_id = Some(node_id.into());
+        self
+    }
+    /// builds the state shared by the http and grpc apis, for `new_router_with_state`
+    pub fn state(&self) -> Arc<ApiState<S>> {
+        ApiState::new(self)
+    }
+    pub fn build(self) -> Router {
+        new_router_with_state(self.state(), self.api_conf)
+    }
+}
+
+/// serves the router on `listener` until `shutdown` completes. the router is served with
+/// the `ConnectInfo` the rate limits identify clients by
+pub async fn serve(
+    listener: std::net::TcpListener,
+    router: Router,
+    shutdown: impl std::future::Future<Output = ()>,
+) -> anyhow::Result<()> {
+    axum::Server::from_tcp(listener)?
+        .serve(router.into_make_service_with_connect_info::<std::net::SocketAddr>())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+/// builds the router from existing state, allowing the state to be shared with a grpc
+/// api served on a separate address, see `new_grpc_router`. if the grpc api is enabled
+/// without an address of its own it is served by this router
+pub fn new_router_with_state<S: Storage + Send + Sync + 'static>(
+    handle: Arc<ApiState<S>>,
+    api_conf: API,
+) -> Router {
+    let limits = handle.limits.clone();
+    let rate_limiter = handle.rate_limiter.clone();
+    let metrics = handle.metrics.clone();
+    let grpc = if api_conf.grpc.enabled && api_conf.grpc.listen_address.is_none() {
+        Some(self::grpc::new_service(handle.clone()))
+    } else {
+        None
+    };
     let router = if let Some(cors_conf) = api_conf.cors {
         Router::new().layer(
             CorsLayer::new()
@@ -60,24 +260,189 @@ pub fn new_router<S: Storage + Send + Sync + 'static>(
     } else {
         Router::new()
     };
+    let router = buffered_routes()
+        .into_iter()
+        .fold(router, |router, (path, route)| router.route(path, route));
+    #[cfg(feature = "docs-ui")]
+    let router = router.merge(
+        utoipa_swagger_ui::SwaggerUi::new("/docs")
+            .config(utoipa_swagger_ui::Config::from("/openapi.json")),
+    );
+    let router = router
+        .layer(DefaultBodyLimit::max(limits.max_body_size))
+        .layer(middleware::from_fn_with_state(
+            limits,
+            self::limits::limit_body_size,
+        ));
+    // single values and blobs are streamed rather than buffered, so are added after
+    // the body limits and are instead limited by `max_value_size` and `max_blob_size`
+    let router = streamed_routes()
+        .into_iter()
+        .fold(router, |router, (path, route)| router.route(path, route))
+        .layer(middleware::from_fn_with_state(
+            rate_limiter,
+            self::rate_limit::limit_requests,
+        ))
+        .with_state(handle);
+    let router = match grpc {
+        // grpc requests are routed by path, and apply their own rate limits
+        Some(grpc) => router.merge(Routes::new(grpc).into_router()),
+        None => router,
+    };
+    with_service_layers(router, metrics, &api_conf)
+}
+
+/// a route of the http api, and the handlers of the methods it serves
+type ApiRoute<S> = (&'static str, MethodRouter<Arc<ApiState<S>>>);
+
+/// the routes whose bodies are buffered, and so are limited by `max_body_size`. along
+/// with `streamed_routes` these are every route of the http api, which the openapi
+/// specification must document other than `/openapi.json` itself
+fn buffered_routes<S: Storage + Send + Sync + 'static>() -> Vec<ApiRoute<S>> {
+    vec![
+        ("/put", post(self::kv_server::put_value)),
+        ("/get", post(self::kv_server::get_value)),
+        ("/delete", post(self::kv_server::remove_value)),
+        ("/scan", post(self::kv_server::scan_keys)),
+        ("/namespaces", get(self::kv_server::list_namespaces)),
+        (
+            "/namespaces/:namespace",
+            get(self::kv_server::namespace_info),
+        ),
+        (
+            "/namespaces/:namespace/settings",
+            get(self::kv_server::get_namespace_settings)
+                .put(self::kv_server::put_namespace_settings),
+        ),
+        ("/cluster/stats", post(self::kv_server::cluster_stat)),
+        ("/cluster/members", post(self::kv_server::cluster_members)),
+        (
+            "/cluster/keyspaces",
+            post(self::kv_server::cluster_keyspaces),
+        ),
+        ("/metrics", get(self::kv_server::metrics)),
+        ("/healthz", get(self::kv_server::healthz)),
+        ("/readyz", get(self::kv_server::readyz)),
+        ("/openapi.json", get(self::openapi::openapi_json)),
+    ]
+}
+
+/// the single key and blob routes, whose bodies are streamed rather than buffered
+fn streamed_routes<S: Storage + Send + Sync + 'static>() -> Vec<ApiRoute<S>> {
+    vec![
+        (
+            "/kv/:namespace/:key",
+            get(self::kv_server::get_key)
+                .put(self::kv_server::put_key)
+                .delete(self::kv_server::delete_key),
+        ),
+        (
+            "/blobs/:namespace/:key",
+            get(self::kv_server::get_blob)
+                .put(self::kv_server::put_blob)
+                .delete(self::kv_server::delete_blob),
+        ),
+        ("/uploads", post(self::kv_server::create_upload)),
+        (
+            "/uploads/:upload_id",
+            get(self::kv_server::get_upload)
+                .patch(self::kv_server::append_upload)
+                .delete(self::kv_server::abort_upload),
+        ),
+        (
+            "/uploads/:upload_id/complete",
+            post(self::kv_server::complete_upload),
+        ),
+    ]
+}
+
+/// the routes which operate the node or the whole cluster, served by `new_admin_router`
+fn admin_routes<S: Storage + Send + Sync + 'static>() -> Vec<ApiRoute<S>> {
+    vec![
+        (
+            "/admin/log-filter",
+            get(self::kv_server::get_log_filter).put(self::kv_server::put_log_filter),
+        ),
+        (
+            "/namespaces/:namespace",
+            delete(self::kv_server::drop_namespace),
+        ),
+        (
+            "/cluster/repair",
+            post(self::kv_server::repair_namespace).get(self::kv_server::repair_status),
+        ),
+        (
+            "/cluster/drain",
+            post(self::kv_server::drain_node).get(self::kv_server::drain_status),
+        ),
+        ("/cluster/gc", post(self::kv_server::collect_garbage)),
+    ]
+}
+
+/// builds a router serving the admin api, for serving it on `AdminConfig.listen_address`.
+/// the admin routes are not served by the public router as they are unauthenticated
+pub fn new_admin_router<S: Storage + Send + Sync + 'static>(handle: Arc<ApiState<S>>) -> Router {
+    let max_body_size = handle.limits.max_body_size;
+    admin_routes()
+        .into_iter()
+        .fold(Router::new(), |router, (path, route)| {
+            router.route(path, route)
+        })
+        .layer(DefaultBodyLimit::max(max_body_size))
+        .with_state(handle)
+        .layer(TraceLayer::new_for_http().make_span_with(self::trace::make_span))
+        .layer(middleware::from_fn(self::trace::propagate))
+}
+
+/// builds a router serving only the grpc api, for serving it on
+/// `GrpcConfig.listen_address`
+pub fn new_grpc_router<S: Storage + Send + Sync + 'static>(
+    handle: Arc<ApiState<S>>,
+    api_conf: &API,
+) -> Router {
+    let metrics = handle.metrics.clone();
+    let router = Routes::new(self::grpc::new_service(handle)).into_router();
+    with_service_layers(router, metrics, api_conf)
+}
+
+/// applies the load shedding, concurrency limit, timeout, tracing and metrics shared
+/// by the http and grpc apis
+fn with_service_layers(
+    router: Router,
+    metrics: Arc<self::metrics::Metrics>,
+    api_conf: &API,
+) -> Router {
     router
-        .route("/put", post(self::kv_server::put_value))
-        .route("/get", post(self::kv_server::get_value))
-        .route("/delete", post(self::kv_server::remove_value))
-        .route("/cluster/stats", post(self::kv_server::cluster_stat))
-        .layer(
+        .layer({
+            let metrics = metrics.clone();
             ServiceBuilder::new()
                 // Handle errors from middleware
-                .layer(HandleErrorLayer::new(handle_error))
+                .layer(HandleErrorLayer::new(move |error: BoxError| {
+                    handle_error(metrics.clone(), error)
+                }))
                 .load_shed()
                 .concurrency_limit(api_conf.concurrency_limit as usize)
                 .timeout(std::time::Duration::from_secs(api_conf.timeout))
-                .layer(TraceLayer::new_for_http()),
-        )
-        .with_state(handle)
+                .layer(TraceLayer::new_for_http().make_span_with(self::trace::make_span))
+        })
+        // applied last so that requests rejected by the middleware above are counted
+        .layer(middleware::from_fn_with_state(
+            metrics,
+            self::metrics::track_requests,
+        ))
+        // outermost so every response, including rejections, carries a request id
+        .layer(middleware::from_fn(self::trace::propagate))
+}
+
+/// hashes a key into the `u64` document id used by datacake
+pub(crate) fn hash_key(key: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    hasher.write(key.as_bytes());
+    hasher.finish()
 }
 
-async fn handle_error(error: BoxError) -> impl IntoResponse {
+async fn handle_error(metrics: Arc<self::metrics::Metrics>, error: BoxError) -> impl IntoResponse {
+    metrics.observe_middleware_error(&error);
     return (
         StatusCode::INTERNAL_SERVER_ERROR,
         format!("Something went wrong: {}", error),
@@ -89,22 +454,239 @@ async fn handle_error(error: BoxError) -> impl IntoResponse {
 mod test {
     use super::*;
     use crate::prelude::{
-        DeleteKVsRequest, GetKVsRequest, GetKVsResponse, KeyValue, PutKVsRequest,
+        ClusterMembersResponse, DeleteKVsRequest, DropNamespaceResponse, GetKVsRequest,
+        GetKVsResponse, HealthStatus, KeyValue, ListNamespacesResponse, MemberInfo, NamespaceInfo,
+        NamespaceSettings, PutKVsRequest, RepairRequest, RepairResponse, ScanKVsRequest,
+        ScanKVsResponse, ValueCodec, WrappedDocument,
     };
+    use axum::middleware::Next;
     use axum::response::Response;
     use axum::{
         body::Body,
         http::{self, Request, StatusCode},
     };
-    use bonerjams_config::Configuration;
+    use bonerjams_config::{RateLimit, RateLimits};
     use datacake;
     use datacake::cluster::{ClusterOptions, ConnectionConfig, DCAwareSelector};
     use datacake_sled::{self, SledStorage};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
     use serde_json::{json, Value};
     use std::collections::HashMap;
-    use std::net::{SocketAddr, TcpListener};
     use tower::Service; // for `call`
     use tower::ServiceExt; // for `oneshot` and `ready`
+    use utoipa::openapi::PathItemType;
+    use utoipa::OpenApi;
+
+    /// a single node cluster along with its routers, which are called directly rather
+    /// than being served
+    struct TestServer {
+        cluster: DatacakeCluster<SledStorage>,
+        storage: SledStorage,
+        app: Router,
+        admin: Router,
+    }
+
+    impl TestServer {
+        async fn start() -> Self {
+            let _ = tracing_subscriber::fmt::try_init();
+            let storage = SledStorage::open_temporary().unwrap();
+            let addr = testing::ephemeral_addr().unwrap();
+            let cluster = DatacakeCluster::connect(
+                age::x25519::Identity::generate(),
+                ConnectionConfig::new(addr, addr, Vec::<String>::new()),
+                storage.clone(),
+                DCAwareSelector::default(),
+                ClusterOptions::default(),
+            )
+            .await
+            .unwrap();
+            let state = RouterBuilder::new(&cluster, API::default())
+                .storage(Arc::new(storage.clone()))
+                .state();
+            Self {
+                app: new_router_with_state(state.clone(), API::default()),
+                admin: new_admin_router(state),
+                cluster,
+                storage,
+            }
+        }
+        /// returns a router for the same node and storage with a different configuration
+        fn router(&self, api_conf: API) -> Router {
+            RouterBuilder::new(&self.cluster, api_conf)
+                .storage(Arc::new(self.storage.clone()))
+                .build()
+        }
+        async fn shutdown(self) {
+            self.cluster.shutdown().await;
+        }
+    }
+
+    fn json_request(method: http::Method, uri: &str, body: &impl Serialize) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .method(method)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(body).unwrap()))
+            .unwrap()
+    }
+
+    fn empty_request(method: http::Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .uri(uri)
+            .method(method)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn json_body<T: DeserializeOwned>(response: Response) -> T {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// keys `key-0` through `key-99` within `namespace`
+    fn keys(namespace: &str) -> HashMap<String, Vec<String>> {
+        HashMap::from([(
+            namespace.to_string(),
+            (0..100).map(|idx| format!("key-{}", idx)).collect(),
+        )])
+    }
+
+    /// writes `key-0` through `key-99` to `namespace`
+    async fn put_keys(app: &Router, namespace: &str) {
+        let entries = keys(namespace)
+            .into_iter()
+            .map(|(namespace, keys)| {
+                let key_values = keys
+                    .into_iter()
+                    .map(|key| KeyValue {
+                        value: format!("value of {}", key).into_bytes(),
+                        key,
+                    })
+                    .collect();
+                (namespace, key_values)
+            })
+            .collect();
+        let response = app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/put",
+                &PutKVsRequest { entries },
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    /// returns the methods each of `paths` is routed for by `router`. requests which
+    /// match a route are answered by a route layer rather than the handler, so that the
+    /// route table is found without acting on any request
+    async fn routed(
+        router: &Router,
+        paths: impl IntoIterator<Item = &'static str>,
+    ) -> Vec<(String, PathItemType)> {
+        let probe = router.clone().route_layer(middleware::from_fn(
+            |_: Request<Body>, _: Next<Body>| async { StatusCode::NO_CONTENT },
+        ));
+        let methods = [
+            (PathItemType::Get, http::Method::GET),
+            (PathItemType::Post, http::Method::POST),
+            (PathItemType::Put, http::Method::PUT),
+            (PathItemType::Delete, http::Method::DELETE),
+            (PathItemType::Patch, http::Method::PATCH),
+        ];
+        let mut routed = Vec::new();
+        for path in paths {
+            // path parameters are replaced by a placeholder
+            let uri = path
+                .split('/')
+                .map(|segment| {
+                    if segment.starts_with(':') {
+                        "probe"
+                    } else {
+                        segment
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for (operation, method) in methods.iter() {
+                let response = probe
+                    .clone()
+                    .oneshot(empty_request(method.clone(), &uri))
+                    .await
+                    .unwrap();
+                if response.status() == StatusCode::NO_CONTENT {
+                    routed.push((path.to_string(), operation.clone()));
+                }
+            }
+        }
+        routed.sort_by_key(|(path, operation)| format!("{} {:?}", path, operation));
+        routed
+    }
+
+    #[test]
+    fn test_document_format() {
+        for (expires_at, content_type) in [
+            (None, None),
+            (Some(1234), None),
+            (None, Some("text/plain")),
+            (Some(1234), Some("")),
+        ] {
+            let doc = WrappedDocument {
+                key: "key".to_string(),
+                data: vec![0, 1, 2, 255],
+                expires_at,
+                content_type: content_type.map(str::to_string),
+            };
+            let encoded = document::encode(&doc);
+            assert_eq!(encoded[0], document::FORMAT_VERSION);
+            let decoded = document::decode(&encoded).unwrap();
+            assert_eq!(decoded.key, doc.key);
+            assert_eq!(decoded.data, doc.data);
+            assert_eq!(decoded.expires_at, doc.expires_at);
+            assert_eq!(decoded.content_type, doc.content_type);
+            assert!(document::decode(&encoded[..encoded.len() - 5]).is_err());
+        }
+        // documents written as json, with values as byte arrays or base64
+        for legacy in [
+            r#"{"key":"key","data":[104,105]}"#,
+            r#"{"key":"key","data":"aGk=","expires_at":5}"#,
+        ] {
+            let decoded = document::decode(legacy.as_bytes()).unwrap();
+            assert_eq!(decoded.key, "key");
+            assert_eq!(decoded.data, b"hi".to_vec());
+        }
+        assert!(document::decode(&[]).is_err());
+        assert!(document::decode(&[2, 0]).is_err());
+    }
+    #[test]
+    fn test_trace_parent() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let parent = trace::TraceParent::parse(header).unwrap();
+        assert_eq!(parent.trace_id, 0x4bf92f3577b34da6a3ce929d0e0e4736);
+        assert_eq!(parent.parent_id, 0x00f067aa0ba902b7);
+        assert_eq!(parent.flags, 1);
+        assert_eq!(parent.to_string(), header);
+        // later versions may append fields
+        assert!(trace::TraceParent::parse(&format!("01{}-extra", &header[2..])).is_some());
+        for invalid in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        ] {
+            assert!(trace::TraceParent::parse(invalid).is_none(), "{}", invalid);
+        }
+        let generated = trace::TraceParent::generate();
+        assert_eq!(
+            trace::TraceParent::parse(&generated.to_string()),
+            Some(generated)
+        );
+    }
     #[tokio::test(flavor = "multi_thread")]
     async fn test_key_value_server() {
         std::env::set_var("RUST_LOG", "debug");
@@ -112,7 +694,7 @@ mod test {
         let node_d = age::x25519::Identity::generate();
         let store_1 = SledStorage::open_temporary().unwrap();
 
-        let addr_1 = "127.0.0.1:9000".parse::<SocketAddr>().unwrap();
+        let addr_1 = testing::ephemeral_addr().unwrap();
         let connection_cfg_1 = ConnectionConfig::new(addr_1, addr_1, Vec::<String>::new());
 
         let cluster_1 = DatacakeCluster::connect(
@@ -199,7 +781,6 @@ mod test {
                 assert_eq!(msg, format!("muchdata yo {}", idx));
             });
         });
-        println!("{:#?}", res);
         let response = app
             .clone()
             .oneshot(
@@ -215,7 +796,696 @@ mod test {
 
         assert_eq!(response.status(), StatusCode::OK);
 
-        tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
         cluster_1.shutdown().await;
     }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_codecs() {
+        let server = TestServer::start().await;
+        let app = &server.app;
+        put_keys(app, "codecspace").await;
+        let get_kv_req = GetKVsRequest {
+            entries: keys("codecspace"),
+        };
+        // values are sent as base64 within json
+        let response = app
+            .clone()
+            .oneshot(json_request(http::Method::POST, "/get", &get_kv_req))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let raw: Value = json_body(response).await;
+        assert!(raw["entries"]["codecspace"][0]["data"].is_string());
+        for format in [codec::Format::MessagePack, codec::Format::Cbor] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/get")
+                        .method(http::Method::POST)
+                        .header(http::header::CONTENT_TYPE, format.content_type())
+                        .header(http::header::ACCEPT, format.content_type())
+                        .body(Body::from(format.encode(&get_kv_req).unwrap()))
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(
+                response.headers()[http::header::CONTENT_TYPE],
+                format.content_type()
+            );
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let res: GetKVsResponse = format.decode(&body).unwrap();
+            assert_eq!(res.entries["codecspace"].len(), 100);
+        }
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .method(http::Method::POST)
+                    .header(http::header::CONTENT_TYPE, "text/plain")
+                    .body(Body::from(serde_json::to_vec(&get_kv_req).unwrap()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rest_keys() {
+        let server = TestServer::start().await;
+        let app = &server.app;
+        // single keys are written and read as is, with preconditions on their etag
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/kv/rest/a%2Fkey")
+                    .method(http::Method::PUT)
+                    .header(http::header::CONTENT_TYPE, "text/plain")
+                    .header(http::header::IF_NONE_MATCH, "*")
+                    .body(Body::from("hello"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let etag = response.headers()[http::header::ETAG].clone();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/kv/rest/a%2Fkey")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[http::header::CONTENT_TYPE], "text/plain");
+        assert_eq!(response.headers()[http::header::ETAG], etag);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"hello");
+        for (method, header, status) in [
+            (
+                http::Method::GET,
+                http::header::IF_NONE_MATCH,
+                StatusCode::NOT_MODIFIED,
+            ),
+            (http::Method::HEAD, http::header::IF_MATCH, StatusCode::OK),
+            (
+                http::Method::PUT,
+                http::header::IF_NONE_MATCH,
+                StatusCode::PRECONDITION_FAILED,
+            ),
+            (
+                http::Method::DELETE,
+                http::header::IF_MATCH,
+                StatusCode::NO_CONTENT,
+            ),
+            (
+                http::Method::GET,
+                http::header::IF_MATCH,
+                StatusCode::NOT_FOUND,
+            ),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::builder()
+                        .uri("/kv/rest/a%2Fkey")
+                        .method(method.clone())
+                        .header(header, etag.clone())
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status, "{}", method);
+        }
+        // of two writes conditional on the same etag, only the first succeeds
+        let put_key = |value: &'static str, etag: Option<HeaderValue>| {
+            let mut request = Request::builder()
+                .uri("/kv/rest/a%2Fkey")
+                .method(http::Method::PUT)
+                .header(http::header::CONTENT_TYPE, "text/plain");
+            if let Some(etag) = etag {
+                request = request.header(http::header::IF_MATCH, etag);
+            }
+            app.clone()
+                .oneshot(request.body(Body::from(value)).unwrap())
+        };
+        let response = put_key("hello", None).await.unwrap();
+        let etag = response.headers()[http::header::ETAG].clone();
+        let (first, second) = tokio::join!(
+            put_key("first", Some(etag.clone())),
+            put_key("second", Some(etag))
+        );
+        let mut statuses = [first.unwrap().status(), second.unwrap().status()];
+        statuses.sort();
+        assert_eq!(
+            statuses,
+            [StatusCode::NO_CONTENT, StatusCode::PRECONDITION_FAILED]
+        );
+        // single key writes without a content type keep the one the key was written
+        // with, while batch writes don't set one
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/kv/rest/a%2Fkey")
+                    .method(http::Method::PUT)
+                    .body(Body::from("untyped"))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/kv/rest/a%2Fkey")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.headers()[http::header::CONTENT_TYPE], "text/plain");
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/put")
+                    .method(http::Method::POST)
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&PutKVsRequest {
+                            entries: HashMap::from([(
+                                "rest".to_string(),
+                                vec![KeyValue {
+                                    key: "a/key".to_string(),
+                                    value: b"batch".to_vec(),
+                                }],
+                            )]),
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/kv/rest/a%2Fkey")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/octet-stream"
+        );
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"batch");
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/kv/rest/a%2Fkey")
+                    .method(http::Method::DELETE)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_admin_routes() {
+        let server = TestServer::start().await;
+        let (app, admin) = (&server.app, &server.admin);
+        // the log filter is only served by the admin router. the tests' subscriber is not
+        // installed by `logging::init`, so its filter can't be changed
+        let set_filter = || {
+            Request::builder()
+                .uri("/admin/log-filter")
+                .method(http::Method::PUT)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(r#"{"filter":"debug"}"#))
+                .unwrap()
+        };
+        let response = app.clone().oneshot(set_filter()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let response = admin.clone().oneshot(set_filter()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let response = admin
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/cluster/repair",
+                &RepairRequest {
+                    namespace: "repairspace".to_string(),
+                },
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let res: RepairResponse = json_body(response).await;
+        assert_eq!(res.namespace, "repairspace");
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_request_ids() {
+        let server = TestServer::start().await;
+        let app = &server.app;
+        // request ids are echoed in responses and error bodies, or generated if absent
+        for request_id in [Some("client-request-1"), None] {
+            let mut request = Request::builder().uri("/kv/rest/a%2Fkey");
+            if let Some(request_id) = request_id {
+                request = request.header(trace::REQUEST_ID, request_id);
+            }
+            let response = app
+                .clone()
+                .oneshot(request.body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            let echoed = response.headers()[trace::REQUEST_ID]
+                .to_str()
+                .unwrap()
+                .to_string();
+            if let Some(request_id) = request_id {
+                assert_eq!(echoed, request_id);
+            }
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let body: Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["request_id"], echoed);
+        }
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_scan() {
+        let server = TestServer::start().await;
+        let app = &server.app;
+        put_keys(app, "scanspace").await;
+        for (limit, expected) in [
+            // key-1, and key-10 through key-19
+            (None, 11),
+            // the first keys in sorted order rather than the first found
+            (Some(2), 2),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(json_request(
+                    http::Method::POST,
+                    "/scan",
+                    &ScanKVsRequest {
+                        namespace: "scanspace".to_string(),
+                        prefix: Some("key-1".to_string()),
+                        limit,
+                    },
+                ))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let res: ScanKVsResponse = json_body(response).await;
+            assert_eq!(res.keys.len(), expected);
+            assert_eq!(res.keys[..2], ["key-1".to_string(), "key-10".to_string()]);
+        }
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_metrics_and_health() {
+        let server = TestServer::start().await;
+        let app = &server.app;
+        put_keys(app, "metricspace").await;
+        let response = app
+            .clone()
+            .oneshot(empty_request(http::Method::GET, "/metrics"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(
+            r#"bonerjams_http_requests_total{method="POST",route="/put",status="200"} 1"#
+        ));
+        assert!(body.contains("bonerjams_cluster_live_members"));
+
+        let response = app
+            .clone()
+            .oneshot(empty_request(http::Method::GET, "/healthz"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(empty_request(http::Method::GET, "/readyz"))
+            .await
+            .unwrap();
+        let res: HealthStatus = json_body(response).await;
+        assert!(res
+            .checks
+            .iter()
+            .any(|check| check.name == "storage" && check.healthy));
+        // a router without access to the storage can't report it as healthy
+        let response = new_router(&server.cluster, API::default())
+            .oneshot(empty_request(http::Method::GET, "/readyz"))
+            .await
+            .unwrap();
+        let res: HealthStatus = json_body(response).await;
+        assert!(!res.healthy);
+        assert!(res
+            .checks
+            .iter()
+            .any(|check| check.name == "storage" && !check.healthy));
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_namespaces() {
+        let server = TestServer::start().await;
+        let (app, admin) = (&server.app, &server.admin);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/namespaces/settingsspace/settings")
+                    .method(http::Method::PUT)
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&NamespaceSettings {
+                            max_value_size: Some(8),
+                            codec: Some(ValueCodec::Json),
+                            max_keys: Some(1),
+                            ..Default::default()
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/namespaces/settingsspace/settings")
+                    .method(http::Method::PUT)
+                    .header(http::header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&NamespaceSettings {
+                            ttl: Some(u64::MAX),
+                            ..Default::default()
+                        })
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let put_settings_req = |key: &str, value: &str| {
+            let mut entries = HashMap::new();
+            entries.insert(
+                "settingsspace".to_string(),
+                vec![KeyValue {
+                    key: key.to_string(),
+                    value: value.as_bytes().to_vec(),
+                }],
+            );
+            Request::builder()
+                .uri("/put")
+                .method(http::Method::POST)
+                .header(http::header::CONTENT_TYPE, "application/json")
+                .body(Body::from(
+                    serde_json::to_vec(&PutKVsRequest { entries }).unwrap(),
+                ))
+                .unwrap()
+        };
+        // too large, and not json
+        for (value, status) in [
+            ("[1,2,3,4,5]", StatusCode::PAYLOAD_TOO_LARGE),
+            ("nojson", StatusCode::BAD_REQUEST),
+        ] {
+            let response = app
+                .clone()
+                .oneshot(put_settings_req("key", value))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), status);
+        }
+        let response = app
+            .clone()
+            .oneshot(put_settings_req("key", "[1,2]"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        // overwriting the key stays within the quota, but a second key does not
+        let response = app
+            .clone()
+            .oneshot(put_settings_req("key", "[1]"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app
+            .clone()
+            .oneshot(put_settings_req("key-2", "[1]"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INSUFFICIENT_STORAGE);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/namespaces")
+                    .method(http::Method::GET)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let res: ListNamespacesResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(res.namespaces, vec!["settingsspace".to_string()]);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/namespaces/settingsspace")
+                    .method(http::Method::GET)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let res: NamespaceInfo = serde_json::from_slice(&body).unwrap();
+        assert_eq!(res.keys, 1);
+        assert_eq!(res.bytes, 3);
+        assert_eq!(res.settings.max_value_size, Some(8));
+
+        let response = admin
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/namespaces/settingsspace")
+                    .method(http::Method::DELETE)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let res: DropNamespaceResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(res.deleted, 1);
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_limits() {
+        let server = TestServer::start().await;
+        let limited_app = server.router(API {
+            limits: RequestLimits {
+                max_body_size: 1024,
+                max_key_length: 8,
+                ..Default::default()
+            },
+            rate_limits: RateLimits {
+                routes: HashMap::from([(
+                    "/get".to_string(),
+                    RateLimit {
+                        requests_per_second: 0.001,
+                        burst: 1,
+                    },
+                )]),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let put_kv_req = |key: &str, value: &[u8]| PutKVsRequest {
+            entries: HashMap::from([(
+                "limitspace".to_string(),
+                vec![KeyValue {
+                    key: key.to_string(),
+                    value: value.to_vec(),
+                }],
+            )]),
+        };
+        let response = limited_app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/put",
+                &put_kv_req("key", &[0; 1024]),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("max_body_size"));
+        // the key repair markers are written under can't be used
+        let response = server
+            .app
+            .clone()
+            .oneshot(json_request(
+                http::Method::POST,
+                "/put",
+                &put_kv_req(repair::MARKER_KEY, b"value"),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let get_long_key = || {
+            json_request(
+                http::Method::POST,
+                "/get",
+                &json!({"entries": {"limitspace": ["a-very-long-key"]}}),
+            )
+        };
+        let response = limited_app.clone().oneshot(get_long_key()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("max_key_length"));
+        // the only token in the bucket was used by the previous request
+        let response = limited_app.clone().oneshot(get_long_key()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_openapi() {
+        let server = TestServer::start().await;
+        let (app, admin) = (&server.app, &server.admin);
+        // the served specification is the generated one
+        let spec = openapi::ApiDoc::openapi();
+        let response = app
+            .clone()
+            .oneshot(empty_request(http::Method::GET, "/openapi.json"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let served: Value = json_body(response).await;
+        assert_eq!(served, serde_json::to_value(&spec).unwrap());
+        // the specification documents exactly the routes which are served
+        let public = buffered_routes::<SledStorage>()
+            .into_iter()
+            .chain(streamed_routes::<SledStorage>())
+            .map(|(path, _)| path)
+            .filter(|path| *path != "/openapi.json");
+        let routed = routed(app, public).await;
+        let mut documented = Vec::new();
+        for (path, item) in spec.paths.paths.iter() {
+            let path = path
+                .split('/')
+                .map(|segment| match segment.strip_prefix('{') {
+                    Some(param) => format!(":{}", param.trim_end_matches('}')),
+                    None => segment.to_string(),
+                })
+                .collect::<Vec<_>>()
+                .join("/");
+            for operation in item.operations.keys() {
+                documented.push((path.clone(), operation.clone()));
+            }
+        }
+        documented.sort_by_key(|(path, operation)| format!("{} {:?}", path, operation));
+        assert_eq!(routed, documented);
+        // and the admin routes are only served by the admin router
+        let admin_paths = || {
+            admin_routes::<SledStorage>()
+                .into_iter()
+                .map(|(path, _)| path)
+        };
+        let served_by_admin = routed(admin, admin_paths()).await;
+        assert!(!served_by_admin.is_empty());
+        let served_by_app = routed(app, admin_paths()).await;
+        assert!(served_by_admin
+            .iter()
+            .all(|route| !served_by_app.contains(route)));
+        // the schema mirrored for the config crate's ConsistencyLevel matches it
+        let schema =
+            serde_json::to_value(&spec.components.unwrap().schemas["ConsistencyLevel"]).unwrap();
+        let variants = schema["enum"].as_array().unwrap();
+        assert_eq!(variants.len(), 5);
+        for variant in variants {
+            serde_json::from_value::<ConsistencyLevel>(variant.clone()).unwrap();
+        }
+
+        server.shutdown().await;
+    }
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_member_leave() {
+        let server = TestServer::start().await;
+        let heartbeat = membership::start_heartbeat(
+            &server.cluster,
+            MemberInfo {
+                node_id: "node-1".to_string(),
+                rpc_address: testing::ephemeral_addr().unwrap().to_string(),
+                ..Default::default()
+            },
+            std::time::Duration::from_millis(100),
+        );
+        let members = || async {
+            let response = server
+                .app
+                .clone()
+                .oneshot(empty_request(http::Method::POST, "/cluster/members"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            json_body::<ClusterMembersResponse>(response).await.members
+        };
+        testing::wait_until(testing::DEFAULT_CONVERGENCE_TIMEOUT, || async {
+            !members().await.is_empty()
+        })
+        .await
+        .unwrap();
+        let res = members().await;
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].info.node_id, "node-1");
+        assert!(res[0].live);
+
+        // a node leaving the cluster is removed from the member list
+        heartbeat.abort();
+        membership::leave(&server.cluster, "node-1").await.unwrap();
+        assert!(members().await.is_empty());
+
+        server.shutdown().await;
+    }
 }
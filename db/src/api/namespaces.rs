@@ -0,0 +1,443 @@
+//! namespace management. namespaces are datacake keyspaces and are created by the
+//! first write to them. datacake has no way to remove a keyspace, so dropping a
+//! namespace deletes every key within it, after which it is no longer listed.
+//! settings are stored in a replicated system keyspace, and cached by each node for
+//! `SETTINGS_CACHE_TTL` so that writes don't read them from storage
+
+use super::error::Error;
+use super::membership::SYSTEM_KEYSPACE_PREFIX;
+use super::ApiState;
+use crate::prelude::{KeyValue, NamespaceInfo, NamespaceSettings, ValueCodec, WrappedDocument};
+use datacake::cluster::{Consistency, Storage};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// the keyspace namespace settings are stored in
+pub const SETTINGS_KEYSPACE: &str = "__bonerjams_namespaces";
+
+/// how long cached settings are used for, after which changes made through other
+/// nodes are observed
+pub const SETTINGS_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// how long counted namespace usage is maintained for before it is counted again, so
+/// that writes made through other nodes are observed
+pub const USAGE_RECOUNT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// the number of keys and bytes within a namespace
+#[derive(Clone, Copy)]
+struct Usage {
+    keys: u64,
+    bytes: u64,
+    counted: Instant,
+}
+
+/// the usage of namespaces with quotas. usage is counted from the documents stored by
+/// this node, then maintained as keys are written and deleted through this node
+#[derive(Default)]
+pub struct UsageCounters {
+    usage: Mutex<HashMap<String, Usage>>,
+}
+
+impl UsageCounters {
+    fn get(&self, namespace: &str) -> Option<Usage> {
+        self.usage
+            .lock()
+            .unwrap()
+            .get(namespace)
+            .filter(|usage| usage.counted.elapsed() < USAGE_RECOUNT_INTERVAL)
+            .copied()
+    }
+    /// applies a change in usage if the namespace is being counted
+    fn apply(&self, namespace: &str, keys: i64, bytes: i64) {
+        if let Some(usage) = self.usage.lock().unwrap().get_mut(namespace) {
+            usage.keys = add(usage.keys, keys);
+            usage.bytes = add(usage.bytes, bytes);
+        }
+    }
+    /// discards the usage of a namespace so that it is counted again when next needed
+    pub fn invalidate(&self, namespace: &str) {
+        self.usage.lock().unwrap().remove(namespace);
+    }
+}
+
+/// adds a change to a count, which can't become negative
+fn add(count: u64, change: i64) -> u64 {
+    (count as i64).saturating_add(change).max(0) as u64
+}
+
+/// the settings of recently used namespaces
+#[derive(Default)]
+pub struct SettingsCache {
+    settings: Mutex<HashMap<String, (Instant, NamespaceSettings)>>,
+}
+
+impl SettingsCache {
+    fn get(&self, namespace: &str) -> Option<NamespaceSettings> {
+        let mut cached = self.settings.lock().unwrap();
+        match cached.get(namespace) {
+            Some((fetched, settings)) if fetched.elapsed() < SETTINGS_CACHE_TTL => {
+                Some(settings.clone())
+            }
+            Some(_) => {
+                cached.remove(namespace);
+                None
+            }
+            None => None,
+        }
+    }
+    fn insert(&self, namespace: &str, settings: NamespaceSettings) {
+        let mut cached = self.settings.lock().unwrap();
+        // stale entries are discarded rather than letting unused namespaces accumulate
+        cached.retain(|_, (fetched, _)| fetched.elapsed() < SETTINGS_CACHE_TTL);
+        cached.insert(namespace.to_string(), (Instant::now(), settings));
+    }
+    fn invalidate(&self, namespace: &str) {
+        self.settings.lock().unwrap().remove(namespace);
+    }
+}
+
+/// returns an error if the namespace is reserved for internal use
+pub fn check_name(namespace: &str) -> Result<(), Error> {
+    if namespace.starts_with(SYSTEM_KEYSPACE_PREFIX) {
+        return Err(Error::CustomError(format!(
+            "namespace {} is reserved",
+            namespace
+        )));
+    }
+    Ok(())
+}
+
+/// returns an error if the settings can't be applied to writes
+pub fn check_settings(settings: &NamespaceSettings) -> Result<(), Error> {
+    if let Some(ttl) = settings.ttl {
+        if expires_at(ttl).is_none() {
+            return Err(Error::CustomError(format!(
+                "ttl of {} seconds is too long",
+                ttl
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// returns the time in milliseconds at which a document written now with `ttl`
+/// expires, or None if it can't be represented
+pub fn expires_at(ttl: u64) -> Option<u64> {
+    ttl.checked_mul(1000)?
+        .checked_add(super::membership::now_millis())
+}
+
+/// returns an error if the value violates the namespace's settings
+pub fn check_value(settings: &NamespaceSettings, kv: &KeyValue) -> Result<(), Error> {
+    if let Some(max_value_size) = settings.max_value_size {
+        if kv.value.len() > max_value_size {
+            return Err(Error::PayloadTooLarge(format!(
+                "value of {} is {} bytes which exceeds the namespace max_value_size of {}",
+                kv.key,
+                kv.value.len(),
+                max_value_size
+            )));
+        }
+    }
+    let valid = match settings.codec {
+        None | Some(ValueCodec::Binary) => true,
+        Some(ValueCodec::Utf8) => std::str::from_utf8(&kv.value).is_ok(),
+        Some(ValueCodec::Json) => serde_json::from_slice::<serde_json::Value>(&kv.value).is_ok(),
+    };
+    if !valid {
+        return Err(Error::CustomError(format!(
+            "value of {} is not valid {:?}",
+            kv.key,
+            settings.codec.unwrap_or(ValueCodec::Binary)
+        )));
+    }
+    Ok(())
+}
+
+/// returns an error if writing `key_values` would exceed the namespace's `max_keys` or
+/// `max_bytes`, otherwise adds the write to the namespace's usage. if the write then
+/// fails the usage must be invalidated with `UsageCounters::invalidate`
+pub async fn check_quota<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    settings: &NamespaceSettings,
+    key_values: &[KeyValue],
+) -> Result<(), Error> {
+    if settings.max_keys.is_none() && settings.max_bytes.is_none() {
+        return Ok(());
+    }
+    let counted = match handle.namespace_usage.get(namespace) {
+        Some(_) => None,
+        None => Some(count_usage(handle, namespace).await?),
+    };
+    // the last value written to a key is the one stored
+    let written = key_values
+        .iter()
+        .map(|kv| (kv.key.as_str(), kv.value.len() as i64))
+        .collect::<HashMap<_, _>>();
+    let existing = sizes(handle, namespace, written.keys().copied()).await?;
+    let keys = written
+        .keys()
+        .filter(|key| !existing.contains_key(**key))
+        .count() as i64;
+    let bytes = written.values().sum::<i64>() - existing.values().sum::<i64>();
+    let mut counters = handle.namespace_usage.usage.lock().unwrap();
+    if let Some(counted) = counted {
+        counters.insert(namespace.to_string(), counted);
+    }
+    let usage = match counters.get_mut(namespace) {
+        Some(usage) => usage,
+        // discarded while the sizes were read, so the write is checked when next counted
+        None => return Ok(()),
+    };
+    let new_keys = add(usage.keys, keys);
+    let new_bytes = add(usage.bytes, bytes);
+    match (settings.max_keys, settings.max_bytes) {
+        (Some(max_keys), _) if new_keys > max_keys => Err(Error::QuotaExceeded(format!(
+            "write would store {} keys in {}, exceeding its max_keys quota of {}",
+            new_keys, namespace, max_keys
+        ))),
+        (_, Some(max_bytes)) if new_bytes > max_bytes => Err(Error::QuotaExceeded(format!(
+            "write would store {} bytes in {}, exceeding its max_bytes quota of {}",
+            new_bytes, namespace, max_bytes
+        ))),
+        _ => {
+            usage.keys = new_keys;
+            usage.bytes = new_bytes;
+            Ok(())
+        }
+    }
+}
+
+/// removes deleted keys from the namespace's usage, if it is being counted
+pub async fn record_delete<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    keys: &[String],
+) -> Result<(), Error> {
+    if handle.namespace_usage.get(namespace).is_none() {
+        return Ok(());
+    }
+    let existing = sizes(handle, namespace, keys.iter().map(String::as_str)).await?;
+    handle.namespace_usage.apply(
+        namespace,
+        -(existing.len() as i64),
+        -existing.values().sum::<i64>(),
+    );
+    Ok(())
+}
+
+/// counts the usage of a namespace from the documents stored by this node
+async fn count_usage<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+) -> Result<Usage, Error> {
+    let docs = documents(handle, namespace)
+        .await
+        .map_err(|err| Error::CustomServerError(err.to_string()))?;
+    Ok(Usage {
+        keys: docs.len() as u64,
+        bytes: docs.iter().map(|doc| doc.data.len() as u64).sum(),
+        counted: Instant::now(),
+    })
+}
+
+/// returns the size of the value of each of the keys which exists
+async fn sizes<'a, S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    keys: impl Iterator<Item = &'a str>,
+) -> Result<HashMap<String, i64>, Error> {
+    Ok(handle
+        .cluster_api
+        .get_many(namespace, keys.map(super::hash_key))
+        .await
+        .map_err(|err| Error::CustomServerError(err.to_string()))?
+        .filter_map(|doc| super::document::decode(&doc.data[..]).ok())
+        .filter(|doc| !doc.is_expired())
+        .map(|doc| (doc.key, doc.data.len() as i64))
+        .collect())
+}
+
+/// returns the settings of a namespace, or the defaults if none have been set
+pub async fn settings<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+) -> anyhow::Result<NamespaceSettings> {
+    if let Some(settings) = handle.namespace_settings.get(namespace) {
+        return Ok(settings);
+    }
+    let doc = handle
+        .cluster_api
+        .get_many(
+            SETTINGS_KEYSPACE,
+            std::iter::once(super::hash_key(namespace)),
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?
+        .next();
+    let settings = match doc {
+        Some(doc) => {
+            let wrapped_document = super::document::decode(&doc.data[..])?;
+            serde_json::from_slice(&wrapped_document.data)?
+        }
+        None => NamespaceSettings::default(),
+    };
+    handle
+        .namespace_settings
+        .insert(namespace, settings.clone());
+    Ok(settings)
+}
+
+pub async fn set_settings<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+    settings: &NamespaceSettings,
+) -> anyhow::Result<()> {
+    let document = super::document::encode(&WrappedDocument {
+        key: namespace.to_string(),
+        data: serde_json::to_vec(settings)?,
+        expires_at: None,
+        content_type: None,
+    });
+    handle
+        .cluster_api
+        .put_many(
+            SETTINGS_KEYSPACE,
+            std::iter::once((super::hash_key(namespace), document)),
+            handle.consistency(),
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    handle.namespace_settings.invalidate(namespace);
+    Ok(())
+}
+
+/// the consistency used for writes and deletes within the namespace
+pub fn consistency<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    settings: &NamespaceSettings,
+) -> Consistency {
+    match settings.consistency {
+        Some(level) => super::consistency(level),
+        None => handle.consistency(),
+    }
+}
+
+/// returns the live, unexpired documents within a namespace stored by this node.
+/// expired documents which are found are deleted
+pub async fn documents<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+) -> anyhow::Result<Vec<WrappedDocument>> {
+    let doc_ids = handle
+        .storage()?
+        .iter_metadata(namespace)
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?
+        .filter_map(|(doc_id, _, is_tombstone)| if is_tombstone { None } else { Some(doc_id) })
+        .collect::<Vec<_>>();
+    let (expired, live): (Vec<_>, Vec<_>) = handle
+        .cluster_api
+        .get_many(namespace, doc_ids.into_iter())
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?
+        .filter_map(|doc| super::document::decode(&doc.data[..]).ok())
+        .partition(|doc| doc.is_expired());
+    if !expired.is_empty() {
+        // every replica expires the documents at the same time, so deleting them on
+        // this node alone is enough. documents are stored under the hash of their key
+        handle
+            .cluster_api
+            .del_many(
+                namespace,
+                expired.iter().map(|doc| super::hash_key(&doc.key)),
+                Consistency::One,
+            )
+            .await
+            .map_err(|err| anyhow::anyhow!("{}", err))?;
+        log::debug!(
+            "purged {} expired documents from {}",
+            expired.len(),
+            namespace
+        );
+    }
+    Ok(live)
+}
+
+/// lists every namespace which holds keys or has settings
+pub async fn list<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+) -> anyhow::Result<Vec<String>> {
+    let keyspaces = handle
+        .storage()?
+        .get_keyspace_list()
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    let mut namespaces = Vec::with_capacity(keyspaces.len());
+    for keyspace in keyspaces {
+        if keyspace.starts_with(SYSTEM_KEYSPACE_PREFIX) {
+            continue;
+        }
+        let has_keys = handle
+            .storage()?
+            .iter_metadata(&keyspace)
+            .await
+            .map_err(|err| anyhow::anyhow!("{}", err))?
+            .any(|(_, _, is_tombstone)| !is_tombstone);
+        if has_keys || settings(handle, &keyspace).await? != NamespaceSettings::default() {
+            namespaces.push(keyspace);
+        }
+    }
+    namespaces.sort();
+    Ok(namespaces)
+}
+
+pub async fn info<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+) -> anyhow::Result<NamespaceInfo> {
+    let docs = documents(handle, namespace).await?;
+    Ok(NamespaceInfo {
+        name: namespace.to_string(),
+        keys: docs.len() as u64,
+        bytes: docs.iter().map(|doc| doc.data.len() as u64).sum(),
+        settings: settings(handle, namespace).await?,
+    })
+}
+
+/// deletes every key and blob within the namespace along with its settings,
+/// returning the number of keys deleted
+pub async fn drop<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+) -> anyhow::Result<u64> {
+    let consistency = consistency(handle, &settings(handle, namespace).await?);
+    let doc_ids = handle
+        .storage()?
+        .iter_metadata(namespace)
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?
+        .filter_map(|(doc_id, _, is_tombstone)| if is_tombstone { None } else { Some(doc_id) })
+        .collect::<Vec<_>>();
+    let deleted = doc_ids.len() as u64;
+    handle
+        .cluster_api
+        .del_many(namespace, doc_ids.into_iter(), consistency)
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    super::blobs::drop_namespace(handle, namespace).await?;
+    handle
+        .cluster_api
+        .del_many(
+            SETTINGS_KEYSPACE,
+            std::iter::once(super::hash_key(namespace)),
+            handle.consistency(),
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    handle.namespace_settings.invalidate(namespace);
+    handle.namespace_usage.invalidate(namespace);
+    Ok(deleted)
+}
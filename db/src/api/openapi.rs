@@ -0,0 +1,111 @@
+//! the openapi specification of the http api, generated from the handlers in
+//! `kv_server` and the types in `api::types`. served at `/openapi.json`, and with the
+//! `docs-ui` feature browsable at `/docs`
+
+use super::kv_server;
+use crate::prelude::{
+    BlobChunk, BlobInfo, ClusterKeyspacesResponse, ClusterMember, ClusterMembersResponse,
+    ClusterStatistics, CreateUploadRequest, DeleteKVsRequest, GetKVsRequest, GetKVsResponse,
+    HealthCheck, HealthStatus, KeyValue, KeyspaceInfo, ListNamespacesResponse, MemberInfo,
+    NamespaceInfo, NamespaceSettings, PutKVsRequest, ScanKVsRequest, ScanKVsResponse, Status,
+    SyncState, UploadStatus, ValueCodec, WrappedDocument,
+};
+use axum::Json;
+use utoipa::{OpenApi, ToSchema};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "bonerjams",
+        description = "a distributed, replicated key-value store. the key-value routes \
+            also accept and return messagepack and cbor, selected by the `Content-Type` \
+            and `Accept` headers. the admin routes, such as draining a node, are served \
+            on `api.admin.listen_address` and are not described here"
+    ),
+    paths(
+        kv_server::put_value,
+        kv_server::get_value,
+        kv_server::remove_value,
+        kv_server::scan_keys,
+        kv_server::get_key,
+        kv_server::put_key,
+        kv_server::delete_key,
+        kv_server::put_blob,
+        kv_server::get_blob,
+        kv_server::delete_blob,
+        kv_server::create_upload,
+        kv_server::get_upload,
+        kv_server::append_upload,
+        kv_server::complete_upload,
+        kv_server::abort_upload,
+        kv_server::list_namespaces,
+        kv_server::namespace_info,
+        kv_server::get_namespace_settings,
+        kv_server::put_namespace_settings,
+        kv_server::cluster_stat,
+        kv_server::cluster_members,
+        kv_server::cluster_keyspaces,
+        kv_server::metrics,
+        kv_server::healthz,
+        kv_server::readyz,
+    ),
+    components(schemas(
+        BlobChunk,
+        BlobInfo,
+        ClusterKeyspacesResponse,
+        ClusterMember,
+        ClusterMembersResponse,
+        ClusterStatistics,
+        ConsistencyLevel,
+        CreateUploadRequest,
+        DeleteKVsRequest,
+        GetKVsRequest,
+        GetKVsResponse,
+        HealthCheck,
+        HealthStatus,
+        KeyValue,
+        KeyspaceInfo,
+        ListNamespacesResponse,
+        MemberInfo,
+        NamespaceInfo,
+        NamespaceSettings,
+        PutKVsRequest,
+        ScanKVsRequest,
+        ScanKVsResponse,
+        Status,
+        SyncState,
+        UploadStatus,
+        ValueCodec,
+        WrappedDocument,
+    )),
+    tags(
+        (name = "kv", description = "reading and writing key-values"),
+        (name = "blobs", description = "streaming large values in chunks"),
+        (name = "namespaces", description = "managing namespaces and their settings"),
+        (name = "cluster", description = "inspecting the cluster"),
+        (name = "health", description = "health checks and metrics"),
+    )
+)]
+pub struct ApiDoc;
+
+/// the schema of `bonerjams_config::cluster::ConsistencyLevel`, which is mirrored here
+/// so that the config crate doesn't depend on utoipa
+#[allow(dead_code)]
+#[derive(ToSchema)]
+enum ConsistencyLevel {
+    /// only the local node must acknowledge the operation
+    One,
+    /// a majority of nodes across the cluster must acknowledge the operation
+    Quorum,
+    /// a majority of nodes in the local data center must acknowledge the operation
+    LocalQuorum,
+    /// a majority of nodes in each data center must acknowledge the operation
+    EachQuorum,
+    /// every node must acknowledge the operation
+    All,
+}
+
+/// returns the openapi specification
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
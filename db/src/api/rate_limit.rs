@@ -0,0 +1,173 @@
+//! per-client token bucket rate limiting, configured by `API.rate_limits`. route
+//! limits are applied by the `limit_requests` middleware, while namespace limits
+//! are applied by the handlers once the request has been parsed
+
+use super::error::Error;
+use axum::extract::{ConnectInfo, MatchedPath, State};
+use axum::http::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use bonerjams_config::{RateLimit, RateLimits};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// the maximum number of buckets. once reached, full buckets are discarded as they
+/// are equivalent to a bucket which doesn't exist, followed by the least recently
+/// used buckets
+const MAX_BUCKETS: usize = 10_000;
+
+/// how often full buckets are discarded regardless of the number of buckets
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// identifies the client making a request, inserted into the request extensions
+/// by `limit_requests`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ClientId(pub String);
+
+impl ClientId {
+    /// identifies a client by its ip address, which requires the router to be served
+    /// with `into_make_service_with_connect_info`. headers are ignored as a client can
+    /// set them to anything, giving itself a new bucket for every request
+    pub fn from_request<B>(request: &Request<B>) -> Self {
+        Self::new(
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| *addr),
+        )
+    }
+    pub fn new(addr: Option<SocketAddr>) -> Self {
+        match addr {
+            Some(addr) => Self(format!("ip:{}", addr.ip())),
+            None => Self("unknown".to_string()),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    rate: f64,
+    burst: f64,
+}
+
+impl Bucket {
+    /// refills the bucket for the time elapsed since it was last updated
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+    }
+}
+
+#[derive(Default)]
+struct Buckets {
+    /// buckets keyed by the limited scope, and the client
+    buckets: HashMap<(String, ClientId), Bucket>,
+    pruned: Option<Instant>,
+}
+
+impl Buckets {
+    /// discards full buckets, then the least recently used buckets until there is
+    /// room for another. a discarded bucket which wasn't full is refilled, so clients
+    /// only gain tokens once there are more than `MAX_BUCKETS` clients
+    fn prune(&mut self, now: Instant) {
+        self.pruned = Some(now);
+        self.buckets.retain(|_, bucket| {
+            bucket.refill(now);
+            bucket.tokens < bucket.burst
+        });
+        if self.buckets.len() < MAX_BUCKETS {
+            return;
+        }
+        let mut updated = self
+            .buckets
+            .values()
+            .map(|bucket| bucket.updated)
+            .collect::<Vec<_>>();
+        updated.sort_unstable();
+        let cutoff = updated[self.buckets.len() - MAX_BUCKETS];
+        self.buckets.retain(|_, bucket| bucket.updated > cutoff);
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimits,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimits) -> Self {
+        Self {
+            config,
+            buckets: Default::default(),
+        }
+    }
+    /// applies the default limit, and the limit for the route if one is set
+    pub fn check_route(&self, route: &str, client: &ClientId) -> Result<(), Error> {
+        if let Some(limit) = self.config.default.as_ref() {
+            self.take("*", limit, client)?;
+        }
+        if let Some(limit) = self.config.routes.get(route) {
+            self.take(&format!("route:{}", route), limit, client)?;
+        }
+        Ok(())
+    }
+    /// applies the limit for the namespace if one is set
+    pub fn check_namespace(&self, namespace: &str, client: &ClientId) -> Result<(), Error> {
+        match self.config.namespaces.get(namespace) {
+            Some(limit) => self.take(&format!("namespace:{}", namespace), limit, client),
+            None => Ok(()),
+        }
+    }
+    fn take(&self, scope: &str, limit: &RateLimit, client: &ClientId) -> Result<(), Error> {
+        let now = Instant::now();
+        let burst = limit.burst.max(1) as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let prune_due = buckets
+            .pruned
+            .map_or(true, |pruned| now.duration_since(pruned) >= PRUNE_INTERVAL);
+        if prune_due || buckets.buckets.len() >= MAX_BUCKETS {
+            buckets.prune(now);
+        }
+        let bucket = buckets
+            .buckets
+            .entry((scope.to_string(), client.clone()))
+            .or_insert(Bucket {
+                tokens: burst,
+                updated: now,
+                rate: limit.requests_per_second,
+                burst,
+            });
+        bucket.refill(now);
+        if bucket.tokens < 1.0 {
+            return Err(Error::TooManyRequests(format!(
+                "rate limit of {} requests per second exceeded for {}",
+                limit.requests_per_second, scope
+            )));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+/// middleware identifying the client and applying route rate limits
+pub async fn limit_requests<B>(
+    State(limiter): State<Arc<RateLimiter>>,
+    mut request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let client = ClientId::from_request(&request);
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_default();
+    if let Err(err) = limiter.check_route(&route, &client) {
+        return err.into_response();
+    }
+    request.extensions_mut().insert(client);
+    next.run(request).await
+}
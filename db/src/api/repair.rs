@@ -0,0 +1,57 @@
+//! anti-entropy repair controls. datacake repairs keyspaces on the interval set by
+//! `ClusterOpts.repair_interval`, and synchronises a keyspace with a peer whenever
+//! the peer observes a change to it. as datacake has no way to trigger a repair
+//! directly, an on-demand repair writes a marker document into the namespace and
+//! deletes it again. the namespace's timestamp changes, so every peer synchronises the
+//! namespace with this node. the marker is stored under the reserved `MARKER_KEY` and
+//! is written already expired, so it is never read as a key. running a repair on each
+//! node after a partition brings the whole cluster back in sync
+
+use super::membership::now_millis;
+use super::ApiState;
+use crate::prelude::{ClusterStatistics, SyncState, WrappedDocument};
+use datacake::cluster::{Consistency, Storage};
+
+/// the key repair markers are written under, which can't be used by clients
+pub const MARKER_KEY: &str = "__bonerjams_repair";
+
+/// triggers synchronisation of `namespace` between this node and its peers
+pub async fn trigger<S: Storage + Send + Sync + 'static>(
+    handle: &ApiState<S>,
+    namespace: &str,
+) -> anyhow::Result<()> {
+    let doc_id = super::hash_key(MARKER_KEY);
+    let marker = super::document::encode(&WrappedDocument {
+        key: MARKER_KEY.to_string(),
+        data: Vec::new(),
+        expires_at: Some(now_millis()),
+        content_type: None,
+    });
+    // only the local node needs to observe the change, peers pull it when syncing
+    handle
+        .cluster_api
+        .put_many(
+            namespace,
+            std::iter::once((doc_id, marker)),
+            Consistency::One,
+        )
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    handle
+        .cluster_api
+        .del_many(namespace, std::iter::once(doc_id), Consistency::One)
+        .await
+        .map_err(|err| anyhow::anyhow!("{}", err))?;
+    log::info!("triggered repair of {}", namespace);
+    Ok(())
+}
+
+/// summarises the synchronisation tasks reported by the cluster statistics
+pub fn sync_state(stats: &ClusterStatistics) -> SyncState {
+    SyncState {
+        ongoing_tasks: stats.num_ongoing_sync_tasks,
+        slow_tasks: stats.num_slow_sync_tasks,
+        failed_tasks: stats.num_failed_sync_tasks,
+        synced: stats.num_ongoing_sync_tasks == 0 && stats.num_failed_sync_tasks == 0,
+    }
+}
@@ -0,0 +1,149 @@
+//! runs a key-value server node from its `Configuration`. the node joins the cluster,
+//! serves the http api, along with the grpc api when it is enabled, writes heartbeats
+//! and collects blob garbage until it is shut down or drained, after which it leaves
+//! the cluster
+
+use super::drain::Drain;
+use super::membership;
+use crate::prelude::MemberInfo;
+use age::secrecy::ExposeSecret;
+use anyhow::{anyhow, Context, Result};
+use bonerjams_config::{logging, Configuration};
+use datacake::cluster::{ConnectionConfig, DCAwareSelector, DatacakeCluster};
+use datacake_sled::SledStorage;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// how long a drained node keeps serving before it leaves the cluster, so that those
+/// polling the drain, such as `cli cluster drain`, see it complete
+const DRAINED_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// installs the global subscriber from the logging configuration. with the `otel`
+/// feature spans are also exported to the collector configured by `tracing`
+pub fn init_logging(conf: &Configuration) -> Result<()> {
+    #[allow(unused_mut)]
+    let mut layers: Vec<logging::BoxedLayer> = Vec::new();
+    #[cfg(feature = "otel")]
+    if let Some(layer) = super::trace::otel::layer::<logging::Subscriber>(&conf.tracing)? {
+        layers.push(Box::new(layer));
+    }
+    logging::init_with(&conf.logging, layers)
+}
+
+/// runs the node until `shutdown` completes or the node is drained
+pub async fn run(conf: &Configuration, shutdown: impl Future<Output = ()>) -> Result<()> {
+    let rpc_address = parse_address(&conf.cluster.cluster_rpc_endpoint)?;
+    let node_address = parse_address(&conf.cluster.cluster_node_endpoint)?;
+    let storage = SledStorage::open(&conf.db.path)
+        .map_err(|err| anyhow!("failed to open storage {:#?}", err))?;
+    let cluster = DatacakeCluster::connect(
+        identity(&format!("{}.identity", conf.db.path))?,
+        ConnectionConfig::new(rpc_address, node_address, conf.cluster.seeds.clone()),
+        storage.clone(),
+        DCAwareSelector::default(),
+        super::cluster_options(&conf.cluster)?,
+    )
+    .await
+    .map_err(|err| anyhow!("failed to join the cluster {:#?}", err))?;
+
+    let drain = Arc::new(Drain::default());
+    let node_id = conf.cluster.cluster_node_endpoint.clone();
+    let state = super::RouterBuilder::new(&cluster, conf.api.clone())
+        .storage(Arc::new(storage))
+        .drain(drain.clone())
+        .node_id(node_id.clone())
+        .state();
+    let (stop_tx, stop_rx) = watch::channel(false);
+    let stopped = |mut stop_rx: watch::Receiver<bool>| async move {
+        let _ = stop_rx.changed().await;
+    };
+    let mut servers = vec![tokio::spawn(super::serve(
+        TcpListener::bind(&conf.api.listen_address)
+            .with_context(|| format!("failed to listen on {}", conf.api.listen_address))?,
+        super::new_router_with_state(state.clone(), conf.api.clone()),
+        stopped(stop_rx.clone()),
+    ))];
+    if let Some(listen_address) = &conf.api.admin.listen_address {
+        servers.push(tokio::spawn(super::serve(
+            TcpListener::bind(listen_address)
+                .with_context(|| format!("failed to listen on {}", listen_address))?,
+            super::new_admin_router(state.clone()),
+            stopped(stop_rx.clone()),
+        )));
+    }
+    if let (true, Some(listen_address)) = (conf.api.grpc.enabled, &conf.api.grpc.listen_address) {
+        let listener = TcpListener::bind(listen_address)
+            .with_context(|| format!("failed to listen on {}", listen_address))?;
+        let (state, api_conf, stopped) = (state.clone(), conf.api.clone(), stopped(stop_rx));
+        servers.push(tokio::spawn(async move {
+            super::grpc::serve(state, &api_conf, listener, stopped).await
+        }));
+    }
+    let heartbeat = membership::start_heartbeat(
+        &cluster,
+        MemberInfo {
+            node_id: node_id.clone(),
+            rpc_address: node_address.to_string(),
+            api_address: Some(conf.api.listen_address.clone()),
+            data_center: conf.cluster.data_center.clone(),
+            ..Default::default()
+        },
+        membership::DEFAULT_HEARTBEAT_INTERVAL,
+    );
+    let gc = super::blobs::start_gc(state);
+    log::info!("serving on {}", conf.api.listen_address);
+
+    tokio::pin!(shutdown);
+    tokio::select! {
+        _ = &mut shutdown => log::info!("shutting down"),
+        _ = drain.drained() => {
+            log::info!("node drained, leaving the cluster in {:?}", DRAINED_GRACE_PERIOD);
+            tokio::select! {
+                _ = &mut shutdown => {}
+                _ = tokio::time::sleep(DRAINED_GRACE_PERIOD) => {}
+            }
+        }
+    }
+    let _ = stop_tx.send(true);
+    heartbeat.abort();
+    gc.abort();
+    for server in servers {
+        if let Err(err) = server.await? {
+            log::error!("server failed {:#?}", err);
+        }
+    }
+    if let Err(err) = membership::leave(&cluster, &node_id).await {
+        log::warn!("failed to remove membership {:#?}", err);
+    }
+    cluster.shutdown().await;
+    #[cfg(feature = "otel")]
+    super::trace::otel::shutdown();
+    Ok(())
+}
+
+fn parse_address(address: &str) -> Result<SocketAddr> {
+    address
+        .parse()
+        .with_context(|| format!("invalid address {}", address))
+}
+
+/// loads the node's identity from `path`, generating and saving one if it doesn't
+/// exist, so that the node keeps its identity across restarts
+fn identity(path: &str) -> Result<age::x25519::Identity> {
+    match std::fs::read_to_string(path) {
+        Ok(identity) => identity
+            .trim()
+            .parse()
+            .map_err(|err| anyhow!("invalid identity in {} {}", path, err)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            let identity = age::x25519::Identity::generate();
+            std::fs::write(path, identity.to_string().expose_secret())
+                .with_context(|| format!("failed to write {}", path))?;
+            Ok(identity)
+        }
+        Err(err) => Err(err).with_context(|| format!("failed to read {}", path)),
+    }
+}
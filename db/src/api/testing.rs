@@ -0,0 +1,940 @@
+//! a harness for running a multi-node cluster within a single process, allowing
+//! replication and consistency behaviour to be tested. every node uses temporary
+//! storage and ephemeral ports, and nodes are spread across simulated data centers
+
+use super::drain::Drain;
+use super::membership;
+use crate::prelude::MemberInfo;
+use anyhow::{anyhow, Result};
+use bonerjams_config::cluster::ClusterOpts;
+use bonerjams_config::API;
+use datacake::cluster::{ConnectionConfig, DCAwareSelector, DatacakeCluster, DatacakeHandle};
+use datacake_sled::SledStorage;
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// the default time to wait for the cluster to converge
+pub const DEFAULT_CONVERGENCE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// how often conditions are checked while waiting
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+pub struct TestClusterBuilder {
+    nodes: usize,
+    data_centers: usize,
+    api_conf: API,
+    cluster_conf: ClusterOpts,
+    heartbeat_interval: Duration,
+}
+
+impl TestClusterBuilder {
+    pub fn new(nodes: usize) -> Self {
+        Self {
+            nodes,
+            data_centers: 1,
+            api_conf: API::default(),
+            cluster_conf: ClusterOpts::default(),
+            heartbeat_interval: Duration::from_millis(250),
+        }
+    }
+    /// spreads the nodes across `data_centers` data centers in a round robin fashion
+    pub fn data_centers(mut self, data_centers: usize) -> Self {
+        self.data_centers = data_centers.max(1);
+        self
+    }
+    /// the api configuration used by every node, listen addresses are ignored
+    pub fn api_config(mut self, api_conf: API) -> Self {
+        self.api_conf = api_conf;
+        self
+    }
+    /// the cluster configuration used by every node, addresses, seeds and
+    /// data centers are ignored
+    pub fn cluster_config(mut self, cluster_conf: ClusterOpts) -> Self {
+        self.cluster_conf = cluster_conf;
+        self
+    }
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+    /// starts every node, returning once the cluster has converged
+    pub async fn build(self) -> Result<TestCluster> {
+        let mut addrs = Vec::with_capacity(self.nodes);
+        for _ in 0..self.nodes {
+            addrs.push((ephemeral_addr()?, ephemeral_addr()?, ephemeral_addr()?));
+        }
+        let mut cluster = TestCluster {
+            nodes: Vec::with_capacity(self.nodes),
+            api_conf: self.api_conf,
+            cluster_conf: self.cluster_conf,
+            heartbeat_interval: self.heartbeat_interval,
+        };
+        for (idx, (rpc_addr, api_addr, admin_addr)) in addrs.iter().enumerate() {
+            cluster.nodes.push(TestNode {
+                node_id: format!("node-{}", idx),
+                data_center: format!("dc-{}", idx % self.data_centers),
+                rpc_addr: *rpc_addr,
+                api_addr: *api_addr,
+                admin_addr: *admin_addr,
+                // every node uses every other node as a seed so that any node can be killed
+                seeds: addrs
+                    .iter()
+                    .enumerate()
+                    .filter(|(other, _)| *other != idx)
+                    .map(|(_, (rpc_addr, _, _))| rpc_addr.to_string())
+                    .collect(),
+                identity: age::x25519::Identity::generate(),
+                storage: SledStorage::open_temporary()
+                    .map_err(|err| anyhow!("failed to open storage {:#?}", err))?,
+                running: None,
+            });
+        }
+        for idx in 0..cluster.nodes.len() {
+            cluster.start(idx).await?;
+        }
+        cluster
+            .wait_for_convergence(DEFAULT_CONVERGENCE_TIMEOUT)
+            .await?;
+        Ok(cluster)
+    }
+}
+
+/// a node within a `TestCluster`. the node keeps its storage, identity and
+/// addresses when restarted
+pub struct TestNode {
+    pub node_id: String,
+    pub data_center: String,
+    pub rpc_addr: SocketAddr,
+    pub api_addr: SocketAddr,
+    /// the address of the node's admin api
+    pub admin_addr: SocketAddr,
+    seeds: Vec<String>,
+    identity: age::x25519::Identity,
+    storage: SledStorage,
+    running: Option<RunningNode>,
+}
+
+impl TestNode {
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+    /// the url of the node's http api
+    pub fn url(&self) -> String {
+        format!("http://{}", self.api_addr)
+    }
+    /// the url of the node's admin api
+    pub fn admin_url(&self) -> String {
+        format!("http://{}", self.admin_addr)
+    }
+    /// the storage of the node, which remains accessible while the node is killed
+    pub fn storage(&self) -> &SledStorage {
+        &self.storage
+    }
+}
+
+struct RunningNode {
+    cluster: DatacakeCluster<SledStorage>,
+    drain: Arc<Drain>,
+    heartbeat: JoinHandle<()>,
+    servers: Vec<JoinHandle<()>>,
+    shutdown: watch::Sender<bool>,
+}
+
+pub struct TestCluster {
+    nodes: Vec<TestNode>,
+    api_conf: API,
+    cluster_conf: ClusterOpts,
+    heartbeat_interval: Duration,
+}
+
+impl TestCluster {
+    pub fn nodes(&self) -> &[TestNode] {
+        &self.nodes
+    }
+    pub fn node(&self, idx: usize) -> &TestNode {
+        &self.nodes[idx]
+    }
+    /// returns a handle to the given node, or None if the node is not running
+    pub fn handle(&self, idx: usize) -> Option<DatacakeHandle<SledStorage>> {
+        self.nodes[idx]
+            .running
+            .as_ref()
+            .map(|running| running.cluster.handle())
+    }
+    #[cfg(feature = "client")]
+    pub fn client(&self, idx: usize) -> Result<super::client::KVClient> {
+        super::client::KVClient::new(&self.nodes[idx].url())
+    }
+    /// returns a client for the admin api of the given node
+    #[cfg(feature = "client")]
+    pub fn admin_client(&self, idx: usize) -> Result<super::client::KVClient> {
+        super::client::KVClient::new(&self.nodes[idx].admin_url())
+    }
+    /// returns a client which fails over between every node, in order
+    #[cfg(feature = "client")]
+    pub fn cluster_client(&self) -> Result<super::client::KVClient> {
+        super::client::KVClient::builder()
+            .urls(self.nodes.iter().map(|node| node.url()))
+            .build()
+    }
+    /// stops the given node, simulating a crash. the node's storage is retained
+    pub async fn kill(&mut self, idx: usize) -> Result<()> {
+        let running = self.nodes[idx]
+            .running
+            .take()
+            .ok_or_else(|| anyhow!("{} is not running", self.nodes[idx].node_id))?;
+        running.heartbeat.abort();
+        let _ = running.shutdown.send(true);
+        for server in running.servers {
+            server.await?;
+        }
+        running.cluster.shutdown().await;
+        Ok(())
+    }
+    /// waits for the given node to be drained through its api, then stops it
+    pub async fn shutdown_drained(&mut self, idx: usize, timeout: Duration) -> Result<()> {
+        let drain = self.nodes[idx]
+            .running
+            .as_ref()
+            .map(|running| running.drain.clone())
+            .ok_or_else(|| anyhow!("{} is not running", self.nodes[idx].node_id))?;
+        tokio::time::timeout(timeout, drain.drained())
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "{} was not drained within {:?}",
+                    self.nodes[idx].node_id,
+                    timeout
+                )
+            })?;
+        self.kill(idx).await
+    }
+    /// restarts a killed node with its previous storage and addresses
+    pub async fn restart(&mut self, idx: usize) -> Result<()> {
+        self.start(idx).await
+    }
+    /// waits until every running node reports every other running node as live,
+    /// and has no synchronisation tasks in progress
+    pub async fn wait_for_convergence(&self, timeout: Duration) -> Result<()> {
+        let running = self.nodes.iter().filter(|node| node.is_running()).count() as u64;
+        wait_until(timeout, move || async move {
+            self.nodes
+                .iter()
+                .filter_map(|node| node.running.as_ref())
+                .all(|node| {
+                    let stats = node.cluster.handle().statistics();
+                    stats.num_live_members() == running && stats.num_ongoing_sync_tasks() == 0
+                })
+        })
+        .await
+        .map_err(|_| anyhow!("cluster did not converge within {:?}", timeout))
+    }
+    /// stops every running node
+    pub async fn shutdown(mut self) -> Result<()> {
+        for idx in 0..self.nodes.len() {
+            if self.nodes[idx].is_running() {
+                self.kill(idx).await?;
+            }
+        }
+        Ok(())
+    }
+    async fn start(&mut self, idx: usize) -> Result<()> {
+        let node = &mut self.nodes[idx];
+        if node.is_running() {
+            return Err(anyhow!("{} is already running", node.node_id));
+        }
+        let cluster = DatacakeCluster::connect(
+            node.identity.clone(),
+            ConnectionConfig::new(node.rpc_addr, node.rpc_addr, node.seeds.clone()),
+            node.storage.clone(),
+            DCAwareSelector::default(),
+            super::cluster_options(&ClusterOpts {
+                data_center: Some(node.data_center.clone()),
+                ..self.cluster_conf.clone()
+            })?,
+        )
+        .await
+        .map_err(|err| anyhow!("failed to start {} {:#?}", node.node_id, err))?;
+        let drain = Arc::new(Drain::default());
+        let state = super::RouterBuilder::new(&cluster, self.api_conf.clone())
+            .storage(Arc::new(node.storage.clone()))
+            .drain(drain.clone())
+            .node_id(node.node_id.clone())
+            .state();
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let mut servers = Vec::with_capacity(2);
+        // the ports are reused so that the node keeps its addresses across restarts
+        for (addr, router) in [
+            (
+                node.api_addr,
+                super::new_router_with_state(state.clone(), self.api_conf.clone()),
+            ),
+            (node.admin_addr, super::new_admin_router(state.clone())),
+        ] {
+            let mut shutdown_rx = shutdown_rx.clone();
+            let server = super::serve(TcpListener::bind(addr)?, router, async move {
+                let _ = shutdown_rx.changed().await;
+            });
+            let node_id = node.node_id.clone();
+            servers.push(tokio::spawn(async move {
+                if let Err(err) = server.await {
+                    log::error!("{} server on {} failed {:#?}", node_id, addr, err);
+                }
+            }));
+        }
+        let heartbeat = membership::start_heartbeat(
+            &cluster,
+            MemberInfo {
+                node_id: node.node_id.clone(),
+                rpc_address: node.rpc_addr.to_string(),
+                api_address: Some(node.api_addr.to_string()),
+                data_center: Some(node.data_center.clone()),
+                ..Default::default()
+            },
+            self.heartbeat_interval,
+        );
+        node.running = Some(RunningNode {
+            cluster,
+            drain,
+            heartbeat,
+            servers,
+            shutdown,
+        });
+        Ok(())
+    }
+}
+
+/// polls `condition` until it returns true, returning an error if `timeout` elapses first
+pub async fn wait_until<F, Fut>(timeout: Duration, mut condition: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = bool>,
+{
+    tokio::time::timeout(timeout, async {
+        while !condition().await {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("condition not met within {:?}", timeout))
+}
+
+/// returns a local address with a port chosen by the os. the port is released
+/// before returning so there is a small window where it could be taken
+pub fn ephemeral_addr() -> Result<SocketAddr> {
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use datacake::cluster::Consistency;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cluster_replication() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut cluster = TestClusterBuilder::new(3)
+            .data_centers(2)
+            .build()
+            .await
+            .unwrap();
+        let doc = |value: &str| std::iter::once((1, value.as_bytes().to_vec()));
+
+        // writes at `All` are visible on every node once acknowledged
+        cluster
+            .handle(0)
+            .unwrap()
+            .put_many("replicated", doc("foo"), Consistency::All)
+            .await
+            .unwrap();
+        for idx in 0..3 {
+            let docs = cluster
+                .handle(idx)
+                .unwrap()
+                .get_many("replicated", std::iter::once(1))
+                .await
+                .unwrap()
+                .collect::<Vec<_>>();
+            assert_eq!(docs.len(), 1);
+            assert_eq!(docs[0].data.to_vec(), b"foo".to_vec());
+        }
+
+        // a quorum remains available with a single node down, but `All` does not
+        cluster.kill(2).await.unwrap();
+        cluster
+            .wait_for_convergence(DEFAULT_CONVERGENCE_TIMEOUT)
+            .await
+            .unwrap();
+        let handle = cluster.handle(0).unwrap();
+        handle
+            .put_many("replicated", doc("bar"), Consistency::Quorum)
+            .await
+            .unwrap();
+        assert!(handle
+            .put_many("replicated", doc("baz"), Consistency::All)
+            .await
+            .is_err());
+
+        // the restarted node keeps its storage and rejoins the cluster
+        cluster.restart(2).await.unwrap();
+        cluster
+            .wait_for_convergence(DEFAULT_CONVERGENCE_TIMEOUT)
+            .await
+            .unwrap();
+        handle
+            .put_many("replicated", doc("qux"), Consistency::All)
+            .await
+            .unwrap();
+        let docs = cluster
+            .handle(2)
+            .unwrap()
+            .get_many("replicated", std::iter::once(1))
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(docs[0].data.to_vec(), b"qux".to_vec());
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_drain_handoff() {
+        use crate::prelude::KeyValue;
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut cluster = TestClusterBuilder::new(2)
+            .cluster_config(ClusterOpts {
+                repair_interval: Some("1s".to_string()),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let client = cluster.client(1).unwrap();
+        let admin = cluster.admin_client(1).unwrap();
+        // written only to the node being drained while its peer is down
+        cluster.kill(0).await.unwrap();
+        cluster
+            .handle(1)
+            .unwrap()
+            .put_many(
+                "handoff",
+                (0..10).map(|idx| (idx, vec![idx as u8])),
+                Consistency::One,
+            )
+            .await
+            .unwrap();
+        cluster.restart(0).await.unwrap();
+
+        admin.drain().await.unwrap();
+        let namespaces = ["handoff".to_string()];
+        assert!(client
+            .put_key_values(
+                &namespaces,
+                &mut [vec![KeyValue {
+                    key: "rejected".to_string(),
+                    value: b"value".to_vec(),
+                }]],
+            )
+            .await
+            .is_err());
+        cluster
+            .shutdown_drained(1, DEFAULT_CONVERGENCE_TIMEOUT)
+            .await
+            .unwrap();
+        let docs = cluster
+            .handle(0)
+            .unwrap()
+            .get_many("handoff", 0..10)
+            .await
+            .unwrap()
+            .count();
+        assert_eq!(docs, 10);
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_repair() {
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut cluster = TestClusterBuilder::new(2)
+            .cluster_config(ClusterOpts {
+                repair_interval: Some("10m".to_string()),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        // written only to the first node while its peer is down
+        cluster.kill(1).await.unwrap();
+        cluster
+            .handle(0)
+            .unwrap()
+            .put_many(
+                "repaired",
+                std::iter::once((1, b"foo".to_vec())),
+                Consistency::One,
+            )
+            .await
+            .unwrap();
+        cluster.restart(1).await.unwrap();
+        cluster
+            .wait_for_convergence(DEFAULT_CONVERGENCE_TIMEOUT)
+            .await
+            .unwrap();
+
+        // the document reaches the peer well before the repair interval elapses
+        cluster
+            .admin_client(0)
+            .unwrap()
+            .repair("repaired")
+            .await
+            .unwrap();
+        let handle = cluster.handle(1).unwrap();
+        wait_until(Duration::from_secs(30), || {
+            let handle = handle.clone();
+            async move {
+                handle
+                    .get_many("repaired", std::iter::once(1))
+                    .await
+                    .map(|docs| docs.count() == 1)
+                    .unwrap_or(false)
+            }
+        })
+        .await
+        .unwrap();
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_cluster_members() {
+        use crate::prelude::KeyValue;
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut cluster = TestClusterBuilder::new(2).build().await.unwrap();
+        let client = cluster.client(0).unwrap();
+        let members = client.cluster_members().await.unwrap().members;
+        assert_eq!(members.len(), 2);
+        assert!(members.iter().all(|member| member.live));
+
+        let namespaces = ["memberspace".to_string()];
+        client
+            .put_key_values(
+                &namespaces,
+                &mut [vec![
+                    KeyValue {
+                        key: "kept".to_string(),
+                        value: b"value".to_vec(),
+                    },
+                    KeyValue {
+                        key: "deleted".to_string(),
+                        value: b"value".to_vec(),
+                    },
+                ]],
+            )
+            .await
+            .unwrap();
+        client
+            .delete_key_values(&namespaces, &mut [vec!["deleted".to_string()]])
+            .await
+            .unwrap();
+        // the keyspaces used internally, such as the membership keyspace, are hidden
+        let keyspaces = client.cluster_keyspaces().await.unwrap().keyspaces;
+        assert_eq!(keyspaces.len(), 1);
+        assert_eq!(keyspaces[0].name, "memberspace");
+        assert_eq!(keyspaces[0].documents, 1);
+        assert_eq!(keyspaces[0].tombstones, 1);
+
+        // a killed node stops sending heartbeats, so is no longer reported as live
+        cluster.kill(1).await.unwrap();
+        wait_until(DEFAULT_CONVERGENCE_TIMEOUT, || async {
+            client
+                .cluster_members()
+                .await
+                .map(|res| {
+                    res.members
+                        .iter()
+                        .any(|member| member.info.node_id == "node-1" && !member.live)
+                })
+                .unwrap_or(false)
+        })
+        .await
+        .unwrap();
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_failover() {
+        use crate::prelude::KeyValue;
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let mut cluster = TestClusterBuilder::new(2).build().await.unwrap();
+        let client = cluster.cluster_client().unwrap();
+        let namespaces = ["failover".to_string()];
+        client
+            .put_key_values(
+                &namespaces,
+                &mut [vec![KeyValue {
+                    key: "key".to_string(),
+                    value: b"value".to_vec(),
+                }]],
+            )
+            .await
+            .unwrap();
+
+        // requests to the killed node are retried against the remaining node
+        cluster.kill(0).await.unwrap();
+        let res = client
+            .get_key_values(&namespaces, &mut [vec!["key".to_string()]])
+            .await
+            .unwrap();
+        assert_eq!(res.entries["failover"][0].data, b"value".to_vec());
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_client_typed_values() {
+        use crate::api::client::KVBatch;
+        use crate::types::DbKey;
+        use serde::{Deserialize, Serialize};
+
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct User {
+            name: String,
+            age: u8,
+        }
+        impl DbKey for User {
+            fn key(&self) -> anyhow::Result<Vec<u8>> {
+                Ok(self.name.as_bytes().to_vec())
+            }
+        }
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let cluster = TestClusterBuilder::new(1).build().await.unwrap();
+        let client = cluster.cluster_client().unwrap();
+        let alice = User {
+            name: "alice".to_string(),
+            age: 30,
+        };
+        client.put("users", &alice).await.unwrap();
+        assert_eq!(
+            client.get::<User>("users", "alice").await.unwrap(),
+            Some(alice)
+        );
+        assert_eq!(client.get::<User>("users", "bob").await.unwrap(), None);
+
+        // the last operation on a key within a batch wins
+        let bob = User {
+            name: "bob".to_string(),
+            age: 40,
+        };
+        let mut batch = KVBatch::new();
+        batch.insert("users", &bob).unwrap();
+        batch.remove_raw("users", "alice");
+        batch.insert_raw("other", "key", b"1".to_vec());
+        batch.remove_raw("other", "key");
+        assert_eq!(batch.len(), 3);
+        client.apply_batch(&mut batch).await.unwrap();
+        assert!(batch.is_empty());
+        assert_eq!(client.get::<User>("users", "bob").await.unwrap(), Some(bob));
+        assert_eq!(client.get::<User>("users", "alice").await.unwrap(), None);
+        assert_eq!(client.get::<u8>("other", "key").await.unwrap(), None);
+
+        // mismatched namespaces and entries are rejected rather than truncated
+        assert!(client
+            .get_key_values(&["users".to_string()], &mut [vec![], vec![]])
+            .await
+            .is_err());
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "blocking")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blocking_client() {
+        use crate::api::blocking::KVClient;
+        use crate::prelude::KeyValue;
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let cluster = TestClusterBuilder::new(1).build().await.unwrap();
+        let client = KVClient::from_async(cluster.cluster_client().unwrap()).unwrap();
+        // the blocking client can't be used from within the test's runtime
+        tokio::task::spawn_blocking(move || {
+            client.put_key_values(
+                &["blocking".to_string()],
+                &mut [vec![KeyValue {
+                    key: "key".to_string(),
+                    value: b"value".to_vec(),
+                }]],
+            )?;
+            let res =
+                client.get_key_values(&["blocking".to_string()], &mut [vec!["key".to_string()]])?;
+            assert_eq!(res.entries["blocking"][0].data, b"value".to_vec());
+            client.delete("blocking", "key")?;
+            assert_eq!(client.get::<String>("blocking", "key")?, None);
+            anyhow::Ok(())
+        })
+        .await
+        .unwrap()
+        .unwrap();
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[cfg(feature = "client")]
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_blob_api() {
+        use crate::prelude::{BlobInfo, GcResponse, UploadStatus};
+        use bonerjams_config::BlobConfig;
+        use reqwest::StatusCode;
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let cluster = TestClusterBuilder::new(1)
+            .api_config(API {
+                blobs: BlobConfig {
+                    chunk_size: 4,
+                    gc_interval: 1,
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let url = cluster.node(0).url();
+        let client = reqwest::Client::new();
+
+        // blobs are split into chunks, identical chunks are only stored once
+        let res = client
+            .put(format!("{}/blobs/blobspace/a%2Fblob", url))
+            .header("content-type", "text/plain")
+            .body("abcdabcdefg")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let blob: BlobInfo = res.json().await.unwrap();
+        assert_eq!(blob.size, 11);
+        assert_eq!(blob.chunks.len(), 3);
+        assert_eq!(blob.chunks[0], blob.chunks[1]);
+        let res = client
+            .get(format!("{}/blobs/blobspace/a%2Fblob", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["content-type"], "text/plain");
+        assert_eq!(res.text().await.unwrap(), "abcdabcdefg");
+
+        // uploads are appended to at their offset, and written once completed
+        let upload: UploadStatus = client
+            .post(format!("{}/uploads", url))
+            .json(&serde_json::json!({"namespace": "blobspace", "key": "resumed"}))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let append = |offset: u64, body: &'static str| {
+            client
+                .patch(format!("{}/uploads/{}", url, upload.id))
+                .header("upload-offset", offset)
+                .body(body)
+                .send()
+        };
+        let res = append(0, "hello ").await.unwrap();
+        assert_eq!(res.json::<UploadStatus>().await.unwrap().offset, 6);
+        let res = append(0, "world").await.unwrap();
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        let res = append(6, "world").await.unwrap();
+        assert_eq!(res.json::<UploadStatus>().await.unwrap().offset, 11);
+        let res = client
+            .post(format!("{}/uploads/{}/complete", url, upload.id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let res = client
+            .get(format!("{}/blobs/blobspace/resumed", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "hello world");
+        let res = client
+            .get(format!("{}/uploads/{}", url, upload.id))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        // chunks of deleted blobs are collected once a full epoch, here a second, has
+        // passed since they were written
+        let res = client
+            .delete(format!("{}/blobs/blobspace/a%2Fblob", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        for (deleted, pending) in [(0, 2), (2, 0)] {
+            if deleted > 0 {
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+            let res: GcResponse = client
+                .post(format!("{}/cluster/gc", cluster.node(0).admin_url()))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!((res.chunks_deleted, res.chunks_pending), (deleted, pending));
+        }
+        let res = client
+            .get(format!("{}/blobs/blobspace/resumed", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(res.text().await.unwrap(), "hello world");
+
+        cluster.shutdown().await.unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_grpc_api() {
+        use crate::api::grpc::{proto, KeyValueClient};
+        use bonerjams_config::GrpcConfig;
+        use std::collections::HashMap;
+        use tokio_stream::StreamExt;
+
+        let _ = tracing_subscriber::fmt::try_init();
+        let cluster = TestClusterBuilder::new(1)
+            .api_config(API {
+                grpc: GrpcConfig {
+                    enabled: true,
+                    listen_address: None,
+                },
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        // served on the same address as the http api
+        let mut client = KeyValueClient::connect(cluster.node(0).url())
+            .await
+            .unwrap();
+        let keys = |keys: &[&str]| {
+            HashMap::from([(
+                "grpcspace".to_string(),
+                proto::Keys {
+                    keys: keys.iter().map(|key| key.to_string()).collect(),
+                },
+            )])
+        };
+        // watchers must acknowledge that only changes made through this node are streamed
+        let status = client
+            .watch(proto::WatchRequest {
+                namespace: "grpcspace".to_string(),
+                prefix: None,
+                node_local: false,
+            })
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        let mut watch = client
+            .watch(proto::WatchRequest {
+                namespace: "grpcspace".to_string(),
+                prefix: None,
+                node_local: true,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+
+        client
+            .put(proto::PutRequest {
+                entries: HashMap::from([(
+                    "grpcspace".to_string(),
+                    proto::KeyValues {
+                        entries: vec![proto::KeyValuePair {
+                            key: "key".to_string(),
+                            value: b"value".to_vec(),
+                        }],
+                    },
+                )]),
+            })
+            .await
+            .unwrap();
+        let event = watch.next().await.unwrap().unwrap();
+        assert_eq!(event.key, "key");
+        assert_eq!(event.value, Some(b"value".to_vec()));
+
+        let res = client
+            .get(proto::GetRequest {
+                entries: keys(&["key"]),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(
+            res.entries["grpcspace"].documents[0].data,
+            b"value".to_vec()
+        );
+        let res = client
+            .exists(proto::ExistsRequest {
+                entries: keys(&["key", "missing"]),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(res.entries["grpcspace"].keys["key"]);
+        assert!(!res.entries["grpcspace"].keys["missing"]);
+        let res = client
+            .scan(proto::ScanRequest {
+                namespace: "grpcspace".to_string(),
+                prefix: None,
+                limit: None,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(res.keys, vec!["key".to_string()]);
+
+        client
+            .delete(proto::DeleteRequest {
+                entries: keys(&["key"]),
+            })
+            .await
+            .unwrap();
+        let event = watch.next().await.unwrap().unwrap();
+        assert_eq!(event.key, "key");
+        assert_eq!(event.value, None);
+
+        // grpc requests pass through the same metrics layer as the http api
+        #[cfg(feature = "client")]
+        {
+            let metrics = reqwest::get(format!("{}/metrics", cluster.node(0).url()))
+                .await
+                .unwrap()
+                .text()
+                .await
+                .unwrap();
+            assert!(metrics.contains("bonerjams.kv.KeyValue"));
+        }
+
+        cluster.shutdown().await.unwrap();
+    }
+}
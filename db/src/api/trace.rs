@@ -0,0 +1,247 @@
+//! request ids and w3c trace context. every request is given an id, taken from its
+//! `X-Request-Id` header or otherwise generated, which is recorded by the request's
+//! span and echoed in the response headers and error bodies. the trace id of a
+//! `traceparent` header is recorded by the span too, and with the `otel` feature the
+//! span joins the caller's trace and is exported to an otlp collector
+
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use tracing::Span;
+
+/// the header carrying the id of a request
+pub const REQUEST_ID: &str = "x-request-id";
+
+/// the w3c trace context header
+pub const TRACEPARENT: &str = "traceparent";
+
+/// the maximum length of a request id given by a client, longer ids are replaced
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+tokio::task_local! {
+    /// the id of the request being handled, included in error bodies
+    static CURRENT_REQUEST_ID: String;
+}
+
+/// returns the id of the request being handled by the current task, if any
+pub fn current_request_id() -> Option<String> {
+    CURRENT_REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// returns a new random request id
+pub fn generate_request_id() -> String {
+    format!("{:016x}{:016x}", random(), random())
+}
+
+/// a version 00 w3c `traceparent` header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: u128,
+    /// the id of the caller's span
+    pub parent_id: u64,
+    pub flags: u8,
+}
+
+impl TraceParent {
+    /// starts a new, sampled trace
+    pub fn generate() -> Self {
+        Self {
+            trace_id: ((random() as u128) << 64) | random() as u128,
+            parent_id: random(),
+            flags: 1,
+        }
+    }
+    /// the trace context of the current span when the `otel` feature is enabled and
+    /// the span is part of a trace, otherwise None
+    pub fn current() -> Option<Self> {
+        #[cfg(feature = "otel")]
+        {
+            use opentelemetry::trace::TraceContextExt;
+            use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+            let context = Span::current().context();
+            let span = context.span();
+            let span_context = span.span_context();
+            if span_context.is_valid() {
+                return Some(Self {
+                    trace_id: u128::from_be_bytes(span_context.trace_id().to_bytes()),
+                    parent_id: u64::from_be_bytes(span_context.span_id().to_bytes()),
+                    flags: span_context.trace_flags().to_u8(),
+                });
+            }
+        }
+        None
+    }
+    /// parses a header, returning None if it is invalid. fields appended by later
+    /// versions are ignored as the specification requires
+    pub fn parse(header: &str) -> Option<Self> {
+        let mut fields = header.trim().split('-');
+        let version = fields.next()?;
+        let trace_id = fields.next()?;
+        let parent_id = fields.next()?;
+        let flags = fields.next()?;
+        let is_hex = |field: &str, len: usize| {
+            field.len() == len
+                && field
+                    .bytes()
+                    .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        };
+        if !is_hex(version, 2) || version == "ff" || (version == "00" && fields.next().is_some()) {
+            return None;
+        }
+        if !is_hex(trace_id, 32) || !is_hex(parent_id, 16) || !is_hex(flags, 2) {
+            return None;
+        }
+        let parsed = Self {
+            trace_id: u128::from_str_radix(trace_id, 16).ok()?,
+            parent_id: u64::from_str_radix(parent_id, 16).ok()?,
+            flags: u8::from_str_radix(flags, 16).ok()?,
+        };
+        // all zero ids are invalid
+        if parsed.trace_id == 0 || parsed.parent_id == 0 {
+            return None;
+        }
+        Some(parsed)
+    }
+    /// parses the `traceparent` header of a request
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        Self::parse(headers.get(TRACEPARENT)?.to_str().ok()?)
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.parent_id, self.flags
+        )
+    }
+}
+
+/// middleware assigning each request an id, which is kept if given by the client and
+/// valid. the id is set on the request for `make_span`, and on the response
+pub async fn propagate<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| {
+            !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id.bytes().all(|b| b.is_ascii_graphic())
+        })
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    // the id is visible ascii so is always a valid header value
+    let header = HeaderValue::from_str(&request_id).expect("invalid request id");
+    request.headers_mut().insert(REQUEST_ID, header.clone());
+    let mut response = CURRENT_REQUEST_ID
+        .scope(request_id, next.run(request))
+        .await;
+    response.headers_mut().insert(REQUEST_ID, header);
+    response
+}
+
+/// builds the span of a request for `TraceLayer`, recording its request id and trace id
+pub fn make_span<B>(request: &Request<B>) -> Span {
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|id| id.to_str().ok())
+        .unwrap_or_default();
+    let trace_id = TraceParent::from_headers(request.headers())
+        .map(|parent| format!("{:032x}", parent.trace_id))
+        .unwrap_or_default();
+    let span = tracing::info_span!(
+        "request",
+        method = %request.method(),
+        uri = %request.uri(),
+        version = ?request.version(),
+        request_id,
+        trace_id,
+    );
+    #[cfg(feature = "otel")]
+    otel::set_parent(&span, request.headers());
+    span
+}
+
+fn random() -> u64 {
+    // RandomState is randomly seeded, which is enough randomness for ids
+    RandomState::new().build_hasher().finish()
+}
+
+#[cfg(feature = "otel")]
+pub mod otel {
+    //! exports spans to an opentelemetry collector over otlp
+
+    use axum::http::HeaderMap;
+    use bonerjams_config::TracingConfig;
+    use opentelemetry::propagation::{Extractor, TextMapPropagator};
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::trace::{self, Sampler, Tracer};
+    use opentelemetry::sdk::Resource;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use tracing::Span;
+    use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+    use tracing_subscriber::registry::LookupSpan;
+
+    /// returns a layer exporting spans to the configured collector, or None if no
+    /// collector is configured. spans continue the trace of a request's
+    /// `traceparent` header, and are sampled by `sample_ratio` otherwise
+    pub fn layer<S>(conf: &TracingConfig) -> anyhow::Result<Option<OpenTelemetryLayer<S, Tracer>>>
+    where
+        S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+    {
+        let endpoint = match conf.otlp_endpoint.as_ref() {
+            Some(endpoint) => endpoint,
+            None => return Ok(None),
+        };
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(
+                opentelemetry_otlp::new_exporter()
+                    .tonic()
+                    .with_endpoint(endpoint),
+            )
+            .with_trace_config(
+                trace::config()
+                    .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                        conf.sample_ratio,
+                    ))))
+                    .with_resource(Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        conf.service_name.clone(),
+                    )])),
+            )
+            .install_batch(opentelemetry::runtime::Tokio)?;
+        Ok(Some(tracing_opentelemetry::layer().with_tracer(tracer)))
+    }
+
+    /// flushes spans which have not been exported yet, called before exiting
+    pub fn shutdown() {
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+
+    /// continues the trace of the request's `traceparent` header, if any
+    pub(super) fn set_parent(span: &Span, headers: &HeaderMap) {
+        let context = TraceContextPropagator::new().extract(&HeaderExtractor(headers));
+        span.set_parent(context);
+    }
+
+    struct HeaderExtractor<'a>(&'a HeaderMap);
+
+    impl Extractor for HeaderExtractor<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|value| value.to_str().ok())
+        }
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|key| key.as_str()).collect()
+        }
+    }
+}
@@ -18,7 +18,7 @@ pub struct Empty;
 /// type alias for a vector of key-values
 pub type Values = Vec<KeyValue>;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 #[repr(u8)]
 pub enum Exists {
     Found = 0_u8,
@@ -47,17 +47,18 @@ impl From<&Exists> for bool {
 }
 
 /// request object used for batch deletion of keys into the key value store
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug)]
 pub struct DeleteKVsRequest {
     /// key (namespace) => value (vec![(key, value)])
     ///
     /// allow batch insertion of key-value records, grouping the
     /// records to insert based on the tree they will be inserted them
+    #[serde(deserialize_with = "crate::api::limits::keys_by_namespace")]
     pub entries: HashMap<String, Vec<String>>,
 }
 
 /// request object used for batch insertion of keys into the key value store
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug)]
 pub struct PutKVsRequest {
     /// key (namespace) => value (vec![(key, value)])
     ///
@@ -67,7 +68,7 @@ pub struct PutKVsRequest {
 }
 
 /// request object used for batch insertion of keys into the key value store
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug)]
 pub struct ExistsKVsRequest {
     /// key (namespace) => value (vec![(key, value)])
     ///
@@ -75,31 +76,55 @@ pub struct ExistsKVsRequest {
     /// records to insert based on the tree they will be inserted them
     ///
     /// the hashmap key is a base64 encoded string
+    #[serde(deserialize_with = "crate::api::limits::keys_by_namespace")]
     pub entries: HashMap<String, Vec<String>>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+/// request object used to list the keys stored within a namespace
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct ScanKVsRequest {
+    pub namespace: String,
+    /// if Some, only keys starting with this prefix are returned
+    pub prefix: Option<String>,
+    /// if Some, at most this many keys are returned, being the first in sorted order
+    pub limit: Option<usize>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct ScanKVsResponse {
+    /// the matching keys, sorted lexicographically
+    pub keys: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 pub struct GetKVsRequest {
+    #[serde(deserialize_with = "crate::api::limits::keys_by_namespace")]
     pub entries: HashMap<String, Vec<String>>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 pub struct GetKVsResponse {
     pub entries: HashMap<String, Vec<WrappedDocument>>,
 }
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 pub struct ExistKVsResponse {
     pub entries: HashMap<String, HashMap<String, Exists>>,
 }
 
 /// a wrapper object used when bulk inserting records into the keyvalue store
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 pub struct KeyValue {
+    #[serde(deserialize_with = "crate::api::limits::key")]
     pub key: String,
+    #[serde(
+        serialize_with = "crate::api::codec::bytes::serialize",
+        deserialize_with = "crate::api::limits::value"
+    )]
+    #[schema(value_type = String, format = Byte)]
     pub value: Vec<u8>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 pub struct Status {
     pub msg: String,
 }
@@ -108,13 +133,45 @@ pub struct Status {
 /// the actual stringified key within the document, we
 /// need to wrap the data that the document contains with
 /// the actual key
-#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 pub struct WrappedDocument {
     pub key: String,
+    #[serde(with = "crate::api::codec::bytes")]
+    #[schema(value_type = String, format = Byte)]
     pub data: Vec<u8>,
+    /// unix timestamp in milliseconds after which the document is treated as deleted,
+    /// set when the namespace has a ttl
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// the media type of the data, set when written through the `/kv` routes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
 }
 
+impl WrappedDocument {
+    pub fn is_expired(&self) -> bool {
+        match self.expires_at {
+            Some(expires_at) => crate::api::membership::now_millis() >= expires_at,
+            None => false,
+        }
+    }
+    /// decodes the data as json, as values are encoded by `KVClient::put` and the
+    /// embedded database
+    pub fn value<T: serde::de::DeserializeOwned>(&self) -> anyhow::Result<T> {
+        Ok(serde_json::from_slice(&self.data)?)
+    }
+}
+
+/// a change to a key made through this node, sent to watchers
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct ChangeEvent {
+    pub namespace: String,
+    pub key: String,
+    /// the new value of the key, or None if it was deleted
+    pub value: Option<Vec<u8>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
 pub struct ClusterStatistics {
     /// The number of currently alive members the node is aware of.
     pub num_live_members: u64,
@@ -131,3 +188,250 @@ pub struct ClusterStatistics {
     /// The number of data centers/availability zones the cluster belongs to.
     pub num_data_centers: u64,
 }
+
+/// the result of a single health check
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct HealthCheck {
+    pub name: String,
+    pub healthy: bool,
+    /// if Some, describes why the check failed
+    pub detail: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct HealthStatus {
+    /// true if every check passed
+    pub healthy: bool,
+    pub checks: Vec<HealthCheck>,
+}
+
+/// describes a member of the cluster, as written by its heartbeat
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Default)]
+pub struct MemberInfo {
+    pub node_id: String,
+    /// the address used by other nodes to reach this node
+    pub rpc_address: String,
+    /// the address of the node's http api, if known
+    pub api_address: Option<String>,
+    pub data_center: Option<String>,
+    /// unix timestamp in milliseconds of the member's last heartbeat
+    pub last_seen: u64,
+    /// the interval between the member's heartbeats in milliseconds
+    pub heartbeat_interval: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct ClusterMember {
+    #[serde(flatten)]
+    pub info: MemberInfo,
+    /// true if the member's last heartbeat was recent enough
+    pub live: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct ClusterMembersResponse {
+    pub members: Vec<ClusterMember>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct KeyspaceInfo {
+    pub name: String,
+    /// the number of live documents stored by this node
+    pub documents: u64,
+    /// the number of deleted documents which have not yet been purged
+    pub tombstones: u64,
+    /// a digest of the ids and timestamps of the live documents, which is the same on
+    /// every node holding the same documents
+    #[serde(default)]
+    pub checksum: u64,
+}
+
+/// the query of `/cluster/keyspaces`
+#[derive(serde::Serialize, serde::Deserialize, utoipa::IntoParams, Debug, Clone, Default)]
+#[into_params(parameter_in = Query)]
+pub struct ClusterKeyspacesQuery {
+    /// if true, the keyspaces used internally, such as those holding blobs, are listed too
+    #[serde(default)]
+    pub system: bool,
+}
+
+/// the state of synchronisation between this node and its peers. datacake only tracks
+/// sync tasks across the whole node, so this is shared by all keyspaces
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct SyncState {
+    pub ongoing_tasks: u64,
+    pub slow_tasks: u64,
+    pub failed_tasks: u64,
+    /// true if no sync tasks are running or have failed
+    pub synced: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct RepairRequest {
+    pub namespace: String,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct RepairResponse {
+    pub namespace: String,
+    pub sync: SyncState,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct ClusterKeyspacesResponse {
+    pub keyspaces: Vec<KeyspaceInfo>,
+    pub sync: SyncState,
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Copy, PartialEq, Eq,
+)]
+pub enum DrainPhase {
+    /// the node is accepting writes
+    Serving,
+    /// writes are rejected while peers catch up on the node's documents
+    Draining,
+    /// the peers have caught up, and the node can leave the cluster
+    Drained,
+    /// the drain failed and writes are accepted again, the drain may be retried
+    Failed,
+}
+
+impl Default for DrainPhase {
+    fn default() -> Self {
+        Self::Serving
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Default)]
+pub struct DrainStatus {
+    pub phase: DrainPhase,
+    /// the progress of handing off each keyspace of the node, as of the last check
+    pub keyspaces: Vec<KeyspaceProgress>,
+    pub error: Option<String>,
+}
+
+/// which of the live peers hold the same documents as the draining node in a keyspace
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Default)]
+pub struct KeyspaceProgress {
+    pub name: String,
+    /// the ids of the peers which have caught up on the keyspace
+    pub synced: Vec<String>,
+    /// the ids of the peers which have yet to catch up, or could not be reached
+    pub pending: Vec<String>,
+}
+
+/// how values within a namespace must be encoded
+#[derive(
+    serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Copy, PartialEq, Eq,
+)]
+pub enum ValueCodec {
+    /// values may contain any bytes
+    Binary,
+    /// values must be valid utf8
+    Utf8,
+    /// values must be valid json
+    Json,
+}
+
+/// settings applied to every document within a namespace
+#[derive(
+    serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Default, PartialEq, Eq,
+)]
+pub struct NamespaceSettings {
+    /// overrides the node's consistency level for writes and deletes
+    pub consistency: Option<bonerjams_config::cluster::ConsistencyLevel>,
+    /// the number of seconds after which documents expire
+    pub ttl: Option<u64>,
+    /// the maximum size of a value in bytes
+    pub max_value_size: Option<usize>,
+    pub codec: Option<ValueCodec>,
+    /// the maximum number of keys within the namespace
+    pub max_keys: Option<u64>,
+    /// the maximum total size of the values within the namespace in bytes
+    pub max_bytes: Option<u64>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct ListNamespacesResponse {
+    pub namespaces: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct NamespaceInfo {
+    pub name: String,
+    /// the number of live keys stored by this node
+    pub keys: u64,
+    /// the total size of the values stored by this node
+    pub bytes: u64,
+    pub settings: NamespaceSettings,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct DropNamespaceResponse {
+    pub name: String,
+    /// the number of keys deleted
+    pub deleted: u64,
+}
+
+/// a chunk of a blob, stored under the digest of its contents
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, PartialEq, Eq)]
+pub struct BlobChunk {
+    /// the hex encoded sha256 digest of the chunk
+    pub digest: String,
+    pub size: u64,
+    /// the garbage collection epoch the chunk was written in, which is part of its key
+    pub epoch: u64,
+}
+
+/// the manifest of a blob, listing its chunks in order
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct BlobInfo {
+    pub namespace: String,
+    pub key: String,
+    /// the size of the blob in bytes
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub chunks: Vec<BlobChunk>,
+    /// unix timestamp in milliseconds of when the blob was written
+    pub created_at: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct CreateUploadRequest {
+    pub namespace: String,
+    pub key: String,
+    /// the content type the blob is served with
+    pub content_type: Option<String>,
+}
+
+/// the progress of a resumable upload
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct UploadStatus {
+    pub id: String,
+    pub namespace: String,
+    pub key: String,
+    pub content_type: Option<String>,
+    /// the number of bytes stored so far, which the next append must begin at
+    pub offset: u64,
+    pub chunks: Vec<BlobChunk>,
+    /// unix timestamp in milliseconds of when the upload was last appended to
+    pub updated_at: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone, Default)]
+pub struct GcResponse {
+    /// the number of orphaned chunks deleted
+    pub chunks_deleted: u64,
+    /// the number of unreferenced chunks which were not deleted, as they were written
+    /// too recently or have not been found orphaned by every member
+    pub chunks_pending: u64,
+    /// the number of idle uploads removed
+    pub uploads_expired: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, utoipa::ToSchema, Debug, Clone)]
+pub struct LogFilter {
+    /// `EnvFilter` directives, such as `info,datacake=warn`
+    pub filter: String,
+}
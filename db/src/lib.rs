@@ -70,6 +70,14 @@ impl Database {
     pub fn flush(self: &Arc<Self>) -> Result<usize> {
         Ok(self.db.flush()?)
     }
+    /// returns the names of all trees within the database
+    pub fn tree_names(self: &Arc<Self>) -> Vec<IVec> {
+        self.db.tree_names()
+    }
+    /// returns the size of the database on disk in bytes
+    pub fn size_on_disk(self: &Arc<Self>) -> Result<u64> {
+        Ok(self.db.size_on_disk()?)
+    }
     /// returns a clone of the inner database
     pub fn inner(self: &Arc<Self>) -> sled::Db {
         self.db.clone()
@@ -136,6 +144,14 @@ impl DbTree {
     pub fn iter(&self) -> sled::Iter {
         self.tree.iter()
     }
+    /// returns an iterator over the given range of keys
+    pub fn range<K: AsRef<[u8]>, R: std::ops::RangeBounds<K>>(&self, range: R) -> sled::Iter {
+        self.tree.range(range)
+    }
+    /// returns an iterator over all keys starting with the given prefix
+    pub fn scan_prefix<P: AsRef<[u8]>>(&self, prefix: P) -> sled::Iter {
+        self.tree.scan_prefix(prefix)
+    }
     pub fn contains_key<K: AsRef<[u8]>>(&self, key: K) -> sled::Result<bool> {
         self.tree.contains_key(key)
     }